@@ -13,8 +13,7 @@ pub fn enum_from_impl(input: TokenStream) -> TokenStream {
             enum_name,
             "EnumFrom can only be used on enums",
         )
-        .to_compile_error()
-        .into();
+        .to_compile_error();
     };
 
     let mut impls = Vec::new();
@@ -53,5 +52,4 @@ pub fn enum_from_impl(input: TokenStream) -> TokenStream {
         #(#diagnostics)*
         #(#impls)*
     }
-    .into()
 }
@@ -11,8 +11,7 @@ pub fn enum_kind_impl(input: TokenStream) -> TokenStream {
             name,
             "EnumKind can only be derived for enums"
         )
-        .to_compile_error()
-        .into();
+        .to_compile_error();
     };
 
     let mut arms = Vec::new();
@@ -38,8 +37,7 @@ pub fn enum_kind_impl(input: TokenStream) -> TokenStream {
                             }
                             _ => {
                                 return syn::Error::new_spanned(&nv.value, "Expected integer literal for #[kind = N]")
-                                    .to_compile_error()
-                                    .into();
+                                    .to_compile_error();
                             }
                         }
                     }
@@ -48,8 +46,7 @@ pub fn enum_kind_impl(input: TokenStream) -> TokenStream {
                             attr,
                             "Expected #[kind = N]"
                         )
-                        .to_compile_error()
-                        .into();
+                        .to_compile_error();
                     }
                 }
             }
@@ -88,7 +85,7 @@ pub fn enum_kind_impl(input: TokenStream) -> TokenStream {
         }
     };
 
-    expanded.into()
+    expanded
 }
 
 #[cfg(test)]
@@ -64,7 +64,7 @@ pub fn derive_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
 
     let protocol = match syn::parse2::<Protocol>(attr) {
         Ok(a) => a,
-        Err(e) => return e.to_compile_error().into(),
+        Err(e) => return e.to_compile_error(),
     };
 
     let mut match_arms = vec![];
@@ -75,11 +75,17 @@ pub fn derive_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
 
         let handler_ident = format_ident!("handle_{}", ty.to_string().to_snake_case());
 
+        // Handlers default to rejecting the packet, so that a type only needs to
+        // implement the handlers for the packets its peer is expected to send.
         let handler: TraitItemFn = {
             let quoted = quote! {
                 fn #handler_ident<D>(&mut self, msg: #ty, dest: &mut D)
                     -> impl ::core::future::Future<Output = anyhow::Result<()>> + Send
-                        where D: crate::packets::WriteExt;
+                        where D: crate::packets::WriteExt
+                {
+                    let _ = (msg, dest);
+                    async { Err(::anyhow::anyhow!("Unexpected {}", stringify!(#ty))) }
+                }
             };
 
             parse2(quoted).expect("Failed to parse method handler.")
@@ -95,6 +101,20 @@ pub fn derive_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
         })
     }
 
+    // Protocols that send packets on their own, such as the chunks of a file transfer,
+    // override this to send the next one.
+    let send_queued: TraitItemFn = parse2(quote! {
+        fn send_queued<D>(&mut self, dest: &mut D)
+            -> impl ::core::future::Future<Output = anyhow::Result<bool>> + Send
+                where D: crate::packets::WriteExt
+        {
+            let _ = dest;
+            async { Ok(false) }
+        }
+    }).expect("Failed to parse queued packet handler.");
+
+    input.items.push(TraitItem::Fn(send_queued));
+
     let trait_ident = &input.ident;
     let identifier_ty = &protocol.identifier;
 
@@ -102,7 +122,7 @@ pub fn derive_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
         #input
 
         // Emit the blanket implementation
-        impl<T> Protocol for T where T: #trait_ident {
+        impl<T> Protocol<#identifier_ty> for T where T: #trait_ident {
             fn process_incoming<Source, Dest>(&mut self, source: &mut Source, dest: &mut Dest)
                 -> impl ::core::future::Future<Output = anyhow::Result<()>> + Send
                     where Source: crate::packets::ReadExt, Dest: crate::packets::WriteExt
@@ -116,8 +136,15 @@ pub fn derive_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
                     }
                 }
             }
+
+            fn send_queued<Dest>(&mut self, dest: &mut Dest)
+                -> impl ::core::future::Future<Output = anyhow::Result<bool>> + Send
+                    where Dest: crate::packets::WriteExt
+            {
+                <T as #trait_ident>::send_queued(self, dest)
+            }
         }
-    }.into()
+    }
 }
 
 #[cfg(test)]
@@ -145,14 +172,30 @@ mod tests {
             pub trait GruntProtocol {
                 fn handle_logon_challenge_request<D>(&mut self, msg: LogonChallengeRequest, dest: &mut D)
                     -> impl ::core::future::Future<Output = anyhow::Result<()>> + Send
-                        where D: crate::packets::WriteExt;
+                        where D: crate::packets::WriteExt
+                {
+                    let _ = (msg, dest);
+                    async { Err(::anyhow::anyhow!("Unexpected {}", stringify!(LogonChallengeRequest))) }
+                }
 
                 fn handle_logon_proof_request<D>(&mut self, msg: LogonProofRequest, dest: &mut D)
                     -> impl ::core::future::Future<Output = anyhow::Result<()>> + Send
-                        where D: crate::packets::WriteExt;
+                        where D: crate::packets::WriteExt
+                {
+                    let _ = (msg, dest);
+                    async { Err(::anyhow::anyhow!("Unexpected {}", stringify!(LogonProofRequest))) }
+                }
+
+                fn send_queued<D>(&mut self, dest: &mut D)
+                    -> impl ::core::future::Future<Output = anyhow::Result<bool>> + Send
+                        where D: crate::packets::WriteExt
+                {
+                    let _ = dest;
+                    async { Ok(false) }
+                }
             }
 
-            impl<T> Protocol for T where T: GruntProtocol {
+            impl<T> Protocol<GruntIdentifier> for T where T: GruntProtocol {
                 fn process_incoming<Source, Dest>(&mut self, source: &mut Source, dest: &mut Dest)
                     -> impl ::core::future::Future<Output = anyhow::Result<()>> + Send
                         where Source: crate::packets::ReadExt, Dest: crate::packets::WriteExt,
@@ -172,6 +215,13 @@ mod tests {
                         }
                     }
                 }
+
+                fn send_queued<Dest>(&mut self, dest: &mut Dest)
+                    -> impl ::core::future::Future<Output = anyhow::Result<bool>> + Send
+                        where Dest: crate::packets::WriteExt
+                {
+                    <T as GruntProtocol>::send_queued(self, dest)
+                }
             }
         });
     }
@@ -1,23 +1,59 @@
 #![allow(dead_code)]
 
+pub mod accounts;
+pub mod authenticator;
+pub mod bans;
+pub mod builds;
+pub mod local_auth;
+pub mod lockout;
+pub mod login;
+pub mod matrix;
+pub mod patch;
+pub mod pin;
 pub mod protocol;
+pub mod relay;
+pub mod rewrite;
+pub mod session;
+pub mod srp;
+pub mod state;
+pub mod translate;
 
 #[cfg(test)]
 mod test {
-    use std::net::IpAddr;
+    use std::collections::HashMap;
+    use std::marker::PhantomData;
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::Arc;
     use tokio::sync::mpsc::{self, Receiver, Sender};
     use tokio_util::sync::CancellationToken;
     use anyhow::Result;
     use tracing::info;
-    use crate::grunt::protocol::{GruntProtocol, LogonProofRequest};
-    use crate::grunt::protocol::{LogonChallengeRequest, Version};
+    use crate::grunt::protocol::{GruntProtocol, GruntVersion, LoginResult, LogonProofRequest, LogonProofResponse, Role};
+    use crate::grunt::protocol::LogonChallengeResponse;
+    use crate::grunt::protocol::{Realm, RealmFlags, RealmlistRequest, RealmlistResponse};
+    use crate::grunt::protocol::{ReconnectChallengeRequest, ReconnectChallengeResponse};
+    use crate::grunt::protocol::{ReconnectProofRequest, ReconnectProofResponse};
+    use crate::grunt::protocol::{XferAccept, XferCancel, XferData, XferInitiate, XferResume};
+    use crate::grunt::authenticator::{AuthenticatorSecrets, Totp};
+    use crate::grunt::bans::{AccountBan, AddressBan, Ban, BanList, Bans};
+    use crate::grunt::lockout::LoginThrottle;
+    use crate::grunt::login::{LoginError, LoginProtocol};
+    use crate::grunt::accounts::{Account, AccountStore, MemoryAccountStore};
+    use crate::grunt::local_auth::LocalAuthServer;
+    use crate::grunt::relay::{PatchPolicy, RelayServer};
+    use crate::grunt::patch::{Patch, PatchDirectory, PatchTransfer};
+    use crate::grunt::srp::{Key, SrpServer, generate_verifier};
+    use crate::grunt::session::{ReconnectChallenge, SessionKeys, answer_reconnect_challenge};
+    use crate::grunt::state::{AuthState, UnexpectedCommand};
+    use crate::grunt::rewrite::RealmRewriter;
+    use crate::grunt::protocol::{Game, Locale, LogonChallengeRequest, Os, Platform};
     use crate::network::{Acceptor, LocalPeer, Service};
     use crate::network::connection::Client;
     use crate::network::server::Server;
-    use crate::packets::WriteExt;
+    use crate::packets::{Protocol, WriteExt};
 
     const PACKET_COUNT: usize = 1000;
-    const SERVER_ADDRESS: &'static str = "127.0.0.1:8080";
+    const SERVER_ADDRESS: &str = "127.0.0.1:8080";
 
     /// This type is a very simple server for which [`Server`] will be implemented.
     /// It holds:
@@ -28,6 +64,7 @@ mod test {
     struct TestServer {
         sender: Sender<u32>,
         token: CancellationToken,
+        throttle: LoginThrottle,
     }
 
     impl Server for TestServer {
@@ -39,22 +76,24 @@ mod test {
             &self.token
         }
 
-        fn make_protocol(&self) -> Self::Protocol {
+        fn make_protocol(&self, _: SocketAddr) -> Self::Protocol {
             ServerProtocol {
-                version: 8,
+                version: GruntVersion::V8,
+                state: AuthState::default(),
                 signal: self.sender.clone(),
             }
         }
+
+        fn throttle(&self) -> &LoginThrottle {
+            &self.throttle
+        }
     }
 
     /// A test utility method that simply waits for signals that packets were received.
     async fn all_requests_handled(mut receiver: Receiver<u32>) {
         info!("Awaiting packets...");
         for _ in 0..PACKET_COUNT {
-            match receiver.recv().await {
-                Some(_) => (),
-                None => ()
-            };
+            let _ = receiver.recv().await;
         }
         info!("Done ({} packets)", PACKET_COUNT);
     }
@@ -70,6 +109,7 @@ mod test {
             let server = TestServer {
                 sender: tx,
                 token: controller.child_token(),
+                throttle: LoginThrottle::default(),
             };
 
             // This test doesn't use run() because we need the listener to
@@ -90,7 +130,7 @@ mod test {
         // for this test.
         let mut client = Client::connect(
             SERVER_ADDRESS,
-            ClientProtocol { version: 8 },
+            ClientProtocol { version: GruntVersion::V8 },
             CancellationToken::new()
         ).await.expect("Unable to connect to local server");
         assert!(client.ip().is_ipv4());
@@ -103,11 +143,11 @@ mod test {
             for _ in 0..PACKET_COUNT {
                 match client.ip() {
                     IpAddr::V4(addr) => client.send(LogonChallengeRequest {
-                        game: 0x00576F57, // WoW\0
-                        version: Version::parse("4.3.4.15595"),
-                        platform: 0x00783836, // x86\0
-                        os: 0x4F5358, // OSX\0
-                        locale: 0x656E5553, // enUS
+                        game: Game::WorldOfWarcraft,
+                        version: "4.3.4.15595".parse().unwrap(),
+                        platform: Platform::X86,
+                        os: Os::Mac,
+                        locale: Locale::EnUs,
                         timezone: 0x3C,
                         address: addr,
                         account_name: "pow".to_string()
@@ -134,57 +174,66 @@ mod test {
     /// [`TestingProtocol`] but it lacks the signal state and will panic if it suddenly
     /// starts behaving as a [`TestServer`].
     struct ClientProtocol {
-        pub version: u8
+        pub version: GruntVersion,
     }
 
     impl GruntProtocol for ClientProtocol {
-        fn version(&self) -> u8 { self.version }
-        fn set_version(&mut self, version: u8) {
+        fn version(&self) -> GruntVersion { self.version }
+        fn set_version(&mut self, version: GruntVersion) {
             self.version = version;
         }
 
+        fn role(&self) -> Role { Role::Client }
+
         async fn handle_logon_challenge_request<D>(&mut self, _: LogonChallengeRequest, _: &mut D)
             -> Result<()>
                 where D: WriteExt
         {
-            Ok(assert!(false, "Should never be called"))
+            unreachable!("Should never be called")
         }
         
         async fn handle_logon_proof_request<D>(&mut self, _: LogonProofRequest, _: &mut D)
             -> Result<()>
                 where D:crate::packets::WriteExt
         {
-            Ok(assert!(false, "Should never be called"))
+            unreachable!("Should never be called")
         }
     }
 
     /// This is the protocol that is associated with the [`TestingServer`].
     struct ServerProtocol {
-        pub version: u8,
+        pub version: GruntVersion,
+        pub state: AuthState,
         pub signal: Sender<u32>
     }
 
     impl GruntProtocol for ServerProtocol {
-        fn version(&self) -> u8 {
+        fn version(&self) -> GruntVersion {
             self.version
         }
 
-        fn set_version(&mut self, version: u8) {
+        fn set_version(&mut self, version: GruntVersion) {
             self.version = version;
         }
 
+        fn role(&self) -> Role { Role::Server }
+        fn auth_state(&mut self) -> Option<&mut AuthState> { Some(&mut self.state) }
+
         fn handle_logon_challenge_request<D>(&mut self, msg: LogonChallengeRequest, _: &mut D)
             -> impl Future<Output = Result<()>>  where D: WriteExt
         {
             async move {
-                assert_eq!(msg.game, 0x00576F57);
+                // This server only counts challenges, so every one of them starts over.
+                self.state = AuthState::default();
+
+                assert_eq!(msg.game, Game::WorldOfWarcraft);
                 assert_eq!(msg.version.major, 4, "Invalid version");
                 assert_eq!(msg.version.minor, 3, "Invalid version");
                 assert_eq!(msg.version.patch, 4, "Invalid version");
                 assert_eq!(msg.version.build, 15595, "Invalid version");
-                assert_eq!(msg.platform, 0x00783836);
-                assert_eq!(msg.os, 0x4F5358);
-                assert_eq!(msg.locale, 0x656E5553);
+                assert_eq!(msg.platform, Platform::X86);
+                assert_eq!(msg.os, Os::Mac);
+                assert_eq!(msg.locale, Locale::EnUs);
                 assert_eq!(msg.account_name, "pow");
 
                 self.signal.send(1)
@@ -200,7 +249,927 @@ mod test {
             -> Result<()>
                 where D:crate::packets::WriteExt
         {
-            Ok(assert!(false, "Should never be called"))
+            unreachable!("Should never be called")
+        }
+    }
+
+    /// A protocol that records the responses it receives.
+    struct RecordingProtocol {
+        version: GruntVersion,
+        role: Role,
+        state: AuthState,
+        proofs: Vec<LogonProofResponse>,
+        realmlists: Vec<RealmlistResponse>,
+        realmlist_requests: usize,
+        reconnect_challenges: Vec<ReconnectChallengeResponse>,
+        reconnect_proofs: Vec<ReconnectProofResponse>,
+        transfers: Vec<XferInitiate>,
+        data: Vec<u8>,
+    }
+
+    impl RecordingProtocol {
+        fn new(version: GruntVersion, role: Role) -> Self {
+            Self {
+                version,
+                role,
+                state: AuthState::default(),
+                proofs: vec![],
+                realmlists: vec![],
+                realmlist_requests: 0,
+                reconnect_challenges: vec![],
+                reconnect_proofs: vec![],
+                transfers: vec![],
+                data: vec![],
+            }
+        }
+    }
+
+    impl GruntProtocol for RecordingProtocol {
+        fn version(&self) -> GruntVersion { self.version }
+        fn set_version(&mut self, version: GruntVersion) { self.version = version; }
+        fn role(&self) -> Role { self.role }
+        fn auth_state(&mut self) -> Option<&mut AuthState> { Some(&mut self.state) }
+
+        async fn handle_logon_proof_response<D>(&mut self, msg: LogonProofResponse, _: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            self.proofs.push(msg);
+            Ok(())
+        }
+
+        async fn handle_realmlist_request<D>(&mut self, _: RealmlistRequest, _: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            self.realmlist_requests += 1;
+            Ok(())
+        }
+
+        async fn handle_realmlist_response<D>(&mut self, msg: RealmlistResponse, _: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            self.realmlists.push(msg);
+            Ok(())
+        }
+
+        async fn handle_reconnect_challenge_response<D>(&mut self, msg: ReconnectChallengeResponse, _: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            self.reconnect_challenges.push(msg);
+            Ok(())
+        }
+
+        async fn handle_reconnect_proof_response<D>(&mut self, msg: ReconnectProofResponse, _: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            self.reconnect_proofs.push(msg);
+            Ok(())
+        }
+
+        async fn handle_xfer_initiate<D>(&mut self, msg: XferInitiate, _: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            self.transfers.push(msg);
+            Ok(())
+        }
+
+        async fn handle_xfer_data<D>(&mut self, msg: XferData, _: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            self.data.extend_from_slice(&msg.0);
+            Ok(())
+        }
+    }
+
+    /// A server that offers a patch to every client that asks for the realm list.
+    struct PatchProtocol {
+        state: AuthState,
+        patch: Patch,
+        pending: Option<Patch>,
+        transfer: Option<PatchTransfer>,
+    }
+
+    impl GruntProtocol for PatchProtocol {
+        fn version(&self) -> GruntVersion { GruntVersion::V3 }
+        fn set_version(&mut self, _: GruntVersion) { }
+        fn role(&self) -> Role { Role::Server }
+        fn auth_state(&mut self) -> Option<&mut AuthState> { Some(&mut self.state) }
+
+        async fn handle_realmlist_request<D>(&mut self, _: RealmlistRequest, dest: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            self.pending = Some(self.patch.clone());
+            self.send(dest, self.patch.initiate()).await
+        }
+
+        async fn handle_xfer_accept<D>(&mut self, _: XferAccept, _: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            let patch = self.pending.take().expect("No patch was offered");
+            self.transfer = Some(patch.transfer(0).await?);
+            Ok(())
+        }
+
+        async fn handle_xfer_resume<D>(&mut self, msg: XferResume, _: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            let patch = self.pending.take().expect("No patch was offered");
+            self.transfer = Some(patch.transfer(msg.offset).await?);
+            Ok(())
+        }
+
+        async fn handle_xfer_cancel<D>(&mut self, _: XferCancel, _: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            self.pending = None;
+            self.transfer = None;
+            Ok(())
+        }
+
+        async fn send_queued<D>(&mut self, dest: &mut D)
+            -> Result<bool>
+                where D: WriteExt
+        {
+            let Some(mut transfer) = self.transfer.take() else {
+                return Ok(false);
+            };
+
+            let sent = transfer.send_next(self, dest).await?;
+            if sent {
+                self.transfer = Some(transfer);
+            }
+
+            Ok(sent)
+        }
+    }
+
+    /// A server that only accepts reconnection attempts.
+    struct ReconnectProtocol {
+        state: AuthState,
+        sessions: SessionKeys,
+        challenge: Option<ReconnectChallenge>,
+    }
+
+    impl GruntProtocol for ReconnectProtocol {
+        fn version(&self) -> GruntVersion { GruntVersion::V8 }
+        fn set_version(&mut self, _: GruntVersion) { }
+        fn role(&self) -> Role { Role::Server }
+        fn auth_state(&mut self) -> Option<&mut AuthState> { Some(&mut self.state) }
+
+        async fn handle_reconnect_challenge_request<D>(&mut self, msg: ReconnectChallengeRequest, dest: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            let account = msg.0.account_name;
+            match self.sessions.get(&account) {
+                Some(key) => {
+                    let challenge = ReconnectChallenge::new(&account, key);
+                    let response = challenge.response();
+                    self.challenge = Some(challenge);
+                    self.send(dest, response).await
+                },
+                None => self.send(dest, ReconnectChallengeResponse::Err(LoginResult::UnknownAccount)).await,
+            }
+        }
+
+        async fn handle_reconnect_proof_request<D>(&mut self, msg: ReconnectProofRequest, dest: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            let result = match self.challenge.take() {
+                Some(challenge) if challenge.verify(&msg) => {
+                    self.state.authenticate();
+                    LoginResult::Success
+                },
+                _ => {
+                    self.state.reject();
+                    LoginResult::IncorrectPassword
+                },
+            };
+
+            self.send(dest, ReconnectProofResponse(result)).await
         }
     }
-}
\ No newline at end of file
+
+    /// A server that only accepts reconnection attempts, for the sessions it knows about.
+    struct ReconnectServer {
+        token: CancellationToken,
+        sessions: SessionKeys,
+        throttle: LoginThrottle,
+    }
+
+    impl Server for ReconnectServer {
+        type Protocol = ReconnectProtocol;
+
+        fn addr(&self) -> String { "127.0.0.1:0".to_string() }
+
+        fn token(&self) -> &CancellationToken {
+            &self.token
+        }
+
+        fn make_protocol(&self, _: SocketAddr) -> Self::Protocol {
+            ReconnectProtocol { state: AuthState::default(), sessions: self.sessions.clone(), challenge: None }
+        }
+
+        fn throttle(&self) -> &LoginThrottle {
+            &self.throttle
+        }
+    }
+
+    /// A server that authenticates a single account.
+    struct AuthServer {
+        token: CancellationToken,
+        account: (String, Key, Key),
+        throttle: LoginThrottle,
+    }
+
+    impl Server for AuthServer {
+        type Protocol = AuthProtocol;
+
+        fn addr(&self) -> String { "127.0.0.1:0".to_string() }
+
+        fn token(&self) -> &CancellationToken {
+            &self.token
+        }
+
+        fn make_protocol(&self, _: SocketAddr) -> Self::Protocol {
+            AuthProtocol {
+                version: GruntVersion::V8,
+                account: self.account.clone(),
+                srp: None,
+                state: AuthState::default(),
+            }
+        }
+
+        fn throttle(&self) -> &LoginThrottle {
+            &self.throttle
+        }
+    }
+
+    struct AuthProtocol {
+        version: GruntVersion,
+        account: (String, Key, Key),
+        srp: Option<SrpServer>,
+        state: AuthState,
+    }
+
+    impl GruntProtocol for AuthProtocol {
+        fn version(&self) -> GruntVersion { self.version }
+        fn set_version(&mut self, version: GruntVersion) { self.version = version; }
+        fn role(&self) -> Role { Role::Server }
+        fn auth_state(&mut self) -> Option<&mut AuthState> { Some(&mut self.state) }
+
+        async fn handle_logon_challenge_request<D>(&mut self, msg: LogonChallengeRequest, dest: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            let (account, salt, verifier) = &self.account;
+            if !account.eq_ignore_ascii_case(&msg.account_name) {
+                return self.send(dest, LogonChallengeResponse::Err(LoginResult::UnknownAccount)).await;
+            }
+
+            let srp = SrpServer::new(&msg.account_name, *salt, *verifier);
+            let response = srp.challenge(vec![]);
+            self.srp = Some(srp);
+            self.send(dest, response).await
+        }
+
+        async fn handle_logon_proof_request<D>(&mut self, msg: LogonProofRequest, dest: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            let response = match self.srp.take().and_then(|srp| srp.verify(&msg)) {
+                Some(session) => {
+                    self.state.authenticate();
+                    LogonProofResponse::Ok {
+                        proof: session.proof,
+                        account_flags: 0,
+                        hardware_survey_id: 0,
+                        unknown_flags: 0,
+                    }
+                },
+                None => {
+                    self.state.reject();
+                    LogonProofResponse::Err(LoginResult::IncorrectPassword)
+                },
+            };
+
+            self.send(dest, response).await
+        }
+
+        async fn handle_realmlist_request<D>(&mut self, _: RealmlistRequest, dest: &mut D)
+            -> Result<()>
+                where D: WriteExt
+        {
+            self.send(dest, RealmlistResponse {
+                realms: vec![Realm {
+                    realm_type: 0,
+                    locked: false,
+                    flags: RealmFlags::NONE,
+                    name: "Pow".to_string(),
+                    address: "127.0.0.1:8085".to_string(),
+                    population: 0.0,
+                    characters: 0,
+                    category: 1,
+                    id: 1,
+                    build: None,
+                }]
+            }).await
+        }
+    }
+
+    pub(crate) fn challenge_request(account: &str) -> LogonChallengeRequest {
+        LogonChallengeRequest {
+            game: Game::WorldOfWarcraft,
+            version: "3.3.5.12340".parse().unwrap(),
+            platform: Platform::X86,
+            os: Os::Windows,
+            locale: Locale::EnUs,
+            timezone: 0x3C,
+            address: "127.0.0.1".parse().unwrap(),
+            account_name: account.to_string()
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_logon_proof_response_round_trip() {
+        for version in GruntVersion::ALL {
+            let (mut client_end, mut server_end) = tokio::io::duplex(1024);
+            let mut server = RecordingProtocol::new(version, Role::Server);
+            let mut client = RecordingProtocol::new(version, Role::Client);
+
+            server.send(&mut server_end, LogonProofResponse::Ok {
+                proof: [0xAB; 20],
+                account_flags: 0x01,
+                hardware_survey_id: 0x1234,
+                unknown_flags: 0x02,
+            }).await.expect("Packet couldn't be sent");
+            server.send(&mut server_end, LogonProofResponse::Err(LoginResult::IncorrectPassword))
+                .await
+                .expect("Packet couldn't be sent");
+            server.send(&mut server_end, LogonProofResponse::Err(LoginResult::Banned))
+                .await
+                .expect("Packet couldn't be sent");
+
+            for _ in 0..3 {
+                client.process_incoming(&mut client_end, &mut tokio::io::sink())
+                    .await
+                    .expect("Packet couldn't be parsed");
+            }
+
+            assert_eq!(client.proofs, vec![
+                LogonProofResponse::Ok {
+                    proof: [0xAB; 20],
+                    account_flags: if version == GruntVersion::V8 { 0x01 } else { 0 },
+                    hardware_survey_id: 0x1234,
+                    unknown_flags: if version >= GruntVersion::V5 { 0x02 } else { 0 },
+                },
+                LogonProofResponse::Err(LoginResult::IncorrectPassword),
+                LogonProofResponse::Err(LoginResult::Banned),
+            ], "Round trip failed for version {}", version);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_unknown_version_rejected() {
+        let (mut client_end, mut server_end) = tokio::io::duplex(1024);
+        let mut server = RecordingProtocol::new(GruntVersion::V8, Role::Server);
+
+        // A logon challenge announcing protocol version 4.
+        client_end.write_slice(&[0x00, 0x04, 0x00]).await.expect("Packet couldn't be sent");
+
+        let err = server.process_incoming(&mut server_end, &mut tokio::io::sink())
+            .await
+            .expect_err("Version 4 should be rejected");
+        assert_eq!(err.to_string(), "Unsupported Grunt protocol version 4");
+        assert_eq!(server.version(), GruntVersion::V8);
+    }
+
+    #[tokio::test]
+    pub async fn test_realmlist_round_trip() {
+        for version in GruntVersion::ALL {
+            let (mut client_end, mut server_end) = tokio::io::duplex(1024);
+            let mut server = RecordingProtocol::new(version, Role::Server);
+            let mut client = RecordingProtocol::new(version, Role::Client);
+            server.state = AuthState::Authenticated;
+
+            client.send(&mut client_end, RealmlistRequest).await.expect("Packet couldn't be sent");
+            server.process_incoming(&mut server_end, &mut tokio::io::sink())
+                .await
+                .expect("Packet couldn't be parsed");
+            assert_eq!(server.realmlist_requests, 1);
+
+            server.send(&mut server_end, RealmlistResponse {
+                realms: vec![
+                    Realm {
+                        realm_type: 1,
+                        locked: true,
+                        flags: RealmFlags::RECOMMENDED,
+                        name: "Pow".to_string(),
+                        address: "127.0.0.1:8085".to_string(),
+                        population: 1.5,
+                        characters: 3,
+                        category: 2,
+                        id: 7,
+                        build: Some("3.3.5.12340".parse().unwrap()),
+                    },
+                    Realm {
+                        realm_type: 6,
+                        locked: false,
+                        flags: RealmFlags::OFFLINE,
+                        name: "Other".to_string(),
+                        address: "localhost:8086".to_string(),
+                        population: 0.0,
+                        characters: 0,
+                        category: 1,
+                        id: 8,
+                        build: None,
+                    },
+                ]
+            }).await.expect("Packet couldn't be sent");
+            client.send(&mut client_end, RealmlistRequest).await.expect("Packet couldn't be sent");
+
+            client.process_incoming(&mut client_end, &mut tokio::io::sink())
+                .await
+                .expect("Packet couldn't be parsed");
+            // Make sure the response was entirely consumed.
+            server.process_incoming(&mut server_end, &mut tokio::io::sink())
+                .await
+                .expect("Packet couldn't be parsed");
+            assert_eq!(server.realmlist_requests, 2);
+
+            let realms = &client.realmlists[0].realms;
+            assert_eq!(realms.len(), 2);
+            assert_eq!(realms[0].realm_type, 1);
+            assert_eq!(realms[0].locked, version >= GruntVersion::V5);
+            assert_eq!(realms[0].name, "Pow");
+            assert_eq!(realms[0].address, "127.0.0.1:8085");
+            assert_eq!(realms[0].population, 1.5);
+            assert_eq!((realms[0].characters, realms[0].category, realms[0].id), (3, 2, 7));
+            if version >= GruntVersion::V5 {
+                assert_eq!(realms[0].flags, RealmFlags::RECOMMENDED.with(RealmFlags::SPECIFY_BUILD));
+                assert_eq!(realms[0].build.map(|v| v.to_string()), Some("3.3.5.12340".to_string()));
+            } else {
+                assert_eq!(realms[0].flags, RealmFlags::RECOMMENDED);
+                assert!(realms[0].build.is_none());
+            }
+            assert_eq!(realms[1].name, "Other");
+            assert_eq!(realms[1].flags, RealmFlags::OFFLINE);
+            assert!(realms[1].build.is_none());
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_reconnect_round_trip() {
+        let sessions = SessionKeys::default();
+        sessions.insert("pow", [0x42; 40]);
+
+        let (client_end, server_end) = tokio::io::duplex(1024);
+        let (mut client_reader, mut client_writer) = tokio::io::split(client_end);
+        let (mut server_reader, mut server_writer) = tokio::io::split(server_end);
+        let mut server = ReconnectProtocol { state: AuthState::default(), sessions, challenge: None };
+        let mut client = RecordingProtocol::new(GruntVersion::V8, Role::Client);
+
+        for (key, expected) in [([0x42; 40], LoginResult::Success), ([0x43; 40], LoginResult::IncorrectPassword)] {
+            // Each attempt stands for a new connection.
+            server.state = AuthState::default();
+            client.send(&mut client_writer, ReconnectChallengeRequest(challenge_request("POW")))
+                .await
+                .expect("Packet couldn't be sent");
+            server.process_incoming(&mut server_reader, &mut server_writer)
+                .await
+                .expect("Packet couldn't be handled");
+            client.process_incoming(&mut client_reader, &mut client_writer)
+                .await
+                .expect("Packet couldn't be handled");
+
+            let Some(ReconnectChallengeResponse::Ok { challenge, .. }) = client.reconnect_challenges.pop() else {
+                panic!("Expected a reconnect challenge");
+            };
+
+            client.send(&mut client_writer, answer_reconnect_challenge("POW", &challenge, &key))
+                .await
+                .expect("Packet couldn't be sent");
+            server.process_incoming(&mut server_reader, &mut server_writer)
+                .await
+                .expect("Packet couldn't be handled");
+            client.process_incoming(&mut client_reader, &mut client_writer)
+                .await
+                .expect("Packet couldn't be handled");
+
+            assert_eq!(client.reconnect_proofs.pop(), Some(ReconnectProofResponse(expected)));
+        }
+
+        // Accounts without a session cannot reconnect.
+        client.send(&mut client_writer, ReconnectChallengeRequest(challenge_request("OTHER")))
+            .await
+            .expect("Packet couldn't be sent");
+        server.process_incoming(&mut server_reader, &mut server_writer)
+            .await
+            .expect("Packet couldn't be handled");
+        client.process_incoming(&mut client_reader, &mut client_writer)
+            .await
+            .expect("Packet couldn't be handled");
+        assert_eq!(client.reconnect_challenges.pop(), Some(ReconnectChallengeResponse::Err(LoginResult::UnknownAccount)));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    pub async fn test_relayed_reconnect() {
+        let sessions = SessionKeys::default();
+        sessions.insert("pow", [0x42; 40]);
+        let server = ReconnectServer {
+            token: CancellationToken::new(),
+            sessions,
+            throttle: LoginThrottle::default(),
+        };
+        let listener = server.bind().await.expect("Failed to bind");
+        let upstream = listener.local_addr().expect("Listener should have an address");
+        let server_token = server.token.clone();
+        let server = tokio::spawn(async move {
+            server.listen(listener).await.expect("Server could not start listening.");
+        });
+
+        let relay_token = CancellationToken::new();
+        let relay = RelayServer::new("127.0.0.1:0", &upstream.to_string(), relay_token.clone());
+        let listener = relay.bind().await.expect("Failed to bind");
+        let address = listener.local_addr().expect("Listener should have an address");
+        let relay = tokio::spawn(async move {
+            relay.listen(listener).await.expect("Relay could not start listening.");
+        });
+
+        for (key, expected) in [([0x42; 40], LoginResult::Success), ([0x43; 40], LoginResult::IncorrectPassword)] {
+            let protocol = RecordingProtocol::new(GruntVersion::V8, Role::Client);
+            let mut client = Client::connect(address, protocol, CancellationToken::new())
+                .await
+                .expect("Unable to connect to relay");
+
+            client.send(ReconnectChallengeRequest(challenge_request("POW"))).await.expect("Packet couldn't be sent");
+            client.process_incoming().await.expect("Packet couldn't be handled");
+            let Some(ReconnectChallengeResponse::Ok { challenge, .. }) = client.protocol_mut().reconnect_challenges.pop() else {
+                panic!("Expected a reconnect challenge");
+            };
+
+            client.send(answer_reconnect_challenge("POW", &challenge, &key)).await.expect("Packet couldn't be sent");
+            client.process_incoming().await.expect("Packet couldn't be handled");
+            assert_eq!(client.protocol_mut().reconnect_proofs.pop(), Some(ReconnectProofResponse(expected)));
+            client.disconnect().await.expect("Client should have disconnected");
+        }
+
+        relay_token.cancel();
+        relay.await.expect("Relay should have stopped");
+        server_token.cancel();
+        server.await.expect("Server should have stopped");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    pub async fn test_relayed_bans() {
+        let (salt, verifier) = generate_verifier("pow", "secret");
+        let server = AuthServer {
+            token: CancellationToken::new(),
+            account: ("POW".to_string(), salt, verifier),
+            throttle: LoginThrottle::default(),
+        };
+        let listener = server.bind().await.expect("Failed to bind");
+        let upstream = listener.local_addr().expect("Listener should have an address");
+        let server_token = server.token.clone();
+        let server = tokio::spawn(async move {
+            server.listen(listener).await.expect("Server could not start listening.");
+        });
+
+        let relay_token = CancellationToken::new();
+        let relay = RelayServer::new("127.0.0.1:0", &upstream.to_string(), relay_token.clone())
+            .with_bans(BanList::from(Bans {
+                addresses: vec![AddressBan { network: "10.0.0.0/8".parse().unwrap(), ban: Ban::default() }],
+                accounts: vec![AccountBan { account: "pow".to_string(), ban: Ban::default() }],
+                ..Bans::default()
+            }));
+        assert!(!relay.accepts("10.1.2.3:1234".parse().unwrap()));
+        assert!(relay.accepts("127.0.0.1:1234".parse().unwrap()));
+
+        let listener = relay.bind().await.expect("Failed to bind");
+        let address = listener.local_addr().expect("Listener should have an address");
+        let relay = tokio::spawn(async move {
+            relay.listen(listener).await.expect("Relay could not start listening.");
+        });
+
+        // Banned accounts are refused by the relay, even though the upstream server knows them.
+        let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V8, "secret", false), CancellationToken::new())
+            .await
+            .expect("Unable to connect to relay");
+        let err = client.login(challenge_request("pow")).await.expect_err("Login should fail");
+        assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(LoginResult::Banned))));
+        client.disconnect().await.expect("Client should have disconnected");
+
+        let protocol = RecordingProtocol::new(GruntVersion::V8, Role::Client);
+        let mut client = Client::connect(address, protocol, CancellationToken::new())
+            .await
+            .expect("Unable to connect to relay");
+        client.send(ReconnectChallengeRequest(challenge_request("POW"))).await.expect("Packet couldn't be sent");
+        client.process_incoming().await.expect("Packet couldn't be handled");
+        assert_eq!(client.protocol_mut().reconnect_challenges.pop(), Some(ReconnectChallengeResponse::Err(LoginResult::Banned)));
+        client.disconnect().await.expect("Client should have disconnected");
+
+        relay_token.cancel();
+        relay.await.expect("Relay should have stopped");
+        server_token.cancel();
+        server.await.expect("Server should have stopped");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    pub async fn test_relayed_patch() {
+        let contents: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let directory = std::env::temp_dir().join(format!("pow-test-relayed-patches-{}", std::process::id()));
+        tokio::fs::create_dir_all(&directory).await.expect("Unable to create patch directory");
+        tokio::fs::write(directory.join("12340enUS.mpq"), &contents).await.expect("Unable to write patch");
+
+        let accounts = MemoryAccountStore::default();
+        accounts.put(Account::new("pow", "secret")).unwrap();
+        let server = LocalAuthServer::new("127.0.0.1:0", Arc::new(accounts), vec![], CancellationToken::new())
+            .with_patches(PatchDirectory::new(&directory));
+        let listener = server.bind().await.expect("Failed to bind");
+        let upstream = listener.local_addr().expect("Listener should have an address");
+        let server_token = Server::token(&server).clone();
+        let server = tokio::spawn(async move {
+            server.listen(listener).await.expect("Server could not start listening.");
+        });
+
+        for (policy, expected) in [(PatchPolicy::Relay, LoginResult::DownloadFile), (PatchPolicy::Block, LoginResult::InvalidVersion)] {
+            let relay_token = CancellationToken::new();
+            let relay = RelayServer::new("127.0.0.1:0", &upstream.to_string(), relay_token.clone())
+                .with_patches(policy);
+            let listener = relay.bind().await.expect("Failed to bind");
+            let address = listener.local_addr().expect("Listener should have an address");
+            let relay = tokio::spawn(async move {
+                relay.listen(listener).await.expect("Relay could not start listening.");
+            });
+
+            let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V8, "secret", false), CancellationToken::new())
+                .await
+                .expect("Unable to connect to relay");
+            let err = client.login(challenge_request("pow")).await.expect_err("Login should fail");
+            assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(result)) if *result == expected));
+
+            if policy == PatchPolicy::Relay {
+                // The rest of the exchange is carried on by a client that understands patches.
+                let Client { addr, token, sender, reader, .. } = client;
+                let protocol = RecordingProtocol::new(GruntVersion::V8, Role::Client);
+                let mut client = Client { addr, token, sender, reader, protocol, identifier: PhantomData };
+
+                client.process_incoming().await.expect("Packet couldn't be handled");
+                assert_eq!(client.protocol().transfers.len(), 1);
+                assert_eq!(client.protocol().transfers[0].size, contents.len() as u64);
+
+                client.send(XferResume { offset: 1000 }).await.expect("Packet couldn't be sent");
+                while client.protocol().data.len() < contents.len() - 1000 {
+                    client.process_incoming().await.expect("Packet couldn't be handled");
+                }
+                assert_eq!(client.protocol().data, contents[1000..]);
+                client.disconnect().await.expect("Client should have disconnected");
+            } else {
+                client.disconnect().await.expect("Client should have disconnected");
+            }
+
+            relay_token.cancel();
+            relay.await.expect("Relay should have stopped");
+        }
+
+        server_token.cancel();
+        server.await.expect("Server should have stopped");
+        let _ = tokio::fs::remove_dir_all(&directory).await;
+    }
+
+    #[tokio::test]
+    pub async fn test_patch_transfer() {
+        let contents: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let path = std::env::temp_dir().join(format!("pow-test-patch-{}.mpq", std::process::id()));
+        tokio::fs::write(&path, &contents).await.expect("Unable to write patch");
+
+        let patch = Patch::open(&path).await.expect("Unable to open patch");
+        assert_eq!(patch.size(), contents.len() as u64);
+        assert_eq!(patch.md5(), &[
+            0xBB, 0xF1, 0xC7, 0x75, 0x38, 0xF9, 0x7C, 0xEE, 0xB1, 0xE0, 0x31, 0x26, 0xED, 0xAA, 0x35, 0x94
+        ]);
+
+        let (client_end, server_end) = tokio::io::duplex(1 << 16);
+        let (mut client_reader, mut client_writer) = tokio::io::split(client_end);
+        let (mut server_reader, mut server_writer) = tokio::io::split(server_end);
+        let mut server = PatchProtocol {
+            // The client asks for the realm list without logging in.
+            state: AuthState::Authenticated,
+            patch,
+            pending: None,
+            transfer: None,
+        };
+        let mut client = RecordingProtocol::new(GruntVersion::V3, Role::Client);
+
+        // Declining a patch leaves nothing to transfer.
+        client.send(&mut client_writer, RealmlistRequest).await.expect("Packet couldn't be sent");
+        server.process_incoming(&mut server_reader, &mut server_writer).await.expect("Packet couldn't be handled");
+        client.process_incoming(&mut client_reader, &mut client_writer).await.expect("Packet couldn't be handled");
+        client.send(&mut client_writer, XferCancel).await.expect("Packet couldn't be sent");
+        server.process_incoming(&mut server_reader, &mut server_writer).await.expect("Packet couldn't be handled");
+        assert!(server.pending.is_none());
+
+        // Resuming a patch only sends the remainder of the file.
+        client.send(&mut client_writer, RealmlistRequest).await.expect("Packet couldn't be sent");
+        server.process_incoming(&mut server_reader, &mut server_writer).await.expect("Packet couldn't be handled");
+        client.process_incoming(&mut client_reader, &mut client_writer).await.expect("Packet couldn't be handled");
+        assert_eq!(client.transfers.len(), 2);
+        assert_eq!(client.transfers[1].filename, "Patch");
+        assert_eq!(client.transfers[1].size, contents.len() as u64);
+
+        client.send(&mut client_writer, XferResume { offset: 1000 }).await.expect("Packet couldn't be sent");
+        server.process_incoming(&mut server_reader, &mut server_writer).await.expect("Packet couldn't be handled");
+        while GruntProtocol::send_queued(&mut server, &mut server_writer).await.expect("Chunk couldn't be sent") {
+            client.process_incoming(&mut client_reader, &mut client_writer).await.expect("Packet couldn't be handled");
+        }
+        assert_eq!(client.data, contents[1000..]);
+
+        // Cancelling a transfer stops it before the next chunk.
+        client.data.clear();
+        client.send(&mut client_writer, RealmlistRequest).await.expect("Packet couldn't be sent");
+        server.process_incoming(&mut server_reader, &mut server_writer).await.expect("Packet couldn't be handled");
+        client.process_incoming(&mut client_reader, &mut client_writer).await.expect("Packet couldn't be handled");
+        client.send(&mut client_writer, XferAccept).await.expect("Packet couldn't be sent");
+        server.process_incoming(&mut server_reader, &mut server_writer).await.expect("Packet couldn't be handled");
+        assert!(GruntProtocol::send_queued(&mut server, &mut server_writer).await.expect("Chunk couldn't be sent"));
+        client.process_incoming(&mut client_reader, &mut client_writer).await.expect("Packet couldn't be handled");
+        client.send(&mut client_writer, XferCancel).await.expect("Packet couldn't be sent");
+        server.process_incoming(&mut server_reader, &mut server_writer).await.expect("Packet couldn't be handled");
+        assert!(!GruntProtocol::send_queued(&mut server, &mut server_writer).await.expect("Chunk couldn't be sent"));
+        assert_eq!(client.data, contents[..4096]);
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    pub async fn test_translated_login() {
+        let (salt, verifier) = generate_verifier("pow", "secret");
+        let server = AuthServer {
+            token: CancellationToken::new(),
+            account: ("POW".to_string(), salt, verifier),
+            throttle: LoginThrottle::default(),
+        };
+        let listener = server.bind().await.expect("Failed to bind");
+        let upstream = listener.local_addr().expect("Listener should have an address");
+        let server_token = server.token.clone();
+        let server = tokio::spawn(async move {
+            server.listen(listener).await.expect("Server could not start listening.");
+        });
+
+        let addresses = HashMap::from([("127.0.0.1:8085".to_string(), "pow.example:8085".to_string())]);
+        let secrets = AuthenticatorSecrets::default();
+        let relay_token = CancellationToken::new();
+        let rewriter = RealmRewriter::new(addresses, None, "127.0.0.1", CancellationToken::new());
+        let relay = RelayServer::new("127.0.0.1:0", &upstream.to_string(), relay_token.clone())
+            .with_authenticator(secrets.clone())
+            .with_realms(rewriter.clone());
+        let listener = relay.bind().await.expect("Failed to bind");
+        let address = listener.local_addr().expect("Listener should have an address");
+        let relay = tokio::spawn(async move {
+            relay.listen(listener).await.expect("Relay could not start listening.");
+        });
+
+        // Legacy clients are passed off as a client the upstream server knows.
+        let vanilla = || LogonChallengeRequest { version: "1.12.1.5875".parse().unwrap(), ..challenge_request("pow") };
+        for version in [GruntVersion::V2, GruntVersion::V3] {
+            let mut client = Client::connect(address, LoginProtocol::new(version, "secret", true), CancellationToken::new())
+                .await
+                .expect("Unable to connect to relay");
+            let login = client.login(vanilla()).await.expect("Login should succeed");
+            assert_eq!(login.realms.len(), 1);
+            assert_eq!(login.realms[0].name, "Pow");
+            assert_eq!(login.realms[0].address, "pow.example:8085");
+            client.disconnect().await.expect("Client should have disconnected");
+
+            // The client is let into the world with the build it logged in with.
+            let login = rewriter.logins().get(client.ip()).expect("The login should have been recorded");
+            assert_eq!(login.account, "POW");
+            assert_eq!(login.build.name, "1.12.1");
+        }
+
+        // Guarded accounts must provide a code, which the upstream server never sees.
+        let totp = Totp::new(b"12345678901234567890".to_vec());
+        secrets.insert("pow", totp.clone());
+        for (protocol, request, expected) in [
+            (LoginProtocol::new(GruntVersion::V8, "secret", true).with_authenticator(totp.clone()), challenge_request("pow"), None),
+            (LoginProtocol::new(GruntVersion::V8, "secret", true).with_authenticator(Totp::new(vec![0; 20])), challenge_request("pow"), Some(LoginResult::IncorrectPassword)),
+            (LoginProtocol::new(GruntVersion::V2, "secret", true), vanilla(), Some(LoginResult::InvalidVersion)),
+        ] {
+            let mut client = Client::connect(address, protocol, CancellationToken::new())
+                .await
+                .expect("Unable to connect to relay");
+            match (client.login(request).await, expected) {
+                (Ok(login), None) => assert_eq!(login.realms.len(), 1),
+                (Err(err), Some(result)) => assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(r)) if *r == result)),
+                (outcome, _) => panic!("Unexpected outcome {:?}", outcome.map(|_| ())),
+            }
+            client.disconnect().await.expect("Client should have disconnected");
+        }
+
+        relay_token.cancel();
+        relay.await.expect("Relay should have stopped");
+        server_token.cancel();
+        server.await.expect("Server should have stopped");
+    }
+
+    #[tokio::test]
+    pub async fn test_out_of_order_commands() {
+        let (salt, verifier) = generate_verifier("pow", "secret");
+        let server = || AuthProtocol {
+            version: GruntVersion::V8,
+            account: ("POW".to_string(), salt, verifier),
+            srp: None,
+            state: AuthState::default(),
+        };
+
+        // A proof without a challenge.
+        let (mut client_end, mut server_end) = tokio::io::duplex(1024);
+        let mut client = RecordingProtocol::new(GruntVersion::V8, Role::Client);
+        client.send(&mut client_end, LogonProofRequest {
+            public_key: [1; 32],
+            proof: [0; 20],
+            crc: [0; 20],
+            telemetry_keys: vec![],
+            security: vec![],
+        }).await.expect("Packet couldn't be sent");
+
+        let err = server().process_incoming(&mut server_end, &mut tokio::io::sink())
+            .await
+            .expect_err("Proof should be rejected");
+        let err = err.downcast_ref::<UnexpectedCommand>().expect("Error should be typed");
+        assert_eq!((err.command, err.state), (0x01, AuthState::AwaitingChallenge));
+
+        // A realm list request after a failed proof.
+        let (mut client_end, mut server_end) = tokio::io::duplex(1024);
+        let mut server = server();
+        client.send(&mut client_end, challenge_request("pow")).await.expect("Packet couldn't be sent");
+        client.send(&mut client_end, LogonProofRequest {
+            public_key: [1; 32],
+            proof: [0; 20],
+            crc: [0; 20],
+            telemetry_keys: vec![],
+            security: vec![],
+        }).await.expect("Packet couldn't be sent");
+        client.send(&mut client_end, RealmlistRequest).await.expect("Packet couldn't be sent");
+
+        for _ in 0..2 {
+            server.process_incoming(&mut server_end, &mut tokio::io::sink())
+                .await
+                .expect("Packet should be handled");
+        }
+        assert_eq!(server.state, AuthState::AwaitingChallenge);
+
+        let err = server.process_incoming(&mut server_end, &mut tokio::io::sink())
+            .await
+            .expect_err("Realm list should require authentication");
+        assert!(err.downcast_ref::<UnexpectedCommand>().is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    pub async fn test_login() {
+        let (salt, verifier) = generate_verifier("pow", "secret");
+        let server = AuthServer {
+            token: CancellationToken::new(),
+            account: ("POW".to_string(), salt, verifier),
+            throttle: LoginThrottle::default(),
+        };
+        let listener = server.bind().await.expect("Failed to bind");
+        let address = listener.local_addr().expect("Listener should have an address");
+        let token = server.token.clone();
+
+        let server = tokio::spawn(async move {
+            server.listen(listener).await.expect("Server could not start listening.");
+        });
+
+        for version in [GruntVersion::V3, GruntVersion::V8] {
+            let mut client = Client::connect(address, LoginProtocol::new(version, "SECRET", true), CancellationToken::new())
+                .await
+                .expect("Unable to connect to local server");
+            let login = client.login(challenge_request("pow")).await.expect("Login should succeed");
+            assert_eq!(login.realms.len(), 1);
+            assert_eq!(login.realms[0].name, "Pow");
+            client.disconnect().await.expect("Client should have disconnected");
+        }
+
+        let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V8, "wrong", false), CancellationToken::new())
+            .await
+            .expect("Unable to connect to local server");
+        let err = client.login(challenge_request("pow")).await.expect_err("Login should fail");
+        assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(LoginResult::IncorrectPassword))));
+        client.disconnect().await.expect("Client should have disconnected");
+
+        token.cancel();
+        server.await.expect("Server should have stopped");
+    }
+}
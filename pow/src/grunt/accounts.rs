@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::grunt::bans::Ban;
+use crate::grunt::srp::{Key, generate_verifier};
+
+/// An account, as known by an authentication server.
+///
+/// The password of the account is never kept; only its SRP6 salt and verifier are.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Account {
+    /// The name of the account, in uppercase.
+    pub name: String,
+    #[serde(with = "hex")]
+    pub salt: Key,
+    #[serde(with = "hex")]
+    pub verifier: Key,
+    /// The PIN the account must provide after its password, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+    /// The flags sent to the client in the logon proof response.
+    #[serde(default)]
+    pub flags: u32,
+    /// The ban of the account, if any. It is enforced along with the ban file of the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ban: Option<Ban>,
+}
+
+impl Account {
+    /// Creates an account with the given password. The password itself is not kept.
+    pub fn new(name: &str, password: &str) -> Self {
+        let (salt, verifier) = generate_verifier(name, password);
+
+        Self {
+            name: name.to_uppercase(),
+            salt,
+            verifier,
+            pin: None,
+            flags: 0,
+            ban: None,
+        }
+    }
+
+    /// Requires the account to provide the given PIN after its password.
+    pub fn with_pin(self, pin: &str) -> Result<Self> {
+        if !(4..=10).contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+            bail!("A PIN must have between 4 and 10 digits");
+        }
+
+        Ok(Self { pin: Some(pin.to_string()), ..self })
+    }
+
+    /// Changes the password of the account. A new salt is generated.
+    pub fn set_password(&mut self, password: &str) {
+        (self.salt, self.verifier) = generate_verifier(&self.name, password);
+    }
+}
+
+/// A source of accounts for an authentication server.
+///
+/// Account names are case-insensitive, as they are in Grunt.
+pub trait AccountStore: Send + Sync + 'static {
+    /// Returns the account with the given name, if it exists.
+    fn get(&self, name: &str) -> Option<Account>;
+
+    /// Creates or replaces an account.
+    fn put(&self, account: Account) -> Result<()>;
+
+    /// Returns every account, sorted by name.
+    fn list(&self) -> Vec<Account>;
+}
+
+/// An [`AccountStore`] that only lives in memory.
+#[derive(Default)]
+pub struct MemoryAccountStore {
+    accounts: RwLock<HashMap<String, Account>>,
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn get(&self, name: &str) -> Option<Account> {
+        self.accounts.read().unwrap().get(&name.to_uppercase()).cloned()
+    }
+
+    fn put(&self, account: Account) -> Result<()> {
+        self.accounts.write().unwrap().insert(account.name.to_uppercase(), account);
+        Ok(())
+    }
+
+    fn list(&self) -> Vec<Account> {
+        let mut accounts = self.accounts.read().unwrap().values().cloned().collect::<Vec<_>>();
+        accounts.sort_by(|left, right| left.name.cmp(&right.name));
+        accounts
+    }
+}
+
+/// An [`AccountStore`] backed by a JSON file on the local disk.
+///
+/// The whole file is loaded when the store is opened and rewritten every time an account
+/// changes. Changes made to the file by another process are picked up automatically with
+/// [`JsonAccountStore::watch`].
+pub struct JsonAccountStore {
+    path: PathBuf,
+    accounts: RwLock<BTreeMap<String, Account>>,
+}
+
+impl JsonAccountStore {
+    /// Opens the store at the given path. A missing file is treated as an empty store, and
+    /// is only created once an account is added.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let accounts = Self::read(&path)?;
+
+        Ok(Self { path, accounts: RwLock::new(accounts) })
+    }
+
+    fn read(path: &Path) -> Result<BTreeMap<String, Account>> {
+        let accounts = match fs::read(path) {
+            Ok(contents) => serde_json::from_slice::<Vec<Account>>(&contents)
+                .with_context(|| format!("Malformed account file {}", path.display()))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err).with_context(|| format!("Unable to read {}", path.display())),
+        };
+
+        Ok(accounts.into_iter()
+            .map(|account| (account.name.to_uppercase(), account))
+            .collect())
+    }
+
+    /// Reads the file again. If it cannot be read, the current accounts are kept.
+    pub fn reload(&self) -> Result<()> {
+        // The lock is held while reading, so that a concurrent change is not overwritten by
+        // the previous contents of the file.
+        let mut accounts = self.accounts.write().unwrap();
+        *accounts = Self::read(&self.path)?;
+        Ok(())
+    }
+
+    /// Reloads the file whenever it changes, checking every `interval`.
+    pub async fn watch(self: Arc<Self>, interval: Duration) {
+        let modified = || fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        let mut last_modified = modified();
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let current = modified();
+            if current == last_modified {
+                continue;
+            }
+
+            last_modified = current;
+            match self.reload() {
+                Ok(()) => info!("Reloaded accounts from {}", self.path.display()),
+                Err(err) => error!("Unable to reload accounts: {:#}", err),
+            }
+        }
+    }
+
+    /// Writes the accounts to a temporary file first, so that a failure leaves the previous
+    /// file intact.
+    fn save(&self, accounts: &BTreeMap<String, Account>) -> Result<()> {
+        let contents = serde_json::to_vec_pretty(&accounts.values().collect::<Vec<_>>())?;
+
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+impl AccountStore for JsonAccountStore {
+    fn get(&self, name: &str) -> Option<Account> {
+        self.accounts.read().unwrap().get(&name.to_uppercase()).cloned()
+    }
+
+    fn put(&self, account: Account) -> Result<()> {
+        let mut accounts = self.accounts.write().unwrap();
+
+        // The change is saved before it is committed to memory, so that a failed write does
+        // not leave the two out of sync.
+        let mut updated = accounts.clone();
+        updated.insert(account.name.to_uppercase(), account);
+        self.save(&updated)?;
+
+        *accounts = updated;
+        Ok(())
+    }
+
+    fn list(&self) -> Vec<Account> {
+        self.accounts.read().unwrap().values().cloned().collect()
+    }
+}
+
+/// Serializes keys as hexadecimal strings.
+mod hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    use crate::grunt::srp::Key;
+
+    pub fn serialize<S: Serializer>(key: &Key, serializer: S) -> Result<S::Ok, S::Error> {
+        let text = key.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        serializer.serialize_str(&text)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if text.len() != 64 || !text.is_ascii() {
+            return Err(D::Error::custom("Expected 64 hexadecimal digits"));
+        }
+
+        let mut key = [0; 32];
+        for (byte, digits) in key.iter_mut().zip(text.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).unwrap();
+            *byte = u8::from_str_radix(digits, 16).map_err(D::Error::custom)?;
+        }
+
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::accounts::{Account, AccountStore, JsonAccountStore};
+    use crate::grunt::bans::Ban;
+    use crate::grunt::srp::compute_verifier;
+
+    #[test]
+    pub fn test_json_store() {
+        let path = std::env::temp_dir().join(format!("pow-accounts-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = JsonAccountStore::open(&path).expect("A missing file should be an empty store");
+        assert!(store.list().is_empty());
+
+        store.put(Account::new("pow", "secret").with_pin("1234").unwrap()).unwrap();
+        store.put(Account::new("alice", "hunter2")).unwrap();
+
+        let mut account = store.get("Pow").expect("Names should be case-insensitive");
+        account.set_password("changed");
+        account.ban = Some(Ban { expires: Some(1000), reason: Some("Botting".to_string()) });
+        store.put(account).unwrap();
+
+        let store = JsonAccountStore::open(&path).expect("The file should have been written");
+        let names = store.list().into_iter().map(|account| account.name).collect::<Vec<_>>();
+        assert_eq!(names, ["ALICE", "POW"]);
+
+        let account = store.get("POW").unwrap();
+        assert_eq!(compute_verifier("pow", "changed", &account.salt), account.verifier);
+        assert_eq!(account.pin.as_deref(), Some("1234"));
+        assert_eq!(account.ban, Some(Ban { expires: Some(1000), reason: Some("Botting".to_string()) }));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("hunter2"));
+
+        // Changes made by another process are picked up on reload.
+        let other = JsonAccountStore::open(&path).unwrap();
+        other.put(Account::new("bob", "swordfish")).unwrap();
+        assert!(store.get("bob").is_none());
+        store.reload().unwrap();
+        assert!(store.get("bob").is_some());
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(store.reload().is_err());
+        assert!(store.get("bob").is_some(), "A malformed file should keep the current accounts");
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(Account::new("pow", "secret").with_pin("12a4").is_err());
+
+        // Accounts that cannot be saved are not kept either.
+        let store = JsonAccountStore::open(path.join("missing").join("accounts.json")).unwrap();
+        assert!(store.put(Account::new("pow", "secret")).is_err());
+        assert!(store.get("pow").is_none());
+    }
+}
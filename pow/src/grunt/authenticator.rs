@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, bail};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+use crate::grunt::protocol::{
+    GruntVersion, LoginResult, LogonChallengeRequest, LogonChallengeResponse, LogonProofRequest, LogonProofResponse,
+    SecurityChallenge, SecurityProof
+};
+
+type HmacSha1 = Hmac<Sha1>;
+
+/// A time-based one-time password generator, as described in RFC 6238.
+///
+/// Codes have 6 digits and change every 30 seconds, which is what authenticator apps expect.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Totp {
+    secret: Vec<u8>,
+}
+
+impl Totp {
+    /// The amount of digits in a code.
+    pub const DIGITS: u32 = 6;
+    /// The amount of seconds a code is valid for.
+    pub const STEP: u64 = 30;
+    /// The amount of steps a code may be late or early, to account for clock drift.
+    pub const SKEW: u64 = 1;
+
+    pub fn new(secret: Vec<u8>) -> Self {
+        Self { secret }
+    }
+
+    /// Decodes a secret encoded in base 32, as shown to users by authenticator apps.
+    ///
+    /// Padding, whitespace and case are ignored.
+    pub fn from_base32(secret: &str) -> Result<Self> {
+        let mut bytes = Vec::with_capacity(secret.len() * 5 / 8);
+        let mut buffer = 0u64;
+        let mut bits = 0;
+
+        for c in secret.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+            let value = match c.to_ascii_uppercase() {
+                c @ 'A'..='Z' => c as u64 - 'A' as u64,
+                c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+                _ => bail!("Invalid base 32 character '{}'", c),
+            };
+
+            buffer = (buffer << 5) | value;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                bytes.push((buffer >> bits) as u8);
+            }
+        }
+
+        if bytes.is_empty() {
+            bail!("An authenticator secret cannot be empty");
+        }
+
+        Ok(Self::new(bytes))
+    }
+
+    /// Returns the code for the given UNIX timestamp.
+    pub fn code_at(&self, timestamp: u64) -> String {
+        let counter = timestamp / Self::STEP;
+        let digest = HmacSha1::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any size")
+            .chain_update(counter.to_be_bytes())
+            .finalize()
+            .into_bytes();
+
+        // Dynamic truncation, see RFC 4226 section 5.3.
+        let offset = (digest[19] & 0x0F) as usize;
+        let value = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+        let code = (value & 0x7FFF_FFFF) % 10u32.pow(Self::DIGITS);
+
+        format!("{:0width$}", code, width = Self::DIGITS as usize)
+    }
+
+    /// Returns the current code.
+    pub fn code(&self) -> String {
+        self.code_at(now())
+    }
+
+    /// Returns the step a code belongs to if it is valid at the given UNIX timestamp,
+    /// allowing for [`Self::SKEW`] steps of drift.
+    pub fn step_at(&self, code: &str, timestamp: u64) -> Option<u64> {
+        (0..=Self::SKEW * 2)
+            .filter_map(|step| (timestamp + Self::SKEW * Self::STEP).checked_sub(step * Self::STEP))
+            .map(|timestamp| timestamp / Self::STEP)
+            .find(|step| bool::from(self.code_at(step * Self::STEP).as_bytes().ct_eq(code.as_bytes())))
+    }
+
+    /// Verifies a code for the given UNIX timestamp, allowing for [`Self::SKEW`] steps of drift.
+    pub fn verify_at(&self, code: &str, timestamp: u64) -> bool {
+        self.step_at(code, timestamp).is_some()
+    }
+
+    /// Verifies a code against the current time.
+    pub fn verify(&self, code: &str) -> bool {
+        self.verify_at(code, now())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Authenticator secrets of the accounts protected by the proxy, shared between all connections of a pipe.
+///
+/// Account names are case-insensitive, as they are in Grunt.
+#[derive(Clone, Default)]
+pub struct AuthenticatorSecrets {
+    secrets: Arc<RwLock<HashMap<String, Totp>>>,
+    /// The step of the last code accepted from each account.
+    last_steps: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl AuthenticatorSecrets {
+    pub fn insert(&self, account: &str, totp: Totp) {
+        self.secrets.write().unwrap().insert(account.to_uppercase(), totp);
+    }
+
+    pub fn get(&self, account: &str) -> Option<Totp> {
+        self.secrets.read().unwrap().get(&account.to_uppercase()).cloned()
+    }
+
+    pub fn remove(&self, account: &str) -> Option<Totp> {
+        self.secrets.write().unwrap().remove(&account.to_uppercase())
+    }
+
+    /// Verifies a code sent for the given account at the given UNIX timestamp.
+    ///
+    /// A code is only accepted once: neither it nor any code of an earlier step is accepted
+    /// again for the same account, so that a code seen by someone else cannot be replayed.
+    pub fn verify_at(&self, account: &str, code: &str, timestamp: u64) -> bool {
+        let Some(step) = self.get(account).and_then(|totp| totp.step_at(code, timestamp)) else {
+            return false;
+        };
+
+        let mut last_steps = self.last_steps.lock().unwrap();
+        match last_steps.get(&account.to_uppercase()) {
+            Some(&last) if step <= last => false,
+            _ => {
+                last_steps.insert(account.to_uppercase(), step);
+                true
+            },
+        }
+    }
+
+    /// Verifies a code sent for the given account against the current time.
+    pub fn verify(&self, account: &str, code: &str) -> bool {
+        self.verify_at(account, code, now())
+    }
+}
+
+/// Enforces an authenticator code on behalf of an upstream server that does not support it.
+///
+/// One instance is created per proxied connection. The proxy hands it every logon packet
+/// before forwarding it:
+/// - [`Self::challenge_request`] looks up the secret of the account logging in.
+/// - [`Self::challenge_response`] asks the client for a code on behalf of the server.
+/// - [`Self::proof_request`] checks the code and strips it, so that the server never sees it.
+///
+/// Accounts without a secret are not affected.
+pub struct AuthenticatorGate {
+    secrets: AuthenticatorSecrets,
+    /// The protected account being logged into, if any.
+    pending: Option<String>,
+}
+
+impl AuthenticatorGate {
+    pub fn new(secrets: AuthenticatorSecrets) -> Self {
+        Self { secrets, pending: None }
+    }
+
+    /// Inspects the challenge sent by the client.
+    pub fn challenge_request(&mut self, request: &LogonChallengeRequest) {
+        self.pending = self.secrets.get(&request.account_name).map(|_| request.account_name.clone());
+    }
+
+    /// Injects the authenticator challenge into the response sent by the server.
+    ///
+    /// Clients without security flags cannot answer the challenge and are turned away with
+    /// [`LoginResult::InvalidVersion`]. Other second factors required by the server are left
+    /// for the client to answer, but protected accounts are turned away with
+    /// [`LoginResult::NoAccess`] if the server asks for an authenticator code of its own,
+    /// since the client can only send one.
+    ///
+    /// # Arguments
+    ///
+    /// - `response`: The response of the server.
+    /// - `version`: The protocol version of the client.
+    pub fn challenge_response(&mut self, response: LogonChallengeResponse, version: GruntVersion) -> LogonChallengeResponse {
+        if self.pending.is_none() {
+            return response;
+        }
+
+        match response {
+            LogonChallengeResponse::Ok { .. } if !version.has_security_flags() => {
+                self.pending = None;
+                LogonChallengeResponse::Err(LoginResult::InvalidVersion)
+            },
+            LogonChallengeResponse::Ok { security, .. }
+                if security.iter().any(|challenge| matches!(challenge, SecurityChallenge::Authenticator(_))) =>
+            {
+                self.pending = None;
+                LogonChallengeResponse::Err(LoginResult::NoAccess)
+            },
+            LogonChallengeResponse::Ok { public_key, generator, large_safe_prime, salt, crc, mut security } => {
+                security.push(SecurityChallenge::Authenticator(1));
+                LogonChallengeResponse::Ok {
+                    public_key,
+                    generator,
+                    large_safe_prime,
+                    salt,
+                    crc,
+                    security,
+                }
+            },
+            LogonChallengeResponse::Err(result) => {
+                self.pending = None;
+                LogonChallengeResponse::Err(result)
+            },
+        }
+    }
+
+    /// Verifies the code sent by the client.
+    ///
+    /// Returns the request to forward to the server, or the response to send back to the
+    /// client if the code is missing or invalid.
+    pub fn proof_request(&mut self, mut request: LogonProofRequest) -> Result<LogonProofRequest, LogonProofResponse> {
+        let Some(account) = self.pending.take() else {
+            return Ok(request);
+        };
+
+        let code = request.security.iter()
+            .position(|proof| matches!(proof, SecurityProof::Authenticator(_)))
+            .map(|index| request.security.remove(index));
+
+        match code {
+            Some(SecurityProof::Authenticator(code)) if self.secrets.verify(&account, &code) => Ok(request),
+            _ => Err(LogonProofResponse::Err(LoginResult::IncorrectPassword)),
+        }
+    }
+}
+
+/// Answers an authenticator challenge. This is the client side of the exchange.
+pub fn answer_authenticator_challenge(totp: &Totp, challenge: &SecurityChallenge) -> Result<SecurityProof> {
+    let SecurityChallenge::Authenticator(_) = challenge else {
+        bail!("Expected an authenticator challenge, found {:?}", challenge);
+    };
+
+    Ok(SecurityProof::Authenticator(totp.code()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::authenticator::{AuthenticatorGate, AuthenticatorSecrets, Totp, answer_authenticator_challenge};
+    use crate::grunt::protocol::{
+        GruntVersion, LoginResult, LogonChallengeResponse, LogonProofRequest, LogonProofResponse, SecurityChallenge, SecurityProof
+    };
+    use crate::grunt::test::challenge_request;
+
+    fn rfc_totp() -> Totp {
+        Totp::new(b"12345678901234567890".to_vec())
+    }
+
+    fn challenge_response(security: Vec<SecurityChallenge>) -> LogonChallengeResponse {
+        LogonChallengeResponse::Ok {
+            public_key: [1; 32],
+            generator: Box::new([7]),
+            large_safe_prime: Box::new([2; 32]),
+            salt: [3; 32],
+            crc: [4; 16],
+            security,
+        }
+    }
+
+    fn proof_request(security: Vec<SecurityProof>) -> LogonProofRequest {
+        LogonProofRequest {
+            public_key: [1; 32],
+            proof: [2; 20],
+            crc: [3; 20],
+            telemetry_keys: vec![],
+            security,
+        }
+    }
+
+    #[test]
+    pub fn test_rfc_vectors() {
+        // RFC 6238 appendix B, truncated to 6 digits.
+        let totp = rfc_totp();
+        assert_eq!(totp.code_at(59), "287082");
+        assert_eq!(totp.code_at(1111111109), "081804");
+        assert_eq!(totp.code_at(1234567890), "005924");
+        assert_eq!(totp.code_at(20000000000), "353130");
+
+        assert!(totp.verify_at("081804", 1111111109 + 30));
+        assert!(totp.verify_at("081804", 1111111109 - 30));
+        assert!(!totp.verify_at("081804", 1111111109 + 90));
+        assert!(totp.verify_at("287082", 0));
+    }
+
+    #[test]
+    pub fn test_replayed_codes() {
+        let secrets = AuthenticatorSecrets::default();
+        secrets.insert("pow", rfc_totp());
+        secrets.insert("other", rfc_totp());
+
+        assert!(secrets.verify_at("pow", "081804", 1111111109));
+        // The same code cannot be used twice, even for another login within its window.
+        assert!(!secrets.verify_at("POW", "081804", 1111111109 + 30));
+        // Neither can codes of earlier steps, while later ones are still accepted.
+        assert!(!secrets.verify_at("pow", &rfc_totp().code_at(1111111109 - 30), 1111111109));
+        assert!(secrets.verify_at("pow", &rfc_totp().code_at(1111111109 + 30), 1111111109 + 30));
+        // Other accounts are not affected.
+        assert!(secrets.verify_at("other", "081804", 1111111109));
+    }
+
+    #[test]
+    pub fn test_base32() {
+        assert_eq!(Totp::from_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap(), rfc_totp());
+        assert_eq!(Totp::from_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap(), rfc_totp());
+        assert!(Totp::from_base32("GEZDGNBV1").is_err());
+        assert!(Totp::from_base32("====").is_err());
+    }
+
+    #[test]
+    pub fn test_gate() {
+        let secrets = AuthenticatorSecrets::default();
+        secrets.insert("pow", rfc_totp());
+
+        // Accounts without a secret go through untouched.
+        let mut gate = AuthenticatorGate::new(secrets.clone());
+        gate.challenge_request(&challenge_request("other"));
+        let response = gate.challenge_response(challenge_response(vec![]), GruntVersion::V8);
+        assert!(matches!(response, LogonChallengeResponse::Ok { security, .. } if security.is_empty()));
+        let request = gate.proof_request(proof_request(vec![])).expect("Proof should be forwarded");
+        assert_eq!(request.security, vec![]);
+
+        // Protected accounts must provide a valid code, which is stripped.
+        let mut gate = AuthenticatorGate::new(secrets.clone());
+        gate.challenge_request(&challenge_request("POW"));
+        let response = gate.challenge_response(challenge_response(vec![]), GruntVersion::V8);
+        let LogonChallengeResponse::Ok { security, .. } = response else { panic!("Challenge should succeed") };
+        let proof = answer_authenticator_challenge(&rfc_totp(), &security[0]).unwrap();
+        let request = gate.proof_request(proof_request(vec![proof])).expect("Proof should be forwarded");
+        assert_eq!(request.security, vec![]);
+
+        let mut gate = AuthenticatorGate::new(secrets.clone());
+        gate.challenge_request(&challenge_request("pow"));
+        gate.challenge_response(challenge_response(vec![]), GruntVersion::V8);
+        let response = gate.proof_request(proof_request(vec![SecurityProof::Authenticator("000000".to_string())]));
+        assert_eq!(response.err(), Some(LogonProofResponse::Err(LoginResult::IncorrectPassword)));
+
+        let mut gate = AuthenticatorGate::new(secrets.clone());
+        gate.challenge_request(&challenge_request("pow"));
+        gate.challenge_response(challenge_response(vec![]), GruntVersion::V8);
+        let response = gate.proof_request(proof_request(vec![]));
+        assert_eq!(response.err(), Some(LogonProofResponse::Err(LoginResult::IncorrectPassword)));
+
+        // Clients that cannot answer are turned away.
+        let mut gate = AuthenticatorGate::new(secrets.clone());
+        gate.challenge_request(&challenge_request("pow"));
+        let response = gate.challenge_response(challenge_response(vec![]), GruntVersion::V2);
+        assert!(matches!(response, LogonChallengeResponse::Err(LoginResult::InvalidVersion)));
+
+        let mut gate = AuthenticatorGate::new(secrets.clone());
+        gate.challenge_request(&challenge_request("pow"));
+        let response = gate.challenge_response(challenge_response(vec![SecurityChallenge::Authenticator(1)]), GruntVersion::V8);
+        assert!(matches!(response, LogonChallengeResponse::Err(LoginResult::NoAccess)));
+
+        // The second factors of the server are answered along with the code.
+        secrets.insert("pin", rfc_totp());
+        let pin = SecurityChallenge::Pin { seed: 0, salt: [0; 16] };
+        let mut gate = AuthenticatorGate::new(secrets);
+        gate.challenge_request(&challenge_request("pin"));
+        let response = gate.challenge_response(challenge_response(vec![pin.clone()]), GruntVersion::V8);
+        assert!(matches!(response, LogonChallengeResponse::Ok { security, .. } if security == [pin, SecurityChallenge::Authenticator(1)]));
+        let proofs = vec![SecurityProof::Pin { salt: [1; 16], hash: [2; 20] }, SecurityProof::Authenticator(rfc_totp().code())];
+        let request = gate.proof_request(proof_request(proofs)).expect("Proof should be forwarded");
+        assert_eq!(request.security, vec![SecurityProof::Pin { salt: [1; 16], hash: [2; 20] }]);
+    }
+}
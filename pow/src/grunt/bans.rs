@@ -0,0 +1,304 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::grunt::protocol::{GruntVersion, LoginResult};
+
+/// A ban, either permanent or lifted at a given time.
+#[derive(Clone, PartialEq, Eq, Default, Serialize, Deserialize, Debug)]
+pub struct Ban {
+    /// The UNIX timestamp at which the ban is lifted. Bans without one are permanent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Ban {
+    /// Creates a ban starting now. Bans without a duration are permanent.
+    pub fn new(duration: Option<Duration>, reason: Option<String>) -> Self {
+        Self { expires: duration.map(|duration| now() + duration.as_secs()), reason }
+    }
+
+    pub fn is_active_at(&self, now: u64) -> bool {
+        self.expires.is_none_or(|expires| now < expires)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active_at(now())
+    }
+
+    /// Returns the result to refuse logins with at the given UNIX timestamp, if the ban is
+    /// still active: [`LoginResult::Banned`] for permanent bans, or [`LoginResult::Suspended`]
+    /// for temporary ones.
+    pub fn result_at(&self, now: u64) -> Option<LoginResult> {
+        match self.expires {
+            _ if !self.is_active_at(now) => None,
+            None => Some(LoginResult::Banned),
+            Some(_) => Some(LoginResult::Suspended),
+        }
+    }
+}
+
+/// A ban of every address in a network.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AddressBan {
+    /// The banned network, in CIDR notation. A single address may omit the prefix length.
+    #[serde(with = "network")]
+    pub network: IpNet,
+    #[serde(flatten)]
+    pub ban: Ban,
+}
+
+/// A ban of an account.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AccountBan {
+    pub account: String,
+    #[serde(flatten)]
+    pub ban: Ban,
+}
+
+/// The contents of a ban file.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct Bans {
+    /// If not empty, only addresses in these networks may connect.
+    #[serde(default, with = "networks")]
+    pub allow: Vec<IpNet>,
+    #[serde(default)]
+    pub addresses: Vec<AddressBan>,
+    #[serde(default)]
+    pub accounts: Vec<AccountBan>,
+}
+
+impl Bans {
+    /// Whether a client may connect from the given address at the given UNIX timestamp.
+    pub fn allows_at(&self, address: IpAddr, now: u64) -> bool {
+        // IPv4 clients connecting to a dual-stack listener show up as IPv4-mapped addresses.
+        let address = address.to_canonical();
+
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|network| network.contains(&address));
+        allowed && !self.addresses.iter().any(|ban| ban.network.contains(&address) && ban.ban.is_active_at(now))
+    }
+
+    /// Returns the result to refuse the given account with at the given UNIX timestamp, if
+    /// it is banned either by this file or by `own`, the ban kept with the account itself.
+    /// Permanent bans take precedence over temporary ones. See [`Ban::result_at`].
+    pub fn account_ban_at(&self, account: &str, own: Option<&Ban>, now: u64) -> Option<LoginResult> {
+        self.accounts.iter()
+            .filter(|ban| ban.account.eq_ignore_ascii_case(account))
+            .map(|ban| &ban.ban)
+            .chain(own)
+            .filter_map(|ban| ban.result_at(now))
+            .min_by_key(|result| *result != LoginResult::Banned)
+    }
+}
+
+/// The bans enforced by a server, shared between all its connections.
+///
+/// Bans loaded from a file can be reloaded at runtime with [`BanList::reload`], or
+/// automatically with [`BanList::watch`].
+#[derive(Clone, Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    bans: Arc<RwLock<Bans>>,
+}
+
+impl From<Bans> for BanList {
+    fn from(bans: Bans) -> Self {
+        Self { path: None, bans: Arc::new(RwLock::new(bans)) }
+    }
+}
+
+impl BanList {
+    /// Loads the bans from the given JSON file.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let bans = Self::read(&path)?;
+
+        Ok(Self { path: Some(path), bans: Arc::new(RwLock::new(bans)) })
+    }
+
+    fn read(path: &PathBuf) -> Result<Bans> {
+        let contents = fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+        serde_json::from_slice(&contents).with_context(|| format!("Malformed ban file {}", path.display()))
+    }
+
+    /// Reads the file again. If it cannot be read, the current bans are kept.
+    pub fn reload(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            let bans = Self::read(path)?;
+            *self.bans.write().unwrap() = bans;
+        }
+
+        Ok(())
+    }
+
+    /// Reloads the file whenever it changes, checking every `interval`.
+    pub async fn watch(self, interval: Duration) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+
+        let modified = || fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+        let mut last_modified = modified();
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let current = modified();
+            if current == last_modified {
+                continue;
+            }
+
+            last_modified = current;
+            match self.reload() {
+                Ok(()) => info!("Reloaded bans from {}", path.display()),
+                Err(err) => error!("Unable to reload bans: {:#}", err),
+            }
+        }
+    }
+
+    pub fn allows(&self, address: IpAddr) -> bool {
+        self.bans.read().unwrap().allows_at(address, now())
+    }
+
+    /// Returns the result to refuse the given account with, if it is banned, as known by the
+    /// given version of the protocol. See [`Bans::account_ban_at`].
+    pub fn account_ban(&self, account: &str, own: Option<&Ban>, version: GruntVersion) -> Option<LoginResult> {
+        self.bans.read().unwrap()
+            .account_ban_at(account, own, now())
+            .map(|result| result.nearest(version))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Parses networks, treating addresses without a prefix length as a single host.
+fn parse_network(text: &str) -> Result<IpNet, String> {
+    text.parse::<IpNet>()
+        .or_else(|_| text.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("Invalid network {}", text))
+}
+
+mod network {
+    use ipnet::IpNet;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(network: &IpNet, serializer: S) -> Result<S::Ok, S::Error> {
+        network.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IpNet, D::Error> {
+        super::parse_network(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+mod networks {
+    use ipnet::IpNet;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(networks: &[IpNet], serializer: S) -> Result<S::Ok, S::Error> {
+        networks.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<IpNet>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|text| super::parse_network(text).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use crate::grunt::bans::{Ban, BanList, Bans};
+    use crate::grunt::protocol::{GruntVersion, LoginResult};
+
+    fn address(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    pub fn test_addresses() {
+        let bans = serde_json::from_str::<Bans>(r#"{
+            "allow": ["10.0.0.0/8", "::1"],
+            "addresses": [
+                { "network": "10.1.0.0/16" },
+                { "network": "10.2.0.1", "expires": 1000, "reason": "Flooding" }
+            ]
+        }"#).unwrap();
+
+        assert!(bans.allows_at(address("10.0.0.1"), 0));
+        assert!(bans.allows_at(address("::ffff:10.0.0.1"), 0));
+        assert!(bans.allows_at(address("::1"), 0));
+        assert!(!bans.allows_at(address("192.168.0.1"), 0));
+        assert!(!bans.allows_at(address("10.1.2.3"), 0));
+        assert!(!bans.allows_at(address("10.2.0.1"), 999));
+        assert!(bans.allows_at(address("10.2.0.1"), 1000));
+        assert!(bans.allows_at(address("10.2.0.2"), 0));
+
+        assert!(Bans::default().allows_at(address("192.168.0.1"), 0));
+        assert!(serde_json::from_str::<Bans>(r#"{ "allow": ["10.0.0.0/33"] }"#).is_err());
+    }
+
+    #[test]
+    pub fn test_accounts() {
+        let bans = serde_json::from_str::<Bans>(r#"{
+            "accounts": [
+                { "account": "cheater" },
+                { "account": "spammer", "expires": 1000 },
+                { "account": "Cheater", "expires": 1000 }
+            ]
+        }"#).unwrap();
+
+        assert_eq!(bans.account_ban_at("CHEATER", None, 0), Some(LoginResult::Banned));
+        assert_eq!(bans.account_ban_at("spammer", None, 999), Some(LoginResult::Suspended));
+        assert_eq!(bans.account_ban_at("spammer", None, 1000), None);
+        assert_eq!(bans.account_ban_at("pow", None, 0), None);
+
+        // Bans kept with accounts follow the same rules.
+        let permanent = Ban::default();
+        let temporary = Ban { expires: Some(2000), reason: None };
+        assert_eq!(bans.account_ban_at("pow", Some(&permanent), 0), Some(LoginResult::Banned));
+        assert_eq!(bans.account_ban_at("pow", Some(&temporary), 1999), Some(LoginResult::Suspended));
+        assert_eq!(bans.account_ban_at("pow", Some(&temporary), 2000), None);
+        assert_eq!(bans.account_ban_at("spammer", Some(&permanent), 0), Some(LoginResult::Banned));
+        assert_eq!(bans.account_ban_at("spammer", Some(&temporary), 1000), Some(LoginResult::Suspended));
+    }
+
+    #[test]
+    pub fn test_reload() {
+        let path = std::env::temp_dir().join(format!("pow-bans-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{ "accounts": [{ "account": "pow" }] }"#).unwrap();
+
+        let bans = BanList::load(&path).expect("The ban file should be valid");
+        let shared = bans.clone();
+        assert_eq!(shared.account_ban("pow", None, GruntVersion::V8), Some(LoginResult::Banned));
+
+        std::fs::write(&path, r#"{ "addresses": [{ "network": "127.0.0.0/8" }] }"#).unwrap();
+        bans.reload().expect("The ban file should still be valid");
+        assert_eq!(shared.account_ban("pow", None, GruntVersion::V8), None);
+        assert!(!shared.allows(address("127.0.0.1")));
+
+        std::fs::write(&path, "{").unwrap();
+        bans.reload().expect_err("The ban file should be malformed");
+        assert!(!shared.allows(address("127.0.0.1")));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt::Display;
+
+use crate::grunt::protocol::{GruntVersion, Version};
+
+/// The expansions of the game, in release order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Expansion {
+    Vanilla,
+    BurningCrusade,
+    WrathOfTheLichKing,
+    Cataclysm,
+    /// Clients built on the engine of the current game, such as Cataclysm Classic. They log
+    /// in through Battle.net and speak the modern world protocol, of which `pow` only
+    /// implements the framing and the packet encryption: it cannot talk to real modern
+    /// clients or servers yet.
+    Modern,
+}
+
+impl Display for Expansion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Vanilla => write!(f, "World of Warcraft"),
+            Self::BurningCrusade => write!(f, "The Burning Crusade"),
+            Self::WrathOfTheLichKing => write!(f, "Wrath of the Lich King"),
+            Self::Cataclysm => write!(f, "Cataclysm"),
+            Self::Modern => write!(f, "World of Warcraft Classic"),
+        }
+    }
+}
+
+/// A client build known to `pow`.
+#[derive(Debug)]
+pub struct ClientBuild {
+    /// The version the client announces in its logon challenge.
+    pub version: Version,
+    /// The name the version is commonly known by, such as `3.3.5a`.
+    pub name: &'static str,
+    /// The revision of the Grunt protocol the client speaks, if it logs in through Grunt.
+    pub protocol: Option<GruntVersion>,
+    pub expansion: Expansion,
+}
+
+const fn build(major: u8, minor: u8, patch: u8, build: u16, name: &'static str, protocol: GruntVersion, expansion: Expansion) -> ClientBuild {
+    ClientBuild {
+        version: Version { major, minor, patch, build },
+        name,
+        protocol: Some(protocol),
+        expansion,
+    }
+}
+
+/// A client that logs in through Battle.net rather than Grunt.
+const fn modern(major: u8, minor: u8, patch: u8, build: u16, name: &'static str) -> ClientBuild {
+    ClientBuild {
+        version: Version { major, minor, patch, build },
+        name,
+        protocol: None,
+        expansion: Expansion::Modern,
+    }
+}
+
+/// Every client build known to `pow`, sorted by build.
+pub const CLIENT_BUILDS: &[ClientBuild] = &[
+    build(1, 12, 1, 5875, "1.12.1", GruntVersion::V3, Expansion::Vanilla),
+    build(1, 12, 2, 6005, "1.12.2", GruntVersion::V3, Expansion::Vanilla),
+    build(1, 12, 3, 6141, "1.12.3", GruntVersion::V3, Expansion::Vanilla),
+    build(2, 4, 3, 8606, "2.4.3", GruntVersion::V8, Expansion::BurningCrusade),
+    build(3, 3, 5, 12340, "3.3.5a", GruntVersion::V8, Expansion::WrathOfTheLichKing),
+    build(4, 3, 4, 15595, "4.3.4", GruntVersion::V8, Expansion::Cataclysm),
+    modern(4, 4, 0, 54737, "4.4.0"),
+];
+
+impl ClientBuild {
+    /// Returns the known client with the given build, if any.
+    pub fn find(build: u16) -> Option<&'static ClientBuild> {
+        CLIENT_BUILDS.binary_search_by_key(&build, |client| client.version.build)
+            .ok()
+            .map(|index| &CLIENT_BUILDS[index])
+    }
+
+    /// Returns the known client with the given common name, such as `3.3.5a`, if any.
+    pub fn find_by_name(name: &str) -> Option<&'static ClientBuild> {
+        CLIENT_BUILDS.iter().find(|client| client.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the most recent known client that speaks the given protocol revision, if any.
+    pub fn latest_for(protocol: GruntVersion) -> Option<&'static ClientBuild> {
+        CLIENT_BUILDS.iter().rev().find(|client| client.protocol == Some(protocol))
+    }
+
+    /// Whether a client announcing the given version and protocol revision is this build.
+    ///
+    /// Clients that announce a known build with a different version or protocol revision
+    /// have been tampered with.
+    pub fn matches(&self, version: &Version, protocol: GruntVersion) -> bool {
+        &self.version == version && self.protocol == Some(protocol)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::builds::{CLIENT_BUILDS, ClientBuild, Expansion};
+    use crate::grunt::protocol::{GruntVersion, Version};
+
+    #[test]
+    pub fn test_catalogue_is_sorted() {
+        assert!(CLIENT_BUILDS.windows(2).all(|pair| pair[0].version < pair[1].version));
+    }
+
+    #[test]
+    pub fn test_find() {
+        let client = ClientBuild::find(12340).expect("3.3.5a should be known");
+        assert_eq!(client.name, "3.3.5a");
+        assert_eq!(client.protocol, Some(GruntVersion::V8));
+        assert_eq!(client.expansion, Expansion::WrathOfTheLichKing);
+        assert!(client.matches(&"3.3.5.12340".parse().unwrap(), GruntVersion::V8));
+        assert!(!client.matches(&"3.3.5.12340".parse().unwrap(), GruntVersion::V3));
+
+        assert_eq!(ClientBuild::find(5875).unwrap().expansion, Expansion::Vanilla);
+        assert_eq!(ClientBuild::find(8606).unwrap().expansion, Expansion::BurningCrusade);
+        assert_eq!(ClientBuild::find(15595).unwrap().version, "4.3.4.15595".parse::<Version>().unwrap());
+        assert!(ClientBuild::find(1234).is_none());
+
+        let modern = ClientBuild::find(54737).expect("4.4.0 should be known");
+        assert_eq!(modern.expansion, Expansion::Modern);
+        assert_eq!(modern.protocol, None);
+        assert!(!modern.matches(&"4.4.0.54737".parse().unwrap(), GruntVersion::V8));
+
+        assert_eq!(ClientBuild::find_by_name("3.3.5A").unwrap().version.build, 12340);
+        assert!(ClientBuild::find_by_name("3.3.5").is_none());
+
+        assert_eq!(ClientBuild::latest_for(GruntVersion::V3).unwrap().name, "1.12.3");
+        assert_eq!(ClientBuild::latest_for(GruntVersion::V8).unwrap().name, "4.3.4");
+        assert!(ClientBuild::latest_for(GruntVersion::V2).is_none());
+    }
+}
@@ -0,0 +1,618 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::grunt::accounts::{Account, AccountStore};
+use crate::grunt::bans::BanList;
+use crate::grunt::lockout::LoginThrottle;
+use crate::grunt::matrix::{MatrixCard, MatrixChallenge};
+use crate::grunt::patch::{Patch, PatchDirectory, PatchTransfer};
+use crate::grunt::pin::PinChallenge;
+use crate::grunt::protocol::{
+    GruntProtocol, GruntVersion, LoginResult, LogonChallengeRequest, LogonChallengeResponse, LogonProofRequest,
+    LogonProofResponse, Realm, RealmlistRequest, RealmlistResponse, ReconnectChallengeRequest,
+    ReconnectChallengeResponse, ReconnectProofRequest, ReconnectProofResponse, Role,
+    XferAccept, XferCancel, XferResume
+};
+use crate::grunt::session::{ReconnectChallenge, SessionKeys};
+use crate::grunt::srp::SrpServer;
+use crate::grunt::state::AuthState;
+use crate::network::server::Server;
+use crate::options::MatrixCardOptions;
+use crate::packets::{Protocol, WriteExt};
+
+/// An authentication server that does not forward anything: accounts are looked up in an
+/// [`AccountStore`] and every client is served the same realm list.
+pub struct LocalAuthServer {
+    address: String,
+    token: CancellationToken,
+    accounts: Arc<dyn AccountStore>,
+    realms: Arc<[Realm]>,
+    sessions: SessionKeys,
+    throttle: LoginThrottle,
+    bans: BanList,
+    patches: Option<Arc<PatchDirectory>>,
+    matrix_cards: Option<Arc<MatrixCardOptions>>,
+}
+
+impl LocalAuthServer {
+    /// # Arguments
+    ///
+    /// - `address`: The address to listen on.
+    /// - `accounts`: The accounts allowed to log in.
+    /// - `realms`: The realm list served to every client.
+    /// - `token`: A token that stops this server once signalled.
+    pub fn new(address: &str, accounts: Arc<dyn AccountStore>, realms: Vec<Realm>, token: CancellationToken) -> Self {
+        Self {
+            address: address.to_string(),
+            token,
+            accounts,
+            realms: realms.into(),
+            sessions: SessionKeys::default(),
+            throttle: LoginThrottle::default(),
+            bans: BanList::default(),
+            patches: None,
+            matrix_cards: None,
+        }
+    }
+
+    /// Replaces the default brute-force protection of this server.
+    pub fn with_throttle(mut self, throttle: LoginThrottle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Enforces the given bans, both on the addresses of clients and on their accounts.
+    pub fn with_bans(mut self, bans: BanList) -> Self {
+        self.bans = bans;
+        self
+    }
+
+    /// Offers the patches of the given directory to the clients they were made for, instead
+    /// of logging them in.
+    pub fn with_patches(mut self, patches: PatchDirectory) -> Self {
+        self.patches = Some(Arc::new(patches));
+        self
+    }
+
+    /// Asks every account for cells of its matrix card, along with its PIN if it has one.
+    pub fn with_matrix_cards(mut self, matrix_cards: Arc<MatrixCardOptions>) -> Self {
+        self.matrix_cards = Some(matrix_cards);
+        self
+    }
+
+    /// The session keys of the accounts that logged in, for the world servers to use.
+    pub fn sessions(&self) -> &SessionKeys {
+        &self.sessions
+    }
+}
+
+impl Server for LocalAuthServer {
+    type Protocol = LocalAuthProtocol;
+
+    fn addr(&self) -> String { self.address.clone() }
+
+    fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    fn make_protocol(&self, addr: SocketAddr) -> Self::Protocol {
+        LocalAuthProtocol {
+            address: addr.ip(),
+            version: GruntVersion::V8,
+            state: AuthState::default(),
+            accounts: self.accounts.clone(),
+            realms: self.realms.clone(),
+            sessions: self.sessions.clone(),
+            throttle: self.throttle.clone(),
+            bans: self.bans.clone(),
+            patches: self.patches.clone(),
+            matrix_cards: self.matrix_cards.clone(),
+            login: None,
+            reconnect: None,
+            offered: None,
+            transfer: None,
+        }
+    }
+
+    fn throttle(&self) -> &LoginThrottle {
+        &self.throttle
+    }
+
+    fn accepts(&self, addr: SocketAddr) -> bool {
+        self.bans.allows(addr.ip())
+    }
+}
+
+/// A login that was challenged and awaits its proof.
+struct PendingLogin {
+    account: Account,
+    srp: SrpServer,
+    pin: Option<PinChallenge>,
+    matrix: Option<(MatrixCard, MatrixChallenge)>,
+    /// The patch the client is offered once it sent its proof, if its build has one.
+    patch: Option<Patch>,
+}
+
+/// The protocol of a single connection to a [`LocalAuthServer`].
+pub struct LocalAuthProtocol {
+    address: IpAddr,
+    version: GruntVersion,
+    state: AuthState,
+    accounts: Arc<dyn AccountStore>,
+    realms: Arc<[Realm]>,
+    sessions: SessionKeys,
+    throttle: LoginThrottle,
+    bans: BanList,
+    patches: Option<Arc<PatchDirectory>>,
+    matrix_cards: Option<Arc<MatrixCardOptions>>,
+    login: Option<PendingLogin>,
+    reconnect: Option<ReconnectChallenge>,
+    /// The patch that was offered and that the client has yet to accept or decline.
+    offered: Option<Patch>,
+    transfer: Option<PatchTransfer>,
+}
+
+impl GruntProtocol for LocalAuthProtocol {
+    fn version(&self) -> GruntVersion { self.version }
+    fn set_version(&mut self, version: GruntVersion) { self.version = version; }
+    fn role(&self) -> Role { Role::Server }
+    fn auth_state(&mut self) -> Option<&mut AuthState> { Some(&mut self.state) }
+
+    async fn handle_logon_challenge_request<D>(&mut self, msg: LogonChallengeRequest, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        if let Err(result) = self.throttle.check(self.address, &msg.account_name, self.version) {
+            return self.send(dest, LogonChallengeResponse::Err(result)).await;
+        }
+
+        let account = self.accounts.get(&msg.account_name);
+        let ban = account.as_ref().and_then(|account| account.ban.as_ref());
+        if let Some(result) = self.bans.account_ban(&msg.account_name, ban, self.version) {
+            return self.send(dest, LogonChallengeResponse::Err(result)).await;
+        }
+
+        let Some(account) = account else {
+            self.throttle.record_failure(self.address, None);
+            return self.send(dest, LogonChallengeResponse::Err(LoginResult::UnknownAccount)).await;
+        };
+
+        // Accounts with a PIN are asked for it, and every account for its matrix card if
+        // cards were handed out. Clients that cannot answer cannot log in.
+        let pin = account.pin.as_ref().map(|_| PinChallenge::new());
+        let matrix = match &self.matrix_cards {
+            Some(options) => Some((options.card(&account.name)?, MatrixChallenge::new(options.challenges))),
+            None => None,
+        };
+        if (pin.is_some() || matrix.is_some()) && !self.version.has_security_flags() {
+            return self.send(dest, LogonChallengeResponse::Err(LoginResult::InvalidVersion)).await;
+        }
+
+        let patch = match &self.patches {
+            Some(patches) => patches.find(msg.version.build, msg.locale).await?,
+            None => None,
+        };
+
+        let srp = SrpServer::new(&account.name, account.salt, account.verifier);
+        let security = pin.iter().map(PinChallenge::challenge)
+            .chain(matrix.iter().map(|(card, matrix)| matrix.challenge(card)))
+            .collect();
+        let response = srp.challenge(security);
+        self.login = Some(PendingLogin { account, srp, pin, matrix, patch });
+
+        self.send(dest, response).await
+    }
+
+    async fn handle_logon_proof_request<D>(&mut self, msg: LogonProofRequest, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let Some(PendingLogin { account, srp, pin, matrix, patch }) = self.login.take() else {
+            self.state.reject();
+            return self.send(dest, LogonProofResponse::Err(LoginResult::UnknownAccount)).await;
+        };
+
+        // Like official servers, clients that have to be patched are told so before their
+        // proof is checked, and the patch is offered right away.
+        if let Some(patch) = patch {
+            info!("Offering a patch to {}", account.name);
+            self.send(dest, LogonProofResponse::Err(LoginResult::DownloadFile)).await?;
+            self.send(dest, patch.initiate()).await?;
+            self.offered = Some(patch);
+            self.state.reject();
+            return Ok(());
+        }
+
+        let session = srp.verify(&msg).ok_or(LoginResult::IncorrectPassword).and_then(|session| {
+            if let (Some(challenge), Some(expected)) = (&pin, &account.pin) {
+                challenge.verify(expected, &msg.security)?;
+            }
+            if let Some((card, challenge)) = &matrix {
+                challenge.verify(card, &session.session_key, &msg.security)?;
+            }
+
+            Ok(session)
+        });
+
+        let response = match session {
+            Ok(session) => {
+                info!("{} logged in", account.name);
+                self.throttle.record_success(&account.name);
+                self.sessions.insert(&account.name, session.session_key);
+                self.state.authenticate();
+
+                LogonProofResponse::Ok {
+                    proof: session.proof,
+                    account_flags: account.flags,
+                    hardware_survey_id: 0,
+                    unknown_flags: 0,
+                }
+            },
+            Err(result) => {
+                self.throttle.record_failure(self.address, Some(&account.name));
+                self.state.reject();
+                LogonProofResponse::Err(result)
+            },
+        };
+
+        self.send(dest, response).await
+    }
+
+    async fn handle_reconnect_challenge_request<D>(&mut self, msg: ReconnectChallengeRequest, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let account = msg.0.account_name;
+        if let Err(result) = self.throttle.check(self.address, &account, self.version) {
+            return self.send(dest, ReconnectChallengeResponse::Err(result)).await;
+        }
+
+        let ban = self.accounts.get(&account).and_then(|account| account.ban);
+        if let Some(result) = self.bans.account_ban(&account, ban.as_ref(), self.version) {
+            return self.send(dest, ReconnectChallengeResponse::Err(result)).await;
+        }
+
+        let Some(session_key) = self.sessions.get(&account) else {
+            return self.send(dest, ReconnectChallengeResponse::Err(LoginResult::UnknownAccount)).await;
+        };
+
+        let challenge = ReconnectChallenge::new(&account, session_key);
+        let response = challenge.response();
+        self.reconnect = Some(challenge);
+
+        self.send(dest, response).await
+    }
+
+    async fn handle_reconnect_proof_request<D>(&mut self, msg: ReconnectProofRequest, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let result = match self.reconnect.take() {
+            Some(challenge) if challenge.verify(&msg) => {
+                info!("{} reconnected", challenge.account());
+                self.state.authenticate();
+                LoginResult::Success
+            },
+            challenge => {
+                self.throttle.record_failure(self.address, challenge.as_ref().map(ReconnectChallenge::account));
+                self.state.reject();
+                LoginResult::IncorrectPassword
+            },
+        };
+
+        self.send(dest, ReconnectProofResponse(result)).await
+    }
+
+    async fn handle_realmlist_request<D>(&mut self, _: RealmlistRequest, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let realms = self.realms.to_vec();
+        self.send(dest, RealmlistResponse { realms }).await
+    }
+
+    async fn handle_xfer_accept<D>(&mut self, _: XferAccept, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        if let Some(patch) = self.offered.take() {
+            self.transfer = Some(patch.transfer(0).await?);
+        }
+
+        Ok(())
+    }
+
+    async fn handle_xfer_resume<D>(&mut self, msg: XferResume, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        if let Some(patch) = self.offered.take() {
+            self.transfer = Some(patch.transfer(msg.offset).await?);
+        }
+
+        Ok(())
+    }
+
+    async fn handle_xfer_cancel<D>(&mut self, _: XferCancel, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.offered = None;
+        self.transfer = None;
+        Ok(())
+    }
+
+    async fn send_queued<D>(&mut self, dest: &mut D) -> Result<bool>
+        where D: WriteExt
+    {
+        let Some(mut transfer) = self.transfer.take() else {
+            return Ok(false);
+        };
+
+        let sent = transfer.send_next(self, dest).await?;
+        if sent {
+            self.transfer = Some(transfer);
+        }
+
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use std::time::Duration;
+
+    use tokio_util::sync::CancellationToken;
+
+    use crate::grunt::accounts::{Account, AccountStore, MemoryAccountStore};
+    use crate::grunt::bans::{AccountBan, Ban, BanList, Bans};
+    use crate::grunt::local_auth::LocalAuthServer;
+    use crate::grunt::lockout::{LockoutPolicy, LoginThrottle};
+    use crate::grunt::login::{LoginError, LoginProtocol};
+    use crate::grunt::patch::PatchDirectory;
+    use crate::grunt::protocol::{GruntVersion, LoginResult, Realm, RealmFlags};
+    use crate::grunt::test::challenge_request;
+    use crate::network::{Acceptor, LocalPeer, Service};
+    use crate::network::connection::Client;
+    use crate::options::MatrixCardOptions;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    pub async fn test_local_auth() {
+        let accounts = MemoryAccountStore::default();
+        accounts.put(Account::new("pow", "secret")).unwrap();
+        accounts.put(Account::new("pin", "secret").with_pin("2468").unwrap()).unwrap();
+        accounts.put(Account { ban: Some(Ban::default()), ..Account::new("banned", "secret") }).unwrap();
+
+        let realm = Realm {
+            realm_type: 1,
+            locked: false,
+            flags: RealmFlags::NONE,
+            name: "Throwaway".to_string(),
+            address: "127.0.0.1:8085".to_string(),
+            population: 0.5,
+            characters: 0,
+            category: 1,
+            id: 1,
+            build: None,
+        };
+
+        let server = LocalAuthServer::new("127.0.0.1:0", Arc::new(accounts), vec![realm], CancellationToken::new())
+            .with_bans(BanList::from(Bans {
+                accounts: vec![AccountBan { account: "suspended".to_string(), ban: Ban { expires: Some(u64::MAX), reason: None } }],
+                ..Bans::default()
+            }));
+        let listener = server.bind().await.expect("Failed to bind");
+        let address = listener.local_addr().expect("Listener should have an address");
+        let token = server.token.clone();
+        let sessions = server.sessions().clone();
+
+        let server = tokio::spawn(async move {
+            server.listen(listener).await.expect("Server could not start listening.");
+        });
+
+        let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V3, "SECRET", true), CancellationToken::new())
+            .await
+            .expect("Unable to connect to local server");
+        let login = client.login(challenge_request("POW")).await.expect("Login should succeed");
+        assert_eq!(login.realms.len(), 1);
+        assert_eq!(login.realms[0].name, "Throwaway");
+        assert_eq!(sessions.get("pow"), Some(login.session_key));
+        client.disconnect().await.expect("Client should have disconnected");
+
+        let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V8, "secret", false), CancellationToken::new())
+            .await
+            .expect("Unable to connect to local server");
+        let err = client.login(challenge_request("nobody")).await.expect_err("Login should fail");
+        assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(LoginResult::UnknownAccount))));
+        client.disconnect().await.expect("Client should have disconnected");
+
+        let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V8, "secret", false), CancellationToken::new())
+            .await
+            .expect("Unable to connect to local server");
+        let err = client.login(challenge_request("banned")).await.expect_err("Login should fail");
+        assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(LoginResult::Banned))));
+        client.disconnect().await.expect("Client should have disconnected");
+
+        let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V8, "secret", false), CancellationToken::new())
+            .await
+            .expect("Unable to connect to local server");
+        let err = client.login(challenge_request("suspended")).await.expect_err("Login should fail");
+        assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(LoginResult::Suspended))));
+        client.disconnect().await.expect("Client should have disconnected");
+
+        for (pin, expected) in [("2468", None), ("1357", Some(LoginResult::IncorrectPassword))] {
+            let protocol = LoginProtocol::new(GruntVersion::V8, "secret", false).with_pin(pin);
+            let mut client = Client::connect(address, protocol, CancellationToken::new())
+                .await
+                .expect("Unable to connect to local server");
+            match (client.login(challenge_request("pin")).await, expected) {
+                (Ok(_), None) => (),
+                (Err(err), Some(result)) => assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(r)) if *r == result)),
+                (outcome, _) => panic!("Unexpected outcome {:?}", outcome.map(|_| ())),
+            }
+            client.disconnect().await.expect("Client should have disconnected");
+        }
+
+        token.cancel();
+        server.await.expect("Server should have stopped");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    pub async fn test_lockout() {
+        let accounts = MemoryAccountStore::default();
+        accounts.put(Account::new("pow", "secret")).unwrap();
+        accounts.put(Account::new("other", "secret")).unwrap();
+
+        let policy = |max_failures| LockoutPolicy { max_failures, window: Duration::from_secs(60), duration: Duration::from_secs(60) };
+        let server = LocalAuthServer::new("127.0.0.1:0", Arc::new(accounts), vec![], CancellationToken::new())
+            .with_throttle(LoginThrottle::new(policy(4), policy(2)));
+        let listener = server.bind().await.expect("Failed to bind");
+        let address = listener.local_addr().expect("Listener should have an address");
+        let token = server.token.clone();
+
+        let server = tokio::spawn(async move {
+            server.listen(listener).await.expect("Server could not start listening.");
+        });
+
+        // The second failure locks the account out, even with the right password. Other
+        // accounts may still log in from the same address.
+        for (account, password, version, expected) in [
+            ("pow", "wrong", GruntVersion::V8, Some(LoginResult::IncorrectPassword)),
+            ("pow", "wrong", GruntVersion::V8, Some(LoginResult::IncorrectPassword)),
+            ("pow", "secret", GruntVersion::V8, Some(LoginResult::LockedEnforced)),
+            ("pow", "secret", GruntVersion::V3, Some(LoginResult::Suspended)),
+            ("other", "secret", GruntVersion::V8, None),
+            ("nobody", "secret", GruntVersion::V8, Some(LoginResult::UnknownAccount)),
+            ("nobody", "secret", GruntVersion::V8, Some(LoginResult::UnknownAccount)),
+        ] {
+            let mut client = Client::connect(address, LoginProtocol::new(version, password, false), CancellationToken::new())
+                .await
+                .expect("Unable to connect to local server");
+            match (client.login(challenge_request(account)).await, expected) {
+                (Ok(_), None) => (),
+                (Err(err), Some(result)) => assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(r)) if *r == result)),
+                (outcome, _) => panic!("Unexpected outcome {:?}", outcome.map(|_| ())),
+            }
+            client.disconnect().await.expect("Client should have disconnected");
+        }
+
+        // The fourth failure from this address locks it out, and its connections are refused.
+        let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V8, "secret", false), CancellationToken::new())
+            .await
+            .expect("Unable to connect to local server");
+        let err = client.login(challenge_request("other")).await.expect_err("The connection should be refused");
+        assert!(err.downcast_ref::<LoginError>().is_none());
+
+        token.cancel();
+        server.await.expect("Server should have stopped");
+    }
+
+    #[tokio::test]
+    pub async fn test_matrix_card() {
+        let options = |secret: &str| MatrixCardOptions {
+            secret: secret.to_string(),
+            width: 8,
+            height: 10,
+            digits: 3,
+            challenges: 3,
+        };
+
+        let accounts = MemoryAccountStore::default();
+        accounts.put(Account::new("pow", "secret")).unwrap();
+        accounts.put(Account::new("pin", "secret").with_pin("2468").unwrap()).unwrap();
+        let server = LocalAuthServer::new("127.0.0.1:0", Arc::new(accounts), vec![], CancellationToken::new())
+            .with_matrix_cards(Arc::new(options("card secret")));
+        let listener = server.bind().await.expect("Failed to bind");
+        let address = listener.local_addr().expect("Listener should have an address");
+        let token = server.token.clone();
+
+        let server = tokio::spawn(async move {
+            server.listen(listener).await.expect("Server could not start listening.");
+        });
+
+        for (secret, expected) in [("card secret", None), ("other secret", Some(LoginResult::IncorrectPassword))] {
+            let card = options(secret).card("pow").expect("Card should be generated");
+            let protocol = LoginProtocol::new(GruntVersion::V8, "secret", false).with_matrix_card(card);
+            let mut client = Client::connect(address, protocol, CancellationToken::new())
+                .await
+                .expect("Unable to connect to local server");
+            match (client.login(challenge_request("pow")).await, expected) {
+                (Ok(_), None) => (),
+                (Err(err), Some(result)) => assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(r)) if *r == result)),
+                (outcome, _) => panic!("Unexpected outcome {:?}", outcome.map(|_| ())),
+            }
+            client.disconnect().await.expect("Client should have disconnected");
+        }
+
+        // Accounts with a PIN are asked for both.
+        let card = options("card secret").card("pin").expect("Card should be generated");
+        let protocol = LoginProtocol::new(GruntVersion::V8, "secret", false).with_pin("2468").with_matrix_card(card);
+        let mut client = Client::connect(address, protocol, CancellationToken::new())
+            .await
+            .expect("Unable to connect to local server");
+        client.login(challenge_request("pin")).await.expect("Login should succeed");
+        client.disconnect().await.expect("Client should have disconnected");
+
+        // Clients that cannot answer the card are turned away.
+        let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V2, "secret", false), CancellationToken::new())
+            .await
+            .expect("Unable to connect to local server");
+        let err = client.login(challenge_request("pow")).await.expect_err("Login should fail");
+        assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(LoginResult::InvalidVersion))));
+        client.disconnect().await.expect("Client should have disconnected");
+
+        token.cancel();
+        server.await.expect("Server should have stopped");
+    }
+
+    #[tokio::test]
+    pub async fn test_patch_offer() {
+        let directory = std::env::temp_dir().join(format!("pow-test-patches-{}", std::process::id()));
+        tokio::fs::create_dir_all(&directory).await.expect("Unable to create patch directory");
+        tokio::fs::write(directory.join("12340enUS.mpq"), [0x42; 16]).await.expect("Unable to write patch");
+
+        let accounts = MemoryAccountStore::default();
+        accounts.put(Account::new("pow", "secret")).unwrap();
+        let server = LocalAuthServer::new("127.0.0.1:0", Arc::new(accounts), vec![], CancellationToken::new())
+            .with_patches(PatchDirectory::new(&directory));
+        let listener = server.bind().await.expect("Failed to bind");
+        let address = listener.local_addr().expect("Listener should have an address");
+        let token = server.token.clone();
+
+        let server = tokio::spawn(async move {
+            server.listen(listener).await.expect("Server could not start listening.");
+        });
+
+        let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V8, "secret", false), CancellationToken::new())
+            .await
+            .expect("Unable to connect to local server");
+        let err = client.login(challenge_request("pow")).await.expect_err("Login should fail");
+        assert!(matches!(err.downcast_ref(), Some(LoginError::Rejected(LoginResult::DownloadFile))));
+        client.disconnect().await.expect("Client should have disconnected");
+
+        token.cancel();
+        server.await.expect("Server should have stopped");
+        let _ = tokio::fs::remove_dir_all(&directory).await;
+    }
+
+    #[tokio::test]
+    pub async fn test_banned_address() {
+        let bans = serde_json::from_str::<Bans>(r#"{ "addresses": [{ "network": "127.0.0.0/8" }] }"#).unwrap();
+        let server = LocalAuthServer::new("127.0.0.1:0", Arc::new(MemoryAccountStore::default()), vec![], CancellationToken::new())
+            .with_bans(BanList::from(bans));
+        let listener = server.bind().await.expect("Failed to bind");
+        let address = listener.local_addr().expect("Listener should have an address");
+        let token = server.token.clone();
+
+        let server = tokio::spawn(async move {
+            server.listen(listener).await.expect("Server could not start listening.");
+        });
+
+        let mut client = Client::connect(address, LoginProtocol::new(GruntVersion::V8, "secret", false), CancellationToken::new())
+            .await
+            .expect("Unable to connect to local server");
+        let err = client.login(challenge_request("pow")).await.expect_err("The connection should be refused");
+        assert!(err.downcast_ref::<LoginError>().is_none());
+
+        token.cancel();
+        server.await.expect("Server should have stopped");
+    }
+}
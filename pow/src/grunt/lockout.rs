@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::grunt::protocol::{GruntVersion, LoginResult};
+
+/// How many failures are tolerated before a key is locked out.
+#[derive(Clone, Copy, Debug)]
+pub struct LockoutPolicy {
+    /// The amount of failures that locks a key out.
+    pub max_failures: u32,
+    /// Failures older than this are forgotten.
+    pub window: Duration,
+    /// How long a key stays locked out.
+    pub duration: Duration,
+}
+
+#[derive(Debug)]
+struct Counter {
+    failures: u32,
+    since: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Counter {
+    fn is_locked(&self, now: Instant) -> bool {
+        self.locked_until.is_some_and(|until| now < until)
+    }
+
+    fn is_stale(&self, policy: &LockoutPolicy, now: Instant) -> bool {
+        !self.is_locked(now) && now.duration_since(self.since) >= policy.window
+    }
+}
+
+/// Failures per key, shared between all connections of a server.
+///
+/// Keys that fail [`LockoutPolicy::max_failures`] times within [`LockoutPolicy::window`] are
+/// locked out for [`LockoutPolicy::duration`]. Because counters are shared, closing the
+/// connection and trying again does not reset them.
+#[derive(Clone)]
+pub struct FailureCounter<K> {
+    policy: LockoutPolicy,
+    counters: Arc<Mutex<HashMap<K, Counter>>>,
+}
+
+impl<K: Eq + Hash> FailureCounter<K> {
+    pub fn new(policy: LockoutPolicy) -> Self {
+        Self { policy, counters: Default::default() }
+    }
+
+    /// Whether the given key is locked out at the given instant.
+    pub fn is_locked_at(&self, key: &K, now: Instant) -> bool {
+        self.counters.lock().unwrap().get(key).is_some_and(|counter| counter.is_locked(now))
+    }
+
+    /// Counts a failure for the given key at the given instant.
+    pub fn record_failure_at(&self, key: K, now: Instant) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.retain(|_, counter| !counter.is_stale(&self.policy, now));
+
+        let counter = counters.entry(key).or_insert(Counter { failures: 0, since: now, locked_until: None });
+        if counter.is_locked(now) {
+            return;
+        }
+
+        counter.failures += 1;
+        if counter.failures >= self.policy.max_failures {
+            *counter = Counter { failures: 0, since: now, locked_until: Some(now + self.policy.duration) };
+        }
+    }
+
+    /// Forgets the failures of the given key. Keys that are locked out stay locked out.
+    pub fn reset(&self, key: &K) {
+        let mut counters = self.counters.lock().unwrap();
+        if counters.get(key).is_some_and(|counter| counter.locked_until.is_none()) {
+            counters.remove(key);
+        }
+    }
+}
+
+/// Protects logins against brute-force attacks by counting failures both per address and
+/// per account.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    addresses: FailureCounter<IpAddr>,
+    accounts: FailureCounter<String>,
+}
+
+impl Default for LoginThrottle {
+    /// Locks an account out for 15 minutes after 5 failures within 5 minutes, and an address
+    /// after 20.
+    fn default() -> Self {
+        let policy = |max_failures| LockoutPolicy {
+            max_failures,
+            window: Duration::from_secs(300),
+            duration: Duration::from_secs(900),
+        };
+
+        Self::new(policy(20), policy(5))
+    }
+}
+
+impl LoginThrottle {
+    pub fn new(addresses: LockoutPolicy, accounts: LockoutPolicy) -> Self {
+        Self {
+            addresses: FailureCounter::new(addresses),
+            accounts: FailureCounter::new(accounts),
+        }
+    }
+
+    /// Checks whether a login may be attempted at the given instant.
+    ///
+    /// Locked out attempts are refused with [`LoginResult::LockedEnforced`], or the nearest
+    /// result the version of the client knows.
+    pub fn check_at(&self, address: IpAddr, account: &str, version: GruntVersion, now: Instant) -> Result<(), LoginResult> {
+        if self.addresses.is_locked_at(&address, now) || self.accounts.is_locked_at(&account.to_uppercase(), now) {
+            Err(LoginResult::LockedEnforced.nearest(version))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check(&self, address: IpAddr, account: &str, version: GruntVersion) -> Result<(), LoginResult> {
+        self.check_at(address, account, version, Instant::now())
+    }
+
+    /// Whether the given address is locked out at the given instant, whatever the account.
+    pub fn is_address_locked_at(&self, address: IpAddr, now: Instant) -> bool {
+        self.addresses.is_locked_at(&address, now)
+    }
+
+    pub fn is_address_locked(&self, address: IpAddr) -> bool {
+        self.is_address_locked_at(address, Instant::now())
+    }
+
+    /// Counts a failed attempt. `account` is `None` if the account does not exist.
+    pub fn record_failure_at(&self, address: IpAddr, account: Option<&str>, now: Instant) {
+        self.addresses.record_failure_at(address, now);
+        if let Some(account) = account {
+            self.accounts.record_failure_at(account.to_uppercase(), now);
+        }
+    }
+
+    pub fn record_failure(&self, address: IpAddr, account: Option<&str>) {
+        self.record_failure_at(address, account, Instant::now())
+    }
+
+    /// Forgets the failures of an account after a successful login.
+    ///
+    /// The failures of the address are kept, so that an attacker cannot clear them by
+    /// logging into an account of their own.
+    pub fn record_success(&self, account: &str) {
+        self.accounts.reset(&account.to_uppercase());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+
+    use crate::grunt::lockout::{LockoutPolicy, LoginThrottle};
+    use crate::grunt::protocol::{GruntVersion, LoginResult};
+
+    const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const OTHER_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    fn policy(max_failures: u32) -> LockoutPolicy {
+        LockoutPolicy {
+            max_failures,
+            window: Duration::from_secs(60),
+            duration: Duration::from_secs(600),
+        }
+    }
+
+    #[test]
+    pub fn test_account_lockout() {
+        let throttle = LoginThrottle::new(policy(100), policy(3));
+        let start = Instant::now();
+
+        throttle.record_failure_at(ADDRESS, Some("pow"), start);
+        throttle.record_failure_at(OTHER_ADDRESS, Some("POW"), start);
+        assert_eq!(throttle.check_at(ADDRESS, "pow", GruntVersion::V8, start), Ok(()));
+
+        throttle.record_failure_at(ADDRESS, Some("pow"), start);
+        assert_eq!(throttle.check_at(OTHER_ADDRESS, "pow", GruntVersion::V8, start), Err(LoginResult::LockedEnforced));
+        assert_eq!(throttle.check_at(ADDRESS, "Pow", GruntVersion::V3, start), Err(LoginResult::Suspended));
+        assert_eq!(throttle.check_at(ADDRESS, "other", GruntVersion::V8, start), Ok(()));
+
+        // A successful login does not lift a lockout.
+        throttle.record_success("pow");
+        let later = start + Duration::from_secs(599);
+        assert!(throttle.check_at(ADDRESS, "pow", GruntVersion::V8, later).is_err());
+        let later = start + Duration::from_secs(600);
+        assert_eq!(throttle.check_at(ADDRESS, "pow", GruntVersion::V8, later), Ok(()));
+    }
+
+    #[test]
+    pub fn test_address_lockout() {
+        let throttle = LoginThrottle::new(policy(2), policy(100));
+        let start = Instant::now();
+
+        throttle.record_failure_at(ADDRESS, None, start);
+        throttle.record_success("pow");
+        throttle.record_failure_at(ADDRESS, Some("other"), start);
+        assert!(throttle.check_at(ADDRESS, "pow", GruntVersion::V8, start).is_err());
+        assert_eq!(throttle.check_at(OTHER_ADDRESS, "pow", GruntVersion::V8, start), Ok(()));
+        assert!(throttle.is_address_locked_at(ADDRESS, start));
+        assert!(!throttle.is_address_locked_at(OTHER_ADDRESS, start));
+    }
+
+    #[test]
+    pub fn test_window() {
+        let throttle = LoginThrottle::new(policy(100), policy(2));
+        let start = Instant::now();
+
+        throttle.record_failure_at(ADDRESS, Some("pow"), start);
+        throttle.record_failure_at(ADDRESS, Some("pow"), start + Duration::from_secs(60));
+        assert_eq!(throttle.check_at(ADDRESS, "pow", GruntVersion::V8, start + Duration::from_secs(60)), Ok(()));
+
+        throttle.record_success("pow");
+        throttle.record_failure_at(ADDRESS, Some("pow"), start + Duration::from_secs(61));
+        assert_eq!(throttle.check_at(ADDRESS, "pow", GruntVersion::V8, start + Duration::from_secs(61)), Ok(()));
+    }
+}
@@ -0,0 +1,211 @@
+use core::fmt;
+
+use anyhow::Result;
+
+use crate::grunt::protocol::{
+    GruntProtocol, GruntVersion, LoginResult, LogonChallengeRequest, LogonChallengeResponse, LogonProofResponse,
+    Realm, RealmlistRequest, RealmlistResponse, Role, SecurityChallenge
+};
+use crate::grunt::authenticator::{Totp, answer_authenticator_challenge};
+use crate::grunt::matrix::{MatrixCard, answer_matrix_challenge};
+use crate::grunt::pin::answer_pin_challenge;
+use crate::grunt::session::SessionKey;
+use crate::grunt::srp::{SrpClient, SrpClientProof};
+use crate::network::connection::Client;
+use crate::packets::{Protocol, WriteExt};
+
+/// The reasons a login may fail, besides network errors.
+#[derive(Debug)]
+pub enum LoginError {
+    /// The server refused the login.
+    Rejected(LoginResult),
+    /// The server does not know the verifier of the account.
+    InvalidServerProof,
+    /// The server requires a second factor this client cannot provide.
+    UnsupportedSecurity(SecurityChallenge),
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rejected(result) => write!(f, "The server refused the login ({:?})", result),
+            Self::InvalidServerProof => write!(f, "The server could not prove it knows the account"),
+            Self::UnsupportedSecurity(security) => write!(f, "Unsupported second factor {:?}", security),
+        }
+    }
+}
+
+impl std::error::Error for LoginError { }
+
+/// The outcome of a successful login.
+#[derive(Debug)]
+pub struct Login {
+    pub session_key: SessionKey,
+    /// The realms the server advertised. This is empty unless the realm list was requested.
+    pub realms: Vec<Realm>,
+}
+
+enum LoginState {
+    Idle,
+    AwaitingChallenge(SrpClient),
+    AwaitingProof(SrpClientProof),
+    AwaitingRealmlist(SessionKey),
+    Done(Login),
+    Failed(LoginError),
+}
+
+/// A Grunt protocol that logs into an authentication server.
+pub struct LoginProtocol {
+    version: GruntVersion,
+    password: String,
+    pin: Option<String>,
+    matrix_card: Option<MatrixCard>,
+    authenticator: Option<Totp>,
+    fetch_realmlist: bool,
+    state: LoginState,
+}
+
+impl LoginProtocol {
+    /// # Arguments
+    ///
+    /// - `version`: The version of the protocol to use.
+    /// - `password`: The password of the account.
+    /// - `fetch_realmlist`: Whether the realm list should be requested once logged in.
+    pub fn new(version: GruntVersion, password: &str, fetch_realmlist: bool) -> Self {
+        Self {
+            version,
+            password: password.to_string(),
+            pin: None,
+            matrix_card: None,
+            authenticator: None,
+            fetch_realmlist,
+            state: LoginState::Idle,
+        }
+    }
+
+    /// Sets the PIN used to answer [`SecurityChallenge::Pin`].
+    pub fn with_pin(mut self, pin: &str) -> Self {
+        self.pin = Some(pin.to_string());
+        self
+    }
+
+    /// Sets the card used to answer [`SecurityChallenge::Matrix`].
+    pub fn with_matrix_card(mut self, card: MatrixCard) -> Self {
+        self.matrix_card = Some(card);
+        self
+    }
+
+    /// Sets the generator used to answer [`SecurityChallenge::Authenticator`].
+    pub fn with_authenticator(mut self, totp: Totp) -> Self {
+        self.authenticator = Some(totp);
+        self
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.state, LoginState::Done(_) | LoginState::Failed(_))
+    }
+}
+
+impl GruntProtocol for LoginProtocol {
+    fn version(&self) -> GruntVersion { self.version }
+    fn set_version(&mut self, version: GruntVersion) { self.version = version; }
+    fn role(&self) -> Role { Role::Client }
+
+    async fn handle_logon_challenge_response<D>(&mut self, msg: LogonChallengeResponse, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let LoginState::AwaitingChallenge(srp) = std::mem::replace(&mut self.state, LoginState::Idle) else {
+            anyhow::bail!("Unexpected logon challenge");
+        };
+
+        let security = match &msg {
+            LogonChallengeResponse::Err(result) => {
+                self.state = LoginState::Failed(LoginError::Rejected(*result));
+                return Ok(());
+            },
+            LogonChallengeResponse::Ok { security, .. } => security,
+        };
+
+        let proof = srp.respond(&msg)?;
+        let mut proofs = Vec::with_capacity(security.len());
+        for challenge in security {
+            proofs.push(match (challenge, &self.pin, &self.matrix_card, &self.authenticator) {
+                (SecurityChallenge::Pin { .. }, Some(pin), ..) => answer_pin_challenge(pin, challenge)?,
+                (SecurityChallenge::Matrix { .. }, _, Some(card), _) => answer_matrix_challenge(card, challenge, &proof.session_key)?,
+                (SecurityChallenge::Authenticator(_), .., Some(totp)) => answer_authenticator_challenge(totp, challenge)?,
+                _ => {
+                    self.state = LoginState::Failed(LoginError::UnsupportedSecurity(challenge.clone()));
+                    return Ok(());
+                },
+            });
+        }
+
+        let request = proof.request(proofs);
+        self.state = LoginState::AwaitingProof(proof);
+
+        self.send(dest, request).await
+    }
+
+    async fn handle_logon_proof_response<D>(&mut self, msg: LogonProofResponse, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let LoginState::AwaitingProof(proof) = std::mem::replace(&mut self.state, LoginState::Idle) else {
+            anyhow::bail!("Unexpected logon proof");
+        };
+
+        match msg {
+            LogonProofResponse::Ok { proof: server_proof, .. } if proof.verify(&server_proof) => {
+                if self.fetch_realmlist {
+                    self.state = LoginState::AwaitingRealmlist(proof.session_key);
+                    self.send(dest, RealmlistRequest).await
+                } else {
+                    self.state = LoginState::Done(Login { session_key: proof.session_key, realms: vec![] });
+                    Ok(())
+                }
+            },
+            LogonProofResponse::Ok { .. } => {
+                self.state = LoginState::Failed(LoginError::InvalidServerProof);
+                Ok(())
+            },
+            LogonProofResponse::Err(result) => {
+                self.state = LoginState::Failed(LoginError::Rejected(result));
+                Ok(())
+            },
+        }
+    }
+
+    async fn handle_realmlist_response<D>(&mut self, msg: RealmlistResponse, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let LoginState::AwaitingRealmlist(session_key) = std::mem::replace(&mut self.state, LoginState::Idle) else {
+            anyhow::bail!("Unexpected realm list");
+        };
+
+        self.state = LoginState::Done(Login { session_key, realms: msg.realms });
+        Ok(())
+    }
+}
+
+impl Client<LoginProtocol> {
+    /// Logs into the server this client is connected to.
+    ///
+    /// # Arguments
+    ///
+    /// - `challenge`: The challenge to send. The account name of this challenge is used
+    ///   along with the password of the [`LoginProtocol`].
+    pub async fn login(&mut self, challenge: LogonChallengeRequest) -> Result<Login> {
+        let protocol = self.protocol_mut();
+        protocol.state = LoginState::AwaitingChallenge(SrpClient::new(&challenge.account_name, &protocol.password));
+
+        self.send(challenge).await?;
+        while !self.protocol().is_finished() {
+            self.process_incoming().await?;
+        }
+
+        match std::mem::replace(&mut self.protocol_mut().state, LoginState::Idle) {
+            LoginState::Done(login) => Ok(login),
+            LoginState::Failed(err) => Err(err.into()),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use core::fmt;
+
+use anyhow::{Result, bail};
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+use crate::grunt::protocol::{LoginResult, SecurityChallenge, SecurityProof};
+use crate::grunt::session::SessionKey;
+
+type HmacSha1 = Hmac<Sha1>;
+
+/// A matrix card, a grid of numbers printed on paper and handed to the owner of an account.
+///
+/// Columns are labelled with letters and rows with numbers, starting at 1. Each cell
+/// holds `digits` digits.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MatrixCard {
+    width: u8,
+    height: u8,
+    digits: u8,
+    cells: Box<[u8]>,
+}
+
+impl MatrixCard {
+    /// Derives the card of an account from a secret only the server knows.
+    ///
+    /// The card does not need to be stored: the same secret and account always yield the
+    /// same card. Changing the secret invalidates every card handed out so far.
+    ///
+    /// # Arguments
+    ///
+    /// - `secret`: The secret of the server.
+    /// - `account`: The name of the account. This is case-insensitive.
+    /// - `width`: The amount of columns of the card.
+    /// - `height`: The amount of rows of the card.
+    /// - `digits`: The amount of digits in each cell.
+    pub fn generate(secret: &[u8], account: &str, width: u8, height: u8, digits: u8) -> Result<Self> {
+        if width == 0 || width > 26 || height == 0 || digits == 0 {
+            bail!("Invalid matrix card dimensions {}x{} with {} digits", width, height, digits);
+        }
+
+        let size = width as usize * height as usize * digits as usize;
+        let mut cells = Vec::with_capacity(size);
+        let mut counter = 0u32;
+        while cells.len() < size {
+            let block = HmacSha1::new_from_slice(secret)?
+                .chain_update(account.to_uppercase())
+                .chain_update(counter.to_le_bytes())
+                .finalize()
+                .into_bytes();
+            counter += 1;
+
+            // Bytes of 250 and above are dropped so that every digit is equally likely.
+            cells.extend(block.iter().filter(|&&b| b < 250).map(|b| b % 10).take(size - cells.len()));
+        }
+
+        Ok(Self { width, height, digits, cells: cells.into_boxed_slice() })
+    }
+
+    pub fn width(&self) -> u8 { self.width }
+    pub fn height(&self) -> u8 { self.height }
+    pub fn digits(&self) -> u8 { self.digits }
+
+    /// Returns the digits of the cell at the given column and row, both starting at 0.
+    pub fn cell(&self, x: u8, y: u8) -> &[u8] {
+        let start = (y as usize * self.width as usize + x as usize) * self.digits as usize;
+        &self.cells[start..start + self.digits as usize]
+    }
+}
+
+impl fmt::Display for MatrixCard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = self.digits as usize;
+
+        write!(f, "   ")?;
+        for x in 0..self.width {
+            write!(f, " {:>width$}", (b'A' + x) as char)?;
+        }
+        writeln!(f)?;
+
+        for y in 0..self.height {
+            write!(f, "{:>3}", y + 1)?;
+            for x in 0..self.width {
+                write!(f, " ")?;
+                for digit in self.cell(x, y) {
+                    write!(f, "{}", digit)?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Selects the cells the client is asked for.
+///
+/// Just like the PIN grid, the seed is consumed as a mixed-radix number: each step picks
+/// one of the remaining cells, so that no cell is asked twice.
+///
+/// Returns the `(column, row)` coordinates of each cell, starting at 0.
+pub fn select_cells(seed: u64, width: u8, height: u8, challenges: u8) -> Vec<(u8, u8)> {
+    let mut cells = (0..width as u64 * height as u64).collect::<Vec<_>>();
+    let mut seed = seed;
+
+    let mut coordinates = Vec::with_capacity(challenges as usize);
+    for _ in 0..challenges {
+        if cells.is_empty() {
+            break;
+        }
+
+        let remaining = cells.len() as u64;
+        let cell = cells.remove((seed % remaining) as usize);
+        seed /= remaining;
+
+        coordinates.push(((cell % width as u64) as u8, (cell / width as u64) as u8));
+    }
+
+    coordinates
+}
+
+/// Computes the proof the client sends for the given card.
+///
+/// The digits of the selected cells are fed, in order and as raw values, to an HMAC-SHA1
+/// keyed with `MD5(seed | K)`. This ties the proof to the SRP6 session.
+///
+/// # Arguments
+///
+/// - `card`: The card of the account.
+/// - `seed`: The seed sent by the server.
+/// - `challenges`: The amount of cells the server asked for.
+/// - `session_key`: The session key of the SRP6 exchange.
+pub fn matrix_proof(card: &MatrixCard, seed: u64, challenges: u8, session_key: &SessionKey) -> Result<[u8; 20]> {
+    let key = Md5::new()
+        .chain_update(seed.to_le_bytes())
+        .chain_update(session_key)
+        .finalize();
+
+    let mut hmac = HmacSha1::new_from_slice(&key)?;
+    for (x, y) in select_cells(seed, card.width, card.height, challenges) {
+        hmac.update(card.cell(x, y));
+    }
+
+    Ok(hmac.finalize().into_bytes().into())
+}
+
+/// The server side of a matrix card challenge.
+pub struct MatrixChallenge {
+    seed: u64,
+    challenges: u8,
+}
+
+impl MatrixChallenge {
+    /// Creates a challenge with a random seed.
+    ///
+    /// # Arguments
+    ///
+    /// - `challenges`: The amount of cells the client will be asked for.
+    pub fn new(challenges: u8) -> Self {
+        Self {
+            seed: rand::random(),
+            challenges,
+        }
+    }
+
+    /// Returns the challenge to send in the logon challenge response.
+    pub fn challenge(&self, card: &MatrixCard) -> SecurityChallenge {
+        SecurityChallenge::Matrix {
+            width: card.width,
+            height: card.height,
+            digits: card.digits,
+            challenges: self.challenges,
+            seed: self.seed,
+        }
+    }
+
+    /// Verifies the matrix card proof among those sent by the client against the card of
+    /// the account.
+    ///
+    /// Any failure, including a client that did not answer the challenge, is reported as
+    /// [`LoginResult::IncorrectPassword`].
+    pub fn verify(&self, card: &MatrixCard, session_key: &SessionKey, proofs: &[SecurityProof]) -> Result<(), LoginResult> {
+        let Some(proof) = proofs.iter().find_map(|proof| match proof {
+            SecurityProof::Matrix { proof } => Some(proof),
+            _ => None,
+        }) else {
+            return Err(LoginResult::IncorrectPassword);
+        };
+
+        match matrix_proof(card, self.seed, self.challenges, session_key) {
+            Ok(expected) if bool::from(expected.ct_eq(proof)) => Ok(()),
+            _ => Err(LoginResult::IncorrectPassword),
+        }
+    }
+}
+
+/// Answers a matrix card challenge. This is the client side of the exchange.
+///
+/// # Arguments
+///
+/// - `card`: The card of the account.
+/// - `challenge`: The challenge sent by the server.
+/// - `session_key`: The session key of the SRP6 exchange.
+pub fn answer_matrix_challenge(card: &MatrixCard, challenge: &SecurityChallenge, session_key: &SessionKey) -> Result<SecurityProof> {
+    let SecurityChallenge::Matrix { width, height, digits, challenges, seed } = challenge else {
+        bail!("Expected a matrix card challenge, found {:?}", challenge);
+    };
+
+    if (*width, *height, *digits) != (card.width, card.height, card.digits) {
+        bail!("The server expects a {}x{} card with {} digits", width, height, digits);
+    }
+
+    let proof = matrix_proof(card, *seed, *challenges, session_key)?;
+    Ok(SecurityProof::Matrix { proof })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::matrix::{MatrixCard, MatrixChallenge, answer_matrix_challenge, select_cells};
+    use crate::grunt::protocol::LoginResult;
+
+    #[test]
+    pub fn test_select_cells() {
+        assert_eq!(select_cells(0, 8, 10, 3), vec![(0, 0), (1, 0), (2, 0)]);
+        // 1 + 2 * 80 + 3 * 80 * 79 selects the second, third and fourth of the remaining cells.
+        assert_eq!(select_cells(1 + 2 * 80 + 3 * 80 * 79, 8, 10, 3), vec![(1, 0), (3, 0), (5, 0)]);
+        assert_eq!(select_cells(u64::MAX, 2, 2, 8).len(), 4);
+    }
+
+    #[test]
+    pub fn test_generate() {
+        let card = MatrixCard::generate(b"secret", "account", 8, 10, 3).unwrap();
+        assert_eq!(card, MatrixCard::generate(b"secret", "ACCOUNT", 8, 10, 3).unwrap());
+        assert_ne!(card, MatrixCard::generate(b"other secret", "account", 8, 10, 3).unwrap());
+        assert_ne!(card, MatrixCard::generate(b"secret", "other", 8, 10, 3).unwrap());
+        assert!(card.cells.iter().all(|&digit| digit < 10));
+
+        let display = card.to_string();
+        assert_eq!(display.lines().count(), 11);
+        assert!(display.starts_with("      A   B   C"));
+        assert!(MatrixCard::generate(b"secret", "account", 27, 10, 3).is_err());
+    }
+
+    #[test]
+    pub fn test_matrix_challenge() {
+        let card = MatrixCard::generate(b"secret", "account", 8, 10, 2).unwrap();
+        let session_key = std::array::from_fn(|i| i as u8);
+        let server = MatrixChallenge::new(3);
+
+        let proof = [answer_matrix_challenge(&card, &server.challenge(&card), &session_key).unwrap()];
+        assert_eq!(server.verify(&card, &session_key, &proof), Ok(()));
+        assert_eq!(server.verify(&card, &[0; 40], &proof), Err(LoginResult::IncorrectPassword));
+
+        let other = MatrixCard::generate(b"secret", "other", 8, 10, 2).unwrap();
+        assert_eq!(server.verify(&other, &session_key, &proof), Err(LoginResult::IncorrectPassword));
+        assert_eq!(server.verify(&card, &session_key, &[]), Err(LoginResult::IncorrectPassword));
+    }
+}
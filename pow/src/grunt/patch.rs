@@ -0,0 +1,155 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use md5::{Digest, Md5};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::info;
+
+use crate::grunt::protocol::{GruntProtocol, Locale, XferData, XferInitiate};
+use crate::packets::{Protocol, WriteExt};
+
+/// The size of the chunks a file is split into. Official servers never sent more than this.
+const CHUNK_SIZE: usize = 4096;
+
+/// A directory containing patches for clients.
+///
+/// Patches are looked up by the build and locale of the client, following the naming
+/// convention used by emulators: a client running build 5875 with the `enUS` locale is
+/// sent `5875enUS.mpq`.
+pub struct PatchDirectory {
+    path: PathBuf,
+}
+
+impl PatchDirectory {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Returns the patch for the given client, if there is any.
+    ///
+    /// # Arguments
+    ///
+    /// - `build`: The build of the client.
+    /// - `locale`: The locale of the client, as sent in the logon challenge.
+    pub async fn find(&self, build: u16, locale: Locale) -> Result<Option<Patch>> {
+        let path = self.path.join(format!("{}{}.mpq", build, locale));
+
+        if tokio::fs::try_exists(&path).await? {
+            Ok(Some(Patch::open(path).await?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// A file that can be transferred to a client.
+#[derive(Clone, Debug)]
+pub struct Patch {
+    path: PathBuf,
+    size: u64,
+    md5: [u8; 16],
+}
+
+impl Patch {
+    /// Opens the file at the given path and computes its checksum.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = File::open(&path).await?;
+
+        let mut hasher = Md5::new();
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut size = 0;
+        loop {
+            match file.read(&mut buf).await? {
+                0 => break,
+                n => {
+                    hasher.update(&buf[..n]);
+                    size += n as u64;
+                }
+            }
+        }
+
+        Ok(Self { path, size, md5: hasher.finalize().into() })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn md5(&self) -> &[u8; 16] {
+        &self.md5
+    }
+
+    /// Returns the packet that announces this patch to a client. The transfer only starts once
+    /// the client answers with either [`XferAccept`] or [`XferResume`]; a client that does not want
+    /// the patch answers with [`XferCancel`] instead.
+    ///
+    /// [`XferAccept`]: crate::grunt::protocol::XferAccept
+    /// [`XferResume`]: crate::grunt::protocol::XferResume
+    /// [`XferCancel`]: crate::grunt::protocol::XferCancel
+    pub fn initiate(&self) -> XferInitiate {
+        XferInitiate {
+            filename: "Patch".to_string(),
+            size: self.size,
+            md5: self.md5,
+        }
+    }
+
+    /// Starts sending the contents of this patch, from the given offset.
+    ///
+    /// The returned transfer is kept by the protocol of the connection, which sends its chunks
+    /// from [`Protocol::send_queued`] so that an [`XferCancel`] is heard in between them.
+    ///
+    /// # Arguments
+    ///
+    /// - `offset`: The offset to start from, as provided by the client in an [`XferResume`].
+    ///
+    /// [`Protocol::send_queued`]: crate::packets::Protocol::send_queued
+    /// [`XferResume`]: crate::grunt::protocol::XferResume
+    /// [`XferCancel`]: crate::grunt::protocol::XferCancel
+    pub async fn transfer(&self, offset: u64) -> Result<PatchTransfer> {
+        if offset > self.size {
+            bail!("Cannot resume transfer of {} at offset {} ({} bytes)", self.path.display(), offset, self.size);
+        }
+
+        info!("Transferring {} from offset {}", self.path.display(), offset);
+
+        let mut file = File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        Ok(PatchTransfer { file, buf: vec![0; CHUNK_SIZE].into_boxed_slice() })
+    }
+}
+
+/// A transfer of a [`Patch`] in progress.
+pub struct PatchTransfer {
+    file: File,
+    buf: Box<[u8]>,
+}
+
+impl PatchTransfer {
+    /// Reads the next chunk of the file, or returns `None` once all of it was sent.
+    async fn next_chunk(&mut self) -> Result<Option<XferData>> {
+        match self.file.read(&mut self.buf).await? {
+            0 => Ok(None),
+            n => Ok(Some(XferData(self.buf[..n].into()))),
+        }
+    }
+
+    /// Sends the next chunk of the file with the given protocol.
+    ///
+    /// Returns `false` once all of the file was sent, which is what [`Protocol::send_queued`]
+    /// expects.
+    ///
+    /// [`Protocol::send_queued`]: crate::packets::Protocol::send_queued
+    pub async fn send_next<P, D>(&mut self, protocol: &mut P, dest: &mut D) -> Result<bool>
+        where P: GruntProtocol, D: WriteExt
+    {
+        match self.next_chunk().await? {
+            Some(chunk) => protocol.send(dest, chunk).await.map(|_| true),
+            None => Ok(false),
+        }
+    }
+}
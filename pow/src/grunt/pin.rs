@@ -0,0 +1,160 @@
+use anyhow::{Result, bail};
+use sha1::{Digest, Sha1};
+use subtle::ConstantTimeEq;
+
+use crate::grunt::protocol::{LoginResult, SecurityChallenge, SecurityProof};
+
+/// Shuffles the digits of the PIN grid the client displays.
+///
+/// The seed is consumed as a mixed-radix number: each step picks one of the
+/// remaining digits and removes it from the pool.
+pub fn shuffle_grid(mut seed: u32) -> [u8; 10] {
+    let mut digits = (0..10).collect::<Vec<u8>>();
+    let mut grid = [0; 10];
+
+    for (cell, remaining) in grid.iter_mut().zip((1..=10).rev()) {
+        let index = (seed % remaining) as usize;
+        seed /= remaining;
+
+        *cell = digits.remove(index);
+    }
+
+    grid
+}
+
+/// Computes the hash the client sends for the given PIN.
+///
+/// The client does not hash the digits it was given, but the positions of these
+/// digits in the shuffled grid.
+///
+/// # Arguments
+///
+/// - `pin`: The digits of the PIN.
+/// - `seed`: The seed of the grid, as sent by the server.
+/// - `server_salt`: The salt sent by the server.
+/// - `client_salt`: The salt chosen by the client.
+pub fn pin_hash(pin: &str, seed: u32, server_salt: &[u8; 16], client_salt: &[u8; 16]) -> Result<[u8; 20]> {
+    if !(4..=10).contains(&pin.len()) {
+        bail!("A PIN must have between 4 and 10 digits");
+    }
+
+    let grid = shuffle_grid(seed);
+    let mut positions = Vec::with_capacity(pin.len());
+    for digit in pin.chars() {
+        let Some(digit) = digit.to_digit(10) else {
+            bail!("A PIN may only contain digits");
+        };
+
+        let position = grid.iter().position(|&cell| cell as u32 == digit).unwrap();
+        positions.push(b'0' + position as u8);
+    }
+
+    let intermediate = Sha1::new()
+        .chain_update(server_salt)
+        .chain_update(&positions)
+        .finalize();
+
+    Ok(Sha1::new()
+        .chain_update(client_salt)
+        .chain_update(intermediate)
+        .finalize()
+        .into())
+}
+
+/// The server side of a PIN challenge.
+pub struct PinChallenge {
+    seed: u32,
+    salt: [u8; 16],
+}
+
+impl Default for PinChallenge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PinChallenge {
+    /// Creates a challenge with a random grid and salt.
+    pub fn new() -> Self {
+        Self {
+            seed: rand::random(),
+            salt: rand::random(),
+        }
+    }
+
+    /// Returns the challenge to send in the logon challenge response.
+    pub fn challenge(&self) -> SecurityChallenge {
+        SecurityChallenge::Pin { seed: self.seed, salt: self.salt }
+    }
+
+    /// Verifies the PIN proof among those sent by the client against the PIN of the account.
+    ///
+    /// Any failure, including a client that did not answer the challenge, is reported as
+    /// [`LoginResult::IncorrectPassword`], which is what the client expects.
+    pub fn verify(&self, pin: &str, proofs: &[SecurityProof]) -> Result<(), LoginResult> {
+        let Some((salt, hash)) = proofs.iter().find_map(|proof| match proof {
+            SecurityProof::Pin { salt, hash } => Some((salt, hash)),
+            _ => None,
+        }) else {
+            return Err(LoginResult::IncorrectPassword);
+        };
+
+        match pin_hash(pin, self.seed, &self.salt, salt) {
+            Ok(expected) if bool::from(expected.ct_eq(hash)) => Ok(()),
+            _ => Err(LoginResult::IncorrectPassword),
+        }
+    }
+}
+
+/// Answers a PIN challenge. This is the client side of the exchange.
+///
+/// # Arguments
+///
+/// - `pin`: The digits of the PIN.
+/// - `challenge`: The challenge sent by the server.
+pub fn answer_pin_challenge(pin: &str, challenge: &SecurityChallenge) -> Result<SecurityProof> {
+    let SecurityChallenge::Pin { seed, salt: server_salt } = challenge else {
+        bail!("Expected a PIN challenge, found {:?}", challenge);
+    };
+
+    let salt = rand::random();
+    let hash = pin_hash(pin, *seed, server_salt, &salt)?;
+
+    Ok(SecurityProof::Pin { salt, hash })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::pin::{PinChallenge, answer_pin_challenge, pin_hash, shuffle_grid};
+    use crate::grunt::protocol::{LoginResult, SecurityProof};
+
+    #[test]
+    pub fn test_shuffle_grid() {
+        assert_eq!(shuffle_grid(0), [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(shuffle_grid(0x0BADF00D), [7, 1, 8, 5, 6, 9, 4, 3, 2, 0]);
+    }
+
+    #[test]
+    pub fn test_pin_hash() {
+        let server_salt = std::array::from_fn(|i| i as u8);
+        let client_salt = std::array::from_fn(|i| i as u8 + 16);
+
+        assert_eq!(pin_hash("1234", 0x0BADF00D, &server_salt, &client_salt).unwrap(), [
+            0x78, 0xD8, 0x61, 0xBF, 0x47, 0x5A, 0xD0, 0xC9, 0x33, 0x0D, 0xC6, 0x7F, 0xAA, 0xC9, 0xB7, 0xE5,
+            0xC3, 0x45, 0x74, 0xA5,
+        ]);
+        assert!(pin_hash("123", 0, &server_salt, &client_salt).is_err());
+        assert!(pin_hash("12a4", 0, &server_salt, &client_salt).is_err());
+    }
+
+    #[test]
+    pub fn test_pin_challenge() {
+        let server = PinChallenge::new();
+
+        let proof = [answer_pin_challenge("13579", &server.challenge()).unwrap()];
+        assert_eq!(server.verify("13579", &proof), Ok(()));
+        assert_eq!(server.verify("13578", &proof), Err(LoginResult::IncorrectPassword));
+        assert_eq!(server.verify("13579", &[]), Err(LoginResult::IncorrectPassword));
+        assert_eq!(server.verify("13579", &[SecurityProof::Matrix { proof: [0; 20] }]), Err(LoginResult::IncorrectPassword));
+    }
+}
@@ -1,44 +1,104 @@
 #![allow(unused_imports)]
 
+mod fourcc;
+mod grunt_version;
 mod logon_challenge;
 mod logon_proof;
 mod login_result;
 mod realmlist;
+mod reconnect;
 mod security;
+mod transfer;
 
 use std::io::Write;
+pub use fourcc::*;
+pub use grunt_version::*;
 pub use logon_challenge::*;
 pub use logon_proof::*;
 pub use login_result::*;
 pub use realmlist::*;
+pub use reconnect::*;
 pub use security::*;
+pub use transfer::*;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use pow_macro::protocol;
 
 use crate::packets::{Identifier, Payload, Protocol, ReadExt, Serializable, WriteExt};
 use crate::grunt::protocol::{self};
+use crate::grunt::state::AuthState;
 
 #[protocol(identifier = GruntIdentifier, handlers = [
-     handler(ty = LogonChallengeRequest, identifier = GruntIdentifier(0x00)),
-     handler(ty = LogonProofRequest, identifier = GruntIdentifier(0x01))
+     handler(ty = LogonChallengeRequest, identifier = GruntIdentifier(0x00, Role::Client)),
+     handler(ty = LogonChallengeResponse, identifier = GruntIdentifier(0x00, Role::Server)),
+     handler(ty = LogonProofRequest, identifier = GruntIdentifier(0x01, Role::Client)),
+     handler(ty = LogonProofResponse, identifier = GruntIdentifier(0x01, Role::Server)),
+     handler(ty = ReconnectChallengeRequest, identifier = GruntIdentifier(0x02, Role::Client)),
+     handler(ty = ReconnectChallengeResponse, identifier = GruntIdentifier(0x02, Role::Server)),
+     handler(ty = ReconnectProofRequest, identifier = GruntIdentifier(0x03, Role::Client)),
+     handler(ty = ReconnectProofResponse, identifier = GruntIdentifier(0x03, Role::Server)),
+     handler(ty = RealmlistRequest, identifier = GruntIdentifier(0x10, Role::Client)),
+     handler(ty = RealmlistResponse, identifier = GruntIdentifier(0x10, Role::Server)),
+     handler(ty = XferInitiate, identifier = GruntIdentifier(0x30, Role::Server)),
+     handler(ty = XferData, identifier = GruntIdentifier(0x31, Role::Server)),
+     handler(ty = XferAccept, identifier = GruntIdentifier(0x32, Role::Client)),
+     handler(ty = XferResume, identifier = GruntIdentifier(0x33, Role::Client)),
+     handler(ty = XferCancel, identifier = GruntIdentifier(0x34, Role::Client))
 ])]
 /// A Grunt-specific [`Protocol`]. Note that using this type as a constraint
 /// does not imply for the given `T` to be [`Protocol`].
 pub trait GruntProtocol: Send + Sync + Unpin + 'static {
-    fn version(&self) -> u8;
-    fn set_version(&mut self, version: u8);
+    fn version(&self) -> GruntVersion;
+    fn set_version(&mut self, version: GruntVersion);
+
+    /// The side of the connection this protocol speaks for.
+    fn role(&self) -> Role;
+
+    /// The authentication state of the connection. Servers have every command of their
+    /// client checked against it before it is dispatched, and must return theirs; clients
+    /// have nothing to check. See [`AuthState`].
+    fn auth_state(&mut self) -> Option<&mut AuthState> { None }
+}
+
+/// Identifies which end of a Grunt connection sent a packet.
+///
+/// Requests and responses share the same command byte on the wire; the role
+/// of the local protocol is what tells them apart.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+impl Role {
+    /// Returns the role of the other end of the connection.
+    pub fn peer(self) -> Self {
+        match self {
+            Role::Client => Role::Server,
+            Role::Server => Role::Client,
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct GruntIdentifier(/* command */ u8);
+pub struct GruntIdentifier(/* command */ u8, /* sender */ Role);
 
 impl<Protocol: GruntProtocol> Identifier<Protocol> for GruntIdentifier {
-    fn recv<S>(source: &mut S, _: &mut Protocol) -> impl Future<Output = Result<Self>> + Send
+    fn recv<S>(source: &mut S, protocol: &mut Protocol) -> impl Future<Output = Result<Self>> + Send
         where S: ReadExt
     {
+        let sender = protocol.role().peer();
         async move {
-            Ok(GruntIdentifier(source.read_u8().await?))
+            let command = source.read_u8().await?;
+            if sender == Role::Client {
+                let Some(state) = protocol.auth_state() else {
+                    bail!("Servers must keep an authentication state");
+                };
+
+                state.advance(command)?;
+            }
+
+            Ok(GruntIdentifier(command, sender))
         }
     }
 
@@ -0,0 +1,240 @@
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::packets::{ReadExt, Serializable, WriteExt};
+use crate::grunt::protocol::GruntProtocol;
+
+/// A four-character code, such as `WoW` or `enUS`.
+///
+/// The characters are packed as a big-endian integer, right-aligned, which is then sent in
+/// little-endian order: `x86` is `0x00783836`, and appears as `68x\0` on the wire.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FourCC(pub u32);
+
+impl FourCC {
+    /// Packs the given characters. Unlike [`FromStr`], this does not validate them.
+    pub const fn from_chars(chars: &[u8]) -> Self {
+        let mut value = 0;
+        let mut i = 0;
+        while i < chars.len() {
+            value = (value << 8) | chars[i] as u32;
+            i += 1;
+        }
+
+        Self(value)
+    }
+
+    /// Returns the characters of this code, without the leading padding.
+    fn chars(self) -> impl Iterator<Item = u8> {
+        self.0.to_be_bytes().into_iter().skip_while(|&b| b == 0)
+    }
+}
+
+impl Display for FourCC {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0 != 0 && self.chars().all(|c| c.is_ascii_graphic()) {
+            self.chars().try_for_each(|c| write!(f, "{}", c as char))
+        } else {
+            write!(f, "{:#010X}", self.0)
+        }
+    }
+}
+
+impl Debug for FourCC {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &self)
+    }
+}
+
+impl FromStr for FourCC {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if value.is_empty() || value.len() > 4 || !value.bytes().all(|c| c.is_ascii_graphic()) {
+            bail!("'{}' is not a valid four-character code", value);
+        }
+
+        Ok(Self::from_chars(value.as_bytes()))
+    }
+}
+
+impl<P: GruntProtocol> Serializable<P> for FourCC {
+    async fn recv<S>(source: &mut S, _: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        Ok(Self(source.read_u32_le().await?))
+    }
+
+    fn send<D>(self, dest: &mut D, _: &mut P) -> impl Future<Output = Result<()>>
+        where D: WriteExt
+    {
+        dest.write_u32_le(self.0)
+    }
+}
+
+/// Declares an enumeration of known four-character codes, with a fallback for unknown ones.
+macro_rules! fourcc_enum {
+    ($(#[$meta:meta])* $name:ident { $($(#[$variant_meta:meta])* $variant:ident = $code:literal),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)+
+            Unknown(FourCC),
+        }
+
+        impl From<FourCC> for $name {
+            fn from(value: FourCC) -> Self {
+                $(
+                    if value == const { FourCC::from_chars($code.as_bytes()) } {
+                        return Self::$variant;
+                    }
+                )+
+                Self::Unknown(value)
+            }
+        }
+
+        impl From<$name> for FourCC {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => const { FourCC::from_chars($code.as_bytes()) },)+
+                    $name::Unknown(value) => value,
+                }
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", FourCC::from(*self))
+            }
+        }
+
+        impl Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", &self)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = anyhow::Error;
+
+            fn from_str(value: &str) -> Result<Self> {
+                Ok(value.parse::<FourCC>()?.into())
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer)?
+                    .parse()
+                    .map_err(serde::de::Error::custom)
+            }
+        }
+
+        impl<P: GruntProtocol> Serializable<P> for $name {
+            async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+                where S: ReadExt
+            {
+                Ok(FourCC::recv(source, protocol).await?.into())
+            }
+
+            fn send<D>(self, dest: &mut D, protocol: &mut P) -> impl Future<Output = Result<()>>
+                where D: WriteExt
+            {
+                FourCC::from(self).send(dest, protocol)
+            }
+        }
+    };
+}
+
+fourcc_enum!(
+    /// The game a client runs.
+    Game {
+        WorldOfWarcraft = "WoW",
+    }
+);
+
+fourcc_enum!(
+    /// The processor architecture a client runs on.
+    Platform {
+        X86 = "x86",
+        X64 = "x64",
+        PowerPc = "PPC",
+    }
+);
+
+fourcc_enum!(
+    /// The operating system a client runs on.
+    Os {
+        Windows = "Win",
+        Mac = "OSX",
+    }
+);
+
+fourcc_enum!(
+    /// The language of a client.
+    Locale {
+        EnUs = "enUS",
+        EnGb = "enGB",
+        EnCn = "enCN",
+        EnTw = "enTW",
+        DeDe = "deDE",
+        EsEs = "esES",
+        EsMx = "esMX",
+        FrFr = "frFR",
+        ItIt = "itIT",
+        KoKr = "koKR",
+        PtBr = "ptBR",
+        PtPt = "ptPT",
+        RuRu = "ruRU",
+        ZhCn = "zhCN",
+        ZhTw = "zhTW",
+    }
+);
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::protocol::{FourCC, Game, Locale, Os, Platform};
+
+    #[test]
+    pub fn test_fourcc() {
+        assert_eq!("WoW".parse::<FourCC>().unwrap(), FourCC(0x00576F57));
+        assert_eq!("x86".parse::<FourCC>().unwrap(), FourCC(0x00783836));
+        assert_eq!("enUS".parse::<FourCC>().unwrap(), FourCC(0x656E5553));
+        assert_eq!(FourCC(0x4F5358).to_string(), "OSX");
+        assert_eq!(FourCC(0).to_string(), "0x00000000");
+        assert_eq!(FourCC(0x0A).to_string(), "0x0000000A");
+
+        assert!("".parse::<FourCC>().is_err());
+        assert!("enUS2".parse::<FourCC>().is_err());
+        assert!("e US".parse::<FourCC>().is_err());
+    }
+
+    #[test]
+    pub fn test_known_codes() {
+        assert_eq!(Game::from(FourCC(0x00576F57)), Game::WorldOfWarcraft);
+        assert_eq!(Platform::from(FourCC(0x00783836)), Platform::X86);
+        assert_eq!(Os::from(FourCC(0x0057696E)), Os::Windows);
+        assert_eq!("frFR".parse::<Locale>().unwrap(), Locale::FrFr);
+        assert_eq!(Locale::EnUs.to_string(), "enUS");
+
+        let unknown = "xxXX".parse::<Locale>().unwrap();
+        assert_eq!(unknown, Locale::Unknown(FourCC(0x78785858)));
+        assert_eq!(unknown.to_string(), "xxXX");
+        assert_eq!(FourCC::from(unknown), FourCC(0x78785858));
+    }
+
+    #[test]
+    pub fn test_serde() {
+        assert_eq!(serde_json::to_string(&Locale::DeDe).unwrap(), "\"deDE\"");
+        assert_eq!(serde_json::from_str::<Locale>("\"koKR\"").unwrap(), Locale::KoKr);
+        assert!(serde_json::from_str::<Locale>("\"toolong\"").is_err());
+    }
+}
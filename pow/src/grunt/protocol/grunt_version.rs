@@ -0,0 +1,112 @@
+use std::fmt::Display;
+
+use anyhow::{Result, bail};
+use crate::packets::{ReadExt, Serializable, WriteExt};
+use crate::grunt::protocol::GruntProtocol;
+
+/// A revision of the Grunt protocol, as announced by the client in its logon challenge.
+///
+/// Every known revision is listed here; a client announcing any other revision is turned
+/// away when its challenge is parsed, so that packets never have to deal with it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum GruntVersion {
+    /// Vanilla clients before 1.12.
+    V2 = 2,
+    /// Vanilla clients from 1.12 onwards.
+    V3 = 3,
+    V5 = 5,
+    V6 = 6,
+    V7 = 7,
+    /// The Burning Crusade clients from 2.4.3 onwards, and later expansions.
+    V8 = 8,
+}
+
+impl GruntVersion {
+    pub const ALL: [GruntVersion; 6] = [Self::V2, Self::V3, Self::V5, Self::V6, Self::V7, Self::V8];
+
+    /// Whether logon challenges and proofs carry a second factor (PIN, matrix card, authenticator).
+    pub fn has_security_flags(self) -> bool {
+        self != Self::V2
+    }
+
+    /// Whether [`LoginResult::LockedEnforced`](crate::grunt::protocol::LoginResult::LockedEnforced) exists.
+    pub fn supports_locked_enforced(self) -> bool {
+        self == Self::V8
+    }
+
+    /// Whether successful logon proofs carry account flags.
+    pub fn has_account_flags(self) -> bool {
+        self == Self::V8
+    }
+
+    /// Whether logon and reconnect proof responses end with two bytes of flags.
+    pub fn has_proof_flags(self) -> bool {
+        self >= Self::V5
+    }
+
+    /// Whether realms carry a lock and an optional build, and are counted with two bytes.
+    pub fn has_extended_realmlist(self) -> bool {
+        self >= Self::V5
+    }
+}
+
+impl TryFrom<u8> for GruntVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        Ok(match value {
+            2 => Self::V2,
+            3 => Self::V3,
+            5 => Self::V5,
+            6 => Self::V6,
+            7 => Self::V7,
+            8 => Self::V8,
+            _ => bail!("Unsupported Grunt protocol version {}", value),
+        })
+    }
+}
+
+impl From<GruntVersion> for u8 {
+    fn from(value: GruntVersion) -> Self {
+        value as u8
+    }
+}
+
+impl Display for GruntVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", *self as u8)
+    }
+}
+
+impl<P: GruntProtocol> Serializable<P> for GruntVersion {
+    async fn recv<S>(source: &mut S, _: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        Self::try_from(source.read_u8::<u8>().await?)
+    }
+
+    fn send<D>(self, dest: &mut D, _: &mut P) -> impl Future<Output = Result<()>>
+        where D: WriteExt
+    {
+        dest.write_u8(self as u8)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::protocol::GruntVersion;
+
+    #[test]
+    pub fn test_conversions() {
+        for version in GruntVersion::ALL {
+            assert_eq!(GruntVersion::try_from(u8::from(version)).unwrap(), version);
+        }
+
+        for value in [0, 1, 4, 9, 0xFF] {
+            assert!(GruntVersion::try_from(value).is_err());
+        }
+
+        assert_eq!(GruntVersion::V8.to_string(), "8");
+        assert!(GruntVersion::V3 < GruntVersion::V5);
+    }
+}
@@ -3,10 +3,10 @@
 use pow_macro::EnumKind;
 
 use anyhow::Result;
-use crate::grunt::protocol::GruntProtocol;
+use crate::grunt::protocol::{GruntProtocol, GruntVersion};
 use crate::packets::{ReadExt, Serializable, WriteExt};
 
-#[derive(PartialEq, PartialOrd, EnumKind, Debug)]
+#[derive(Clone, Copy, PartialEq, PartialOrd, EnumKind, Debug)]
 pub enum LoginResult {
     Success,
     UnknownFailure(u8),
@@ -24,58 +24,126 @@ pub enum LoginResult {
     SuccessSurvey,
     ParentalControl,
     LockedEnforced,
+    /// A code the protocol version of the peer does not define.
+    Unknown(u8),
+}
+
+/// The code of each result, along with the first protocol revision that defines it.
+const CODES: &[(LoginResult, u8, GruntVersion)] = &[
+    (LoginResult::Success, 0x00, GruntVersion::V2),
+    (LoginResult::Banned, 0x03, GruntVersion::V2),
+    (LoginResult::UnknownAccount, 0x04, GruntVersion::V2),
+    (LoginResult::IncorrectPassword, 0x05, GruntVersion::V2),
+    (LoginResult::AlreadyOnline, 0x06, GruntVersion::V2),
+    (LoginResult::NoGameTime, 0x07, GruntVersion::V2),
+    (LoginResult::DatabaseBusy, 0x08, GruntVersion::V2),
+    (LoginResult::InvalidVersion, 0x09, GruntVersion::V2),
+    (LoginResult::DownloadFile, 0x0A, GruntVersion::V2),
+    (LoginResult::InvalidServer, 0x0B, GruntVersion::V2),
+    (LoginResult::Suspended, 0x0C, GruntVersion::V2),
+    (LoginResult::NoAccess, 0x0D, GruntVersion::V2),
+    (LoginResult::SuccessSurvey, 0x0E, GruntVersion::V2),
+    (LoginResult::ParentalControl, 0x0F, GruntVersion::V2),
+    (LoginResult::LockedEnforced, 0x10, GruntVersion::V8),
+];
+
+impl LoginResult {
+    /// Decodes a result sent by a peer speaking the given version.
+    ///
+    /// Codes the version does not define are kept as [`LoginResult::Unknown`].
+    pub fn from_code(code: u8, version: GruntVersion) -> Self {
+        match code {
+            0x01 | 0x02 => Self::UnknownFailure(code),
+            _ => CODES.iter()
+                .find(|(_, value, since)| *value == code && *since <= version)
+                .map_or(Self::Unknown(code), |(result, _, _)| *result),
+        }
+    }
+
+    /// Whether peers speaking the given version know this result.
+    pub fn is_supported(self, version: GruntVersion) -> bool {
+        match self {
+            Self::UnknownFailure(_) | Self::Unknown(_) => true,
+            _ => CODES.iter().any(|(result, _, since)| *result == self && *since <= version),
+        }
+    }
+
+    /// Maps this result to the closest one peers speaking the given version understand.
+    pub fn nearest(self, version: GruntVersion) -> Self {
+        match self {
+            _ if self.is_supported(version) => self,
+            Self::LockedEnforced => Self::Suspended,
+            _ => Self::UnknownFailure(0x01),
+        }
+    }
+
+    /// Encodes this result for a peer speaking the given version, mapping it to its nearest
+    /// equivalent if the version does not define it.
+    pub fn code(self, version: GruntVersion) -> u8 {
+        match self.nearest(version) {
+            Self::UnknownFailure(code) | Self::Unknown(code) => code,
+            result => CODES.iter()
+                .find(|(value, _, _)| *value == result)
+                .map_or(0x01, |(_, code, _)| *code),
+        }
+    }
 }
 
 impl<P: GruntProtocol> Serializable<P> for LoginResult {
     async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
         where S: ReadExt
     {
-        assert!(matches!(protocol.version(), 2..=3 | 5..=8));
-
-        let value = source.read_u8().await?;
-
-        Ok(match value {
-            0x00 => LoginResult::Success,
-            0x01 | 0x02 => LoginResult::UnknownFailure(value),
-            0x03 => LoginResult::Banned,
-            0x04 => LoginResult::UnknownAccount,
-            0x05 => LoginResult::IncorrectPassword,
-            0x06 => LoginResult::AlreadyOnline,
-            0x07 => LoginResult::NoGameTime,
-            0x08 => LoginResult::DatabaseBusy,
-            0x09 => LoginResult::InvalidVersion,
-            0x0A => LoginResult::DownloadFile,
-            0x0B => LoginResult::InvalidServer,
-            0x0C => LoginResult::Suspended,
-            0x0D => LoginResult::NoAccess,
-            0x0E => LoginResult::SuccessSurvey,
-            0x0F => LoginResult::ParentalControl,
-            0x10 if protocol.version() == 8 => LoginResult::LockedEnforced,
-            _ => panic!("Unknown login result {}", value)
-        })
+        Ok(Self::from_code(source.read_u8().await?, protocol.version()))
     }
 
     fn send<D>(self, dest: &mut D, protocol: &mut P) -> impl Future<Output = Result<()>>
         where D: WriteExt
     {
-        match self {
-            LoginResult::Success => dest.write_u8(0x00),
-            LoginResult::UnknownFailure(value) => dest.write_u8(value),
-            LoginResult::Banned => dest.write_u8(0x03),
-            LoginResult::UnknownAccount => dest.write_u8(0x04),
-            LoginResult::IncorrectPassword => dest.write_u8(0x05),
-            LoginResult::AlreadyOnline => dest.write_u8(0x06),
-            LoginResult::NoGameTime => dest.write_u8(0x07),
-            LoginResult::DatabaseBusy => dest.write_u8(0x08),
-            LoginResult::InvalidVersion => dest.write_u8(0x09),
-            LoginResult::DownloadFile => dest.write_u8(0x0A),
-            LoginResult::InvalidServer => dest.write_u8(0x0B),
-            LoginResult::Suspended => dest.write_u8(0x0C),
-            LoginResult::NoAccess => dest.write_u8(0x0D),
-            LoginResult::SuccessSurvey => dest.write_u8(0x0E),
-            LoginResult::ParentalControl => dest.write_u8(0x0F),
-            LoginResult::LockedEnforced if protocol.version() == 8 => dest.write_u8(0x10),
-            _ => panic!("Unknown login result {:?}", self)
+        dest.write_u8(self.code(protocol.version()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::login::LoginProtocol;
+    use crate::grunt::protocol::{GruntVersion, LoginResult};
+    use crate::packets::Serializable;
+
+    #[test]
+    pub fn test_codes() {
+        for version in GruntVersion::ALL {
+            for code in 0..=0xFF {
+                assert_eq!(LoginResult::from_code(code, version).code(version), code);
+            }
+        }
+
+        assert_eq!(LoginResult::from_code(0x10, GruntVersion::V8), LoginResult::LockedEnforced);
+        assert_eq!(LoginResult::from_code(0x10, GruntVersion::V3), LoginResult::Unknown(0x10));
+        assert_eq!(LoginResult::from_code(0x42, GruntVersion::V8), LoginResult::Unknown(0x42));
+    }
+
+    #[test]
+    pub fn test_nearest() {
+        assert_eq!(LoginResult::LockedEnforced.nearest(GruntVersion::V8), LoginResult::LockedEnforced);
+        assert_eq!(LoginResult::LockedEnforced.nearest(GruntVersion::V3), LoginResult::Suspended);
+        assert_eq!(LoginResult::LockedEnforced.code(GruntVersion::V2), 0x0C);
+        assert_eq!(LoginResult::Banned.nearest(GruntVersion::V2), LoginResult::Banned);
+        assert_eq!(LoginResult::Unknown(0x42).nearest(GruntVersion::V2), LoginResult::Unknown(0x42));
+    }
+
+    #[tokio::test]
+    pub async fn test_round_trip() {
+        let mut protocol = LoginProtocol::new(GruntVersion::V3, "", false);
+
+        let mut buffer = Vec::new();
+        for result in [LoginResult::Unknown(0x42), LoginResult::LockedEnforced, LoginResult::Banned] {
+            result.send(&mut buffer, &mut protocol).await.expect("Result couldn't be sent");
         }
+        assert_eq!(buffer, [0x42, 0x0C, 0x03]);
+
+        let mut source = &buffer[..];
+        assert_eq!(LoginResult::recv(&mut source, &mut protocol).await.unwrap(), LoginResult::Unknown(0x42));
+        assert_eq!(LoginResult::recv(&mut source, &mut protocol).await.unwrap(), LoginResult::Suspended);
+        assert_eq!(LoginResult::recv(&mut source, &mut protocol).await.unwrap(), LoginResult::Banned);
     }
 }
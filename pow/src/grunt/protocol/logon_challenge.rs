@@ -1,26 +1,29 @@
 #![allow(dead_code)]
 
-use std::{fmt::{Debug, Display}, net::Ipv4Addr};
+use std::{fmt::{Debug, Display}, net::Ipv4Addr, str::FromStr};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
 use pow_macro::EnumKind;
 use tracing::info;
 use crate::packets::{Payload, ReadExt, Serializable, WriteExt};
+use crate::grunt::builds::ClientBuild;
 
-use crate::grunt::protocol::{GruntIdentifier, GruntProtocol, LoginResult, SecurityChallenge};
+use crate::grunt::protocol::{Game, GruntIdentifier, GruntProtocol, GruntVersion, Locale, LoginResult, Os, Platform, Role, SecurityChallenge};
 
 #[derive(Debug)]
 pub struct LogonChallengeRequest {
-    pub game: u32,
+    pub game: Game,
     pub version: Version,
-    pub platform: u32,
-    pub os: u32,
-    pub locale: u32,
+    pub platform: Platform,
+    pub os: Os,
+    pub locale: Locale,
     pub timezone: i32,
     pub address: Ipv4Addr,
     pub account_name: String
 }
 
+/// The version of a client. Versions are ordered by their components, from major to build.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Version {
     pub major: u8,
     pub minor: u8,
@@ -29,27 +32,32 @@ pub struct Version {
 }
 
 impl Version {
-    pub fn parse(value: &str) -> Self {
-        let mut itr = value.split('.');
-        let major = itr.next()
-            .map(|v| u8::from_str_radix(v, 10).ok())
-            .flatten()
-            .expect("major");
-        let minor = itr.next()
-            .map(|v| u8::from_str_radix(v, 10).ok())
-            .flatten()
-            .expect("minor");
-        let patch = itr.next()
-            .map(|v| u8::from_str_radix(v, 10).ok())
-            .flatten()
-            .expect("patch");
-        let build = itr.next()
-            .map(|v| u16::from_str_radix(v, 10).ok())
-            .flatten()
-            .expect("build");
-        assert!(itr.next().is_none());
-
-        Self { major, minor, patch, build }
+    /// Returns the entry of the build catalogue for this version, if the build is known.
+    pub fn client_build(&self) -> Option<&'static ClientBuild> {
+        ClientBuild::find(self.build)
+    }
+}
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+
+    /// Parses a version of the form `major.minor.patch.build`, such as `3.3.5.12340`.
+    fn from_str(value: &str) -> Result<Self> {
+        let components = value.split('.').collect::<Vec<_>>();
+        let [major, minor, patch, build] = components[..] else {
+            bail!("'{}' is not a version of the form major.minor.patch.build", value);
+        };
+
+        let component = |name: &str, component: &str| component.parse::<u8>()
+            .map_err(|err| anyhow!("Invalid {} version '{}' in '{}': {}", name, component, value, err));
+
+        Ok(Self {
+            major: component("major", major)?,
+            minor: component("minor", minor)?,
+            patch: component("patch", patch)?,
+            build: build.parse()
+                .map_err(|err| anyhow!("Invalid build '{}' in '{}': {}", build, value, err))?,
+        })
     }
 }
 
@@ -69,34 +77,38 @@ impl<P: GruntProtocol> Payload<P> for LogonChallengeRequest {
     type Identifier = GruntIdentifier;
 
     fn identifier(&self) -> GruntIdentifier {
-        GruntIdentifier(0x00)
+        GruntIdentifier(0x00, Role::Client)
     }
 
     async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
         where S: ReadExt
     {
-        protocol.set_version(source.read_u8().await?);
+        let version = GruntVersion::recv(source, protocol).await?;
+        protocol.set_version(version);
 
-        let size: usize = source.read_u8().await?;
+        let size: usize = source.read_u16_le().await?;
         let mut source = source.take(size);
 
-        let game = source.read_u32_le().await?;
+        let game = Game::recv(&mut source, protocol).await?;
 
         let version: [u8; 3] = source.read_exact_slice().await?;
         let build = source.read_u16_le().await?;
 
-        let platform = source.read_u32_le().await?;
-        let os = source.read_u32_le().await?;
-        let locale = source.read_u32_le().await?;
+        let platform = Platform::recv(&mut source, protocol).await?;
+        let os = Os::recv(&mut source, protocol).await?;
+        let locale = Locale::recv(&mut source, protocol).await?;
         let timezone = source.read_i32_le().await?;
         let address = source.read_u32_be::<Ipv4Addr>().await?;
 
         let account_name = {
-            let length = source.read_u8::<usize>().await?.into();
+            let length = source.read_u8::<usize>().await?;
             source.read_string(length).await?
         };
 
-        assert_eq!(size, 4 + 3 + 2 + 4 * 5 + 1 + account_name.len());
+        let expected = 4 + 3 + 2 + 4 * 5 + 1 + account_name.len();
+        if size != expected {
+            bail!("Logon challenge announced {} bytes but carried {}", size, expected);
+        }
 
         Ok(Self {
             game,
@@ -118,19 +130,19 @@ impl<P: GruntProtocol> Payload<P> for LogonChallengeRequest {
     async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
         where D: WriteExt
     {
-        dest.write_u8(protocol.version()).await?;
+        protocol.version().send(dest, protocol).await?;
 
         let size = 4 + 1 + 1 + 1 + 2 + 4 + 4 + 4 + 4 + 4 + 1 + self.account_name.len();
-        dest.write_u8(size as u8).await?;
+        dest.write_u16_le(size as u16).await?;
 
-        dest.write_u32_le(self.game).await?;
+        self.game.send(dest, protocol).await?;
         dest.write_u8(self.version.major).await?;
         dest.write_u8(self.version.minor).await?;
         dest.write_u8(self.version.patch).await?;
         dest.write_u16_le(self.version.build).await?;
-        dest.write_u32_le(self.platform).await?;
-        dest.write_u32_le(self.os).await?;
-        dest.write_u32_le(self.locale).await?;
+        self.platform.send(dest, protocol).await?;
+        self.os.send(dest, protocol).await?;
+        self.locale.send(dest, protocol).await?;
         dest.write_i32_le(self.timezone).await?;
         dest.write_slice(&self.address.octets()).await?;
 
@@ -149,7 +161,7 @@ pub enum LogonChallengeResponse {
         large_safe_prime: Box<[u8]>,
         salt: [u8; 32],
         crc: [u8; 16],
-        security: SecurityChallenge,
+        security: Vec<SecurityChallenge>,
     },
     Err(LoginResult)
 }
@@ -158,12 +170,14 @@ impl<P: GruntProtocol> Payload<P> for LogonChallengeResponse {
     type Identifier = GruntIdentifier;
     
     fn identifier(&self) -> Self::Identifier {
-        GruntIdentifier(0x00)
+        GruntIdentifier(0x00, Role::Server)
     }
 
     async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
         where S: ReadExt
     {
+        let _ = source.read_u8::<u8>().await?; // See send().
+
         let login_result = LoginResult::recv(source, protocol).await?;
         if login_result == LoginResult::Success {
             let public_key = source.read_exact_slice().await?;
@@ -180,7 +194,7 @@ impl<P: GruntProtocol> Payload<P> for LogonChallengeResponse {
             let salt = source.read_exact_slice().await?;
             let crc = source.read_exact_slice().await?;
 
-            let security = SecurityChallenge::recv(source, protocol).await?;
+            let security = Vec::<SecurityChallenge>::recv(source, protocol).await?;
 
             Ok(Self::Ok {
                 public_key,
@@ -202,7 +216,7 @@ impl<P: GruntProtocol> Payload<P> for LogonChallengeResponse {
 
         match self {
             LogonChallengeResponse::Ok { public_key, generator, large_safe_prime, salt, crc, security } => {
-                dest.write_u8(0).await?; // LoginResult::Success
+                LoginResult::Success.send(dest, protocol).await?;
                 dest.write_slice(&public_key).await?;
                 
                 dest.write_u8(generator.len() as u8).await?;
@@ -217,8 +231,57 @@ impl<P: GruntProtocol> Payload<P> for LogonChallengeResponse {
                 security.send(dest, protocol).await
             },
             LogonChallengeResponse::Err(login_result) => {
-                dest.write_u8(login_result.identifier() as u8).await
+                login_result.send(dest, protocol).await
             },
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::login::LoginProtocol;
+    use crate::grunt::protocol::{GruntVersion, LogonChallengeRequest, Version};
+    use crate::grunt::test::challenge_request;
+    use crate::packets::Payload;
+
+    #[tokio::test]
+    pub async fn test_challenge_request_size() {
+        let mut protocol = LoginProtocol::new(GruntVersion::V8, "secret", false);
+
+        // The size is a 16-bit integer following the protocol version.
+        let mut buffer = Vec::new();
+        Payload::send(challenge_request("POW"), &mut buffer, &mut protocol).await.unwrap();
+        assert_eq!(buffer[..3], [0x08, 30 + 3, 0x00]);
+        assert_eq!(buffer.len(), 3 + 30 + 3);
+
+        let request = <LogonChallengeRequest as Payload<_>>::recv(&mut &buffer[..], &mut protocol).await.unwrap();
+        assert_eq!(request.account_name, "POW");
+
+        buffer[1] += 1;
+        buffer.push(0);
+        let err = <LogonChallengeRequest as Payload<_>>::recv(&mut &buffer[..], &mut protocol).await.unwrap_err();
+        assert_eq!(err.to_string(), "Logon challenge announced 34 bytes but carried 33");
+    }
+
+    #[test]
+    pub fn test_version_from_str() {
+        let version = "3.3.5.12340".parse::<Version>().unwrap();
+        assert_eq!(version, Version { major: 3, minor: 3, patch: 5, build: 12340 });
+        assert_eq!(version.to_string(), "3.3.5.12340");
+        assert_eq!(version.client_build().map(|client| client.name), Some("3.3.5a"));
+
+        assert!("3.3.5".parse::<Version>().is_err());
+        assert!("3.3.5.12340.1".parse::<Version>().is_err());
+        assert!("3.3.5a.12340".parse::<Version>().is_err());
+        assert!("3.300.5.12340".parse::<Version>().is_err());
+        assert!("3.3.5.70000".parse::<Version>().is_err());
+    }
+
+    #[test]
+    pub fn test_version_ordering() {
+        let versions = ["1.12.1.5875", "2.4.3.8606", "3.3.5.12340", "3.3.5.12345", "4.3.4.15595"]
+            .map(|version| version.parse::<Version>().unwrap());
+
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -2,7 +2,7 @@
 
 use anyhow::Result;
 use crate::packets::{Payload, ReadExt, Serializable, WriteExt};
-use crate::grunt::protocol::{GruntIdentifier, GruntProtocol, SecurityProof};
+use crate::grunt::protocol::{GruntIdentifier, GruntProtocol, LoginResult, Role, SecurityProof};
 
 #[derive(Debug)]
 pub struct TelemetryKey {
@@ -40,14 +40,14 @@ pub struct LogonProofRequest {
     pub proof: [u8; 20],
     pub crc: [u8; 20],
     pub telemetry_keys: Vec<TelemetryKey>,
-    pub security: SecurityProof,
+    pub security: Vec<SecurityProof>,
 }
 
 impl<P: GruntProtocol> Payload<P> for LogonProofRequest {
     type Identifier = GruntIdentifier;
 
     fn identifier(&self) -> Self::Identifier {
-        GruntIdentifier(0x01)
+        GruntIdentifier(0x01, Role::Client)
     }
 
     async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
@@ -66,7 +66,7 @@ impl<P: GruntProtocol> Payload<P> for LogonProofRequest {
             keys
         };
 
-        let security = SecurityProof::recv(source, protocol).await?;
+        let security = Vec::<SecurityProof>::recv(source, protocol).await?;
 
         Ok(Self {
             public_key,
@@ -92,7 +92,84 @@ impl<P: GruntProtocol> Payload<P> for LogonProofRequest {
     }
 }
 
-#[derive(Debug)]
-pub struct LogonProofResponse {
+#[derive(PartialEq, Debug)]
+pub enum LogonProofResponse {
+    /// The layout of this variant depends on the protocol version:
+    /// - Versions 2 and 3 only carry `proof` and `hardware_survey_id`.
+    /// - Versions 5 to 7 add `unknown_flags`.
+    /// - Version 8 adds `account_flags`.
+    ///
+    /// Fields that do not exist in the protocol in use are ignored when sending
+    /// and set to zero when receiving.
+    Ok {
+        proof: [u8; 20],
+        account_flags: u32,
+        hardware_survey_id: u32,
+        unknown_flags: u16,
+    },
+    Err(LoginResult)
+}
+
+impl<P: GruntProtocol> Payload<P> for LogonProofResponse {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x01, Role::Server)
+    }
+
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let login_result = LoginResult::recv(source, protocol).await?;
+        if login_result == LoginResult::Success {
+            let proof = source.read_exact_slice().await?;
+            let account_flags = if protocol.version().has_account_flags() {
+                source.read_u32_le().await?
+            } else {
+                0
+            };
+            let hardware_survey_id = source.read_u32_le().await?;
+            let unknown_flags = if protocol.version().has_proof_flags() {
+                source.read_u16_le().await?
+            } else {
+                0
+            };
 
+            Ok(Self::Ok { proof, account_flags, hardware_survey_id, unknown_flags })
+        } else {
+            if protocol.version().has_proof_flags() {
+                let _ = source.read_u16_le::<u16>().await?; // Padding
+            }
+
+            Ok(Self::Err(login_result))
+        }
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        match self {
+            LogonProofResponse::Ok { proof, account_flags, hardware_survey_id, unknown_flags } => {
+                LoginResult::Success.send(dest, protocol).await?;
+                dest.write_slice(&proof).await?;
+                if protocol.version().has_account_flags() {
+                    dest.write_u32_le(account_flags).await?;
+                }
+                dest.write_u32_le(hardware_survey_id).await?;
+                if protocol.version().has_proof_flags() {
+                    dest.write_u16_le(unknown_flags).await?;
+                }
+
+                Ok(())
+            },
+            LogonProofResponse::Err(login_result) => {
+                login_result.send(dest, protocol).await?;
+                if protocol.version().has_proof_flags() {
+                    dest.write_u16_le(0u16).await?; // Padding
+                }
+
+                Ok(())
+            },
+        }
+    }
 }
@@ -1,9 +1,214 @@
 #![allow(dead_code)]
 
+use anyhow::Result;
+use crate::packets::{Payload, ReadExt, Serializable, WriteExt};
+use crate::grunt::protocol::{GruntIdentifier, GruntProtocol, Role, Version};
+
+/// Flags describing the state of a [`Realm`].
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct RealmFlags(pub u8);
+
+impl RealmFlags {
+    pub const NONE: Self = Self(0x00);
+    /// The client's version does not match the realm's.
+    pub const INVALID: Self = Self(0x01);
+    pub const OFFLINE: Self = Self(0x02);
+    /// The realm entry is followed by the build it runs. Only exists in versions 5 and later.
+    pub const SPECIFY_BUILD: Self = Self(0x04);
+    pub const NEW_PLAYERS: Self = Self(0x20);
+    pub const RECOMMENDED: Self = Self(0x40);
+    pub const FULL: Self = Self(0x80);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+/// A single entry of a [`RealmlistResponse`].
+#[derive(Clone, Debug)]
 pub struct Realm {
+    /// The kind of realm (0 for Normal, 1 for PvP, 6 for RP, 8 for RP-PvP...)
+    pub realm_type: u8,
+    /// Whether the realm is locked. Always `false` before version 5.
+    pub locked: bool,
+    pub flags: RealmFlags,
+    pub name: String,
+    /// The address of the world server, formatted as `host:port`.
+    pub address: String,
+    pub population: f32,
+    /// The amount of characters the account has on this realm.
+    pub characters: u8,
+    /// The realm category, used by the client to group realms by timezone.
+    pub category: u8,
+    pub id: u8,
+    /// The build this realm runs. Only sent in versions 5 and later, in which
+    /// case [`RealmFlags::SPECIFY_BUILD`] is automatically set.
+    pub build: Option<Version>,
+}
+
+impl<P: GruntProtocol> Serializable<P> for Realm {
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let legacy = !protocol.version().has_extended_realmlist();
+
+        let (realm_type, locked) = if legacy {
+            (source.read_u32_le::<u32>().await? as u8, false)
+        } else {
+            (source.read_u8().await?, source.read_u8::<u8>().await? != 0)
+        };
+
+        let flags = RealmFlags(source.read_u8().await?);
+        let name = source.read_cstring(None).await?;
+        let address = source.read_cstring(None).await?;
+        let population = source.read_f32_le().await?;
+        let characters = source.read_u8().await?;
+        let category = source.read_u8().await?;
+        let id = source.read_u8().await?;
+
+        let build = if !legacy && flags.contains(RealmFlags::SPECIFY_BUILD) {
+            let major = source.read_u8().await?;
+            let minor = source.read_u8().await?;
+            let patch = source.read_u8().await?;
+            let build = source.read_u16_le().await?;
+
+            Some(Version { major, minor, patch, build })
+        } else {
+            None
+        };
+
+        Ok(Self { realm_type, locked, flags, name, address, population, characters, category, id, build })
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        let legacy = !protocol.version().has_extended_realmlist();
+
+        let flags = match self.build {
+            Some(_) if !legacy => self.flags.with(RealmFlags::SPECIFY_BUILD),
+            _ => self.flags.without(RealmFlags::SPECIFY_BUILD),
+        };
+
+        if legacy {
+            dest.write_u32_le(self.realm_type).await?;
+        } else {
+            dest.write_u8(self.realm_type).await?;
+            dest.write_u8(self.locked as u8).await?;
+        }
+
+        dest.write_u8(flags.0).await?;
+        dest.write_cstring(&self.name).await?;
+        dest.write_cstring(&self.address).await?;
+        dest.write_f32_le(self.population).await?;
+        dest.write_u8(self.characters).await?;
+        dest.write_u8(self.category).await?;
+        dest.write_u8(self.id).await?;
+
+        if let Some(version) = self.build.filter(|_| !legacy) {
+            dest.write_u8(version.major).await?;
+            dest.write_u8(version.minor).await?;
+            dest.write_u8(version.patch).await?;
+            dest.write_u16_le(version.build).await?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
-pub struct Realmlist {
-    // realms: Vec<Realm>,
+pub struct RealmlistRequest;
+
+impl<P: GruntProtocol> Payload<P> for RealmlistRequest {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x10, Role::Client)
+    }
+
+    async fn recv<S>(source: &mut S, _: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let _ = source.read_u32_le::<u32>().await?; // Padding
+
+        Ok(Self)
+    }
+
+    async fn send<D>(self, dest: &mut D, _: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        dest.write_u32_le(0u32).await
+    }
+}
+
+#[derive(Debug)]
+pub struct RealmlistResponse {
+    pub realms: Vec<Realm>,
+}
+
+impl<P: GruntProtocol> Payload<P> for RealmlistResponse {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x10, Role::Server)
+    }
+
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let size = source.read_u16_le::<u16>().await?;
+        let mut source = source.take(size as usize);
+
+        let _ = source.read_u32_le::<u32>().await?; // Padding
+        let count: u16 = if protocol.version().has_extended_realmlist() {
+            source.read_u16_le().await?
+        } else {
+            source.read_u8().await?
+        };
+
+        let mut realms = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            realms.push(Realm::recv(&mut source, protocol).await?);
+        }
+
+        let _ = source.read_u16_le::<u16>().await?; // Footer
+
+        Ok(Self { realms })
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        // The payload is prefixed with its size, so it has to be serialized first.
+        let mut body = Vec::new();
+        body.write_u32_le(0u32).await?;
+        if protocol.version().has_extended_realmlist() {
+            body.write_u16_le(self.realms.len() as u16).await?;
+        } else {
+            body.write_u8(self.realms.len() as u8).await?;
+        }
+
+        for realm in self.realms {
+            realm.send(&mut body, protocol).await?;
+        }
+
+        // Emulators and official servers do not agree on the footer; use the
+        // values that clients of each generation were historically sent.
+        if protocol.version().has_extended_realmlist() {
+            body.write_u16_le(0x0010u16).await?;
+        } else {
+            body.write_u16_le(0x0200u16).await?;
+        }
+
+        dest.write_u16_le(body.len() as u16).await?;
+        dest.write_slice(&body).await
+    }
 }
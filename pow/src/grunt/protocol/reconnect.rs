@@ -0,0 +1,148 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use crate::packets::{Payload, ReadExt, Serializable, WriteExt};
+use crate::grunt::protocol::{GruntIdentifier, GruntProtocol, LoginResult, LogonChallengeRequest, Role};
+
+/// Sent by a client that wishes to reuse the session key of a previous login.
+///
+/// This packet has the exact same layout as a [`LogonChallengeRequest`].
+#[derive(Debug)]
+pub struct ReconnectChallengeRequest(pub LogonChallengeRequest);
+
+impl<P: GruntProtocol> Payload<P> for ReconnectChallengeRequest {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x02, Role::Client)
+    }
+
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        Ok(Self(<LogonChallengeRequest as Payload<P>>::recv(source, protocol).await?))
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        self.0.send(dest, protocol).await
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ReconnectChallengeResponse {
+    Ok {
+        /// Random data the client has to prove it can hash along with the session key.
+        challenge: [u8; 16],
+        checksum_salt: [u8; 16],
+    },
+    Err(LoginResult)
+}
+
+impl<P: GruntProtocol> Payload<P> for ReconnectChallengeResponse {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x02, Role::Server)
+    }
+
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let login_result = LoginResult::recv(source, protocol).await?;
+        if login_result == LoginResult::Success {
+            let challenge = source.read_exact_slice().await?;
+            let checksum_salt = source.read_exact_slice().await?;
+
+            Ok(Self::Ok { challenge, checksum_salt })
+        } else {
+            Ok(Self::Err(login_result))
+        }
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        match self {
+            ReconnectChallengeResponse::Ok { challenge, checksum_salt } => {
+                LoginResult::Success.send(dest, protocol).await?;
+                dest.write_slice(&challenge).await?;
+                dest.write_slice(&checksum_salt).await
+            },
+            ReconnectChallengeResponse::Err(login_result) => {
+                login_result.send(dest, protocol).await
+            },
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ReconnectProofRequest {
+    /// Random data generated by the client.
+    pub proof_data: [u8; 16],
+    /// `SHA1(account | proof_data | challenge | session key)`.
+    pub proof: [u8; 20],
+    pub checksum: [u8; 20],
+}
+
+impl<P: GruntProtocol> Payload<P> for ReconnectProofRequest {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x03, Role::Client)
+    }
+
+    async fn recv<S>(source: &mut S, _: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let proof_data = source.read_exact_slice().await?;
+        let proof = source.read_exact_slice().await?;
+        let checksum = source.read_exact_slice().await?;
+        let _ = source.read_u8::<u8>().await?; // Key count, always zero.
+
+        Ok(Self { proof_data, proof, checksum })
+    }
+
+    async fn send<D>(self, dest: &mut D, _: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        dest.write_slice(&self.proof_data).await?;
+        dest.write_slice(&self.proof).await?;
+        dest.write_slice(&self.checksum).await?;
+        dest.write_u8(0).await
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ReconnectProofResponse(pub LoginResult);
+
+impl<P: GruntProtocol> Payload<P> for ReconnectProofResponse {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x03, Role::Server)
+    }
+
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let login_result = LoginResult::recv(source, protocol).await?;
+        if protocol.version().has_proof_flags() {
+            let _ = source.read_u16_le::<u16>().await?; // Padding
+        }
+
+        Ok(Self(login_result))
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        self.0.send(dest, protocol).await?;
+        if protocol.version().has_proof_flags() {
+            dest.write_u16_le(0u16).await?; // Padding
+        }
+
+        Ok(())
+    }
+}
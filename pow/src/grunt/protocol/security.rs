@@ -2,144 +2,254 @@
 
 use pow_macro::EnumKind;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use crate::packets::{ReadExt, Serializable, WriteExt};
 use crate::grunt::protocol::GruntProtocol;
 
-#[derive(PartialEq, EnumKind, Debug)]
+/// A second factor asked for by the server.
+///
+/// Each kind of factor has its own bit in the security flags of a
+/// [`LogonChallengeResponse`](crate::grunt::protocol::LogonChallengeResponse), so that a
+/// server can ask for any combination of them; they are sent in the order of their bits.
+#[derive(Clone, PartialEq, EnumKind, Debug)]
 pub enum SecurityChallenge {
-    None,
+    #[kind = 0x01]
     Pin { seed: u32, salt: [u8; 16] },
+    #[kind = 0x02]
     Matrix { width: u8, height: u8, digits: u8, challenges: u8, seed: u64 },
+    #[kind = 0x04]
     Authenticator(u8)
 }
 
-impl<P: GruntProtocol> Serializable<P> for SecurityChallenge {
+/// The security flags understood by `pow`.
+const KNOWN_FLAGS: u8 = 0x01 | 0x02 | 0x04;
+
+/// Reads the security flags that precede a set of factors.
+async fn read_flags<S: ReadExt>(source: &mut S) -> Result<u8> {
+    let flags = source.read_u8::<u8>().await?;
+    if flags & !KNOWN_FLAGS != 0 {
+        bail!("Unknown security flags {:#04X}", flags);
+    }
+
+    Ok(flags)
+}
+
+/// Writes the security flags of a set of factors, each of which must be of a different kind.
+/// The factors are sorted in the order they have to be sent in.
+async fn write_flags<D: WriteExt, T>(dest: &mut D, factors: &mut [T], identifier: fn(&T) -> usize) -> Result<()> {
+    factors.sort_by_key(identifier);
+
+    let flags = factors.iter().fold(0, |flags, factor| flags | identifier(factor) as u8);
+    if flags.count_ones() as usize != factors.len() {
+        bail!("A second factor can only be asked for once");
+    }
+
+    dest.write_u8(flags).await
+}
+
+impl<P: GruntProtocol> Serializable<P> for Vec<SecurityChallenge> {
     async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
         where S: ReadExt
     {
-        if protocol.version() != 2 {
-            let kind = source.read_u8().await?;
-            Ok(match kind {
-                0 => Self::None,
-                1 => {
-                    let seed = source.read_u32_le().await?;
-                    let salt = source.read_exact_slice().await?;
-
-                    Self::Pin { seed, salt }
-                },
-                2 => {
-                    let width = source.read_u8().await?;
-                    let height = source.read_u8().await?;
-                    let digits = source.read_u8().await?;
-                    let count = source.read_u8().await?;
-                    let seed = source.read_u64_le().await?;
-
-                    Self::Matrix { width, height, digits, challenges: count, seed }
-                },
-                3 => {
-                    Self::Authenticator(source.read_u8().await?)
-                },
-                _ => panic!("Unknown security kind")
-            })
-        } else {
-            Ok(Self::None)
+        if !protocol.version().has_security_flags() {
+            return Ok(vec![]);
+        }
+
+        let flags = read_flags(source).await?;
+        let mut challenges = Vec::with_capacity(flags.count_ones() as usize);
+        if flags & 0x01 != 0 {
+            let seed = source.read_u32_le().await?;
+            let salt = source.read_exact_slice().await?;
+
+            challenges.push(SecurityChallenge::Pin { seed, salt });
+        }
+        if flags & 0x02 != 0 {
+            let width = source.read_u8().await?;
+            let height = source.read_u8().await?;
+            let digits = source.read_u8().await?;
+            let count = source.read_u8().await?;
+            let seed = source.read_u64_le().await?;
+
+            challenges.push(SecurityChallenge::Matrix { width, height, digits, challenges: count, seed });
         }
+        if flags & 0x04 != 0 {
+            challenges.push(SecurityChallenge::Authenticator(source.read_u8().await?));
+        }
+
+        Ok(challenges)
     }
 
-    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+    async fn send<D>(mut self, dest: &mut D, protocol: &mut P) -> Result<()>
         where D: WriteExt
     {
-        if protocol.version() == 2 {
+        if !protocol.version().has_security_flags() {
+            if !self.is_empty() {
+                bail!("Protocol version {} cannot ask for a second factor", protocol.version());
+            }
+
             return Ok(());
         }
 
-        let kind = self.identifier() as u8;
-        dest.write_u8(kind).await?;
-
-        Ok(match self {
-            SecurityChallenge::None => (),
-            SecurityChallenge::Pin { seed, salt } => {
-                dest.write_u32_le(seed).await?;
-                dest.write_slice(&salt).await?;
-            },
-            SecurityChallenge::Matrix { width, height, digits, challenges, seed } => {
-                dest.write_u8(width).await?;
-                dest.write_u8(height).await?;
-                dest.write_u8(digits).await?;
-                dest.write_u8(challenges).await?;
-                dest.write_u64_le(seed).await?;
-            },
-            SecurityChallenge::Authenticator(value) => {
-                dest.write_u8(value).await?;
-            },
-        })
+        write_flags(dest, &mut self, SecurityChallenge::identifier).await?;
+        for challenge in self {
+            match challenge {
+                SecurityChallenge::Pin { seed, salt } => {
+                    dest.write_u32_le(seed).await?;
+                    dest.write_slice(&salt).await?;
+                },
+                SecurityChallenge::Matrix { width, height, digits, challenges, seed } => {
+                    dest.write_u8(width).await?;
+                    dest.write_u8(height).await?;
+                    dest.write_u8(digits).await?;
+                    dest.write_u8(challenges).await?;
+                    dest.write_u64_le(seed).await?;
+                },
+                SecurityChallenge::Authenticator(value) => {
+                    dest.write_u8(value).await?;
+                },
+            };
+        }
+
+        Ok(())
     }
 }
 
+/// The answer of the client to a [`SecurityChallenge`]. Proofs are flagged and ordered just
+/// like challenges.
 #[derive(PartialEq, EnumKind, Debug)]
 pub enum SecurityProof {
-    None,
-    Pin { salt: [u8; 16], hash: [u8; 16] },
+    #[kind = 0x01]
+    Pin { salt: [u8; 16], hash: [u8; 20] },
+    #[kind = 0x02]
     Matrix { proof: [u8; 20] },
+    #[kind = 0x04]
     Authenticator(String)
 }
 
-impl<P: GruntProtocol> Serializable<P> for SecurityProof {
+impl<P: GruntProtocol> Serializable<P> for Vec<SecurityProof> {
     async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
         where S: ReadExt
     {
-        if protocol.version() != 2 {
-            let kind = source.read_u8().await?;
-            Ok(match kind {
-                0 => Self::None,
-                1 => {
-                    let salt = source.read_exact_slice().await?;
-                    let hash = source.read_exact_slice().await?;
-
-                    Self::Pin { salt, hash }
-                },
-                2 => {
-                    let proof = source.read_exact_slice().await?;
+        if !protocol.version().has_security_flags() {
+            return Ok(vec![]);
+        }
 
-                    Self::Matrix { proof }
-                },
-                3 => {
-                    let length = source.read_u8::<usize>().await?;
-                    let str = source.read_string(length).await?;
-                    Self::Authenticator(str)
-                },
-                _ => panic!("Unknown security kind")
-            })
-        } else {
-            Ok(Self::None)
+        let flags = read_flags(source).await?;
+        let mut proofs = Vec::with_capacity(flags.count_ones() as usize);
+        if flags & 0x01 != 0 {
+            let salt = source.read_exact_slice().await?;
+            let hash = source.read_exact_slice().await?;
+
+            proofs.push(SecurityProof::Pin { salt, hash });
+        }
+        if flags & 0x02 != 0 {
+            let proof = source.read_exact_slice().await?;
+
+            proofs.push(SecurityProof::Matrix { proof });
         }
+        if flags & 0x04 != 0 {
+            let length = source.read_u8::<usize>().await?;
+            proofs.push(SecurityProof::Authenticator(source.read_string(length).await?));
+        }
+
+        Ok(proofs)
     }
 
-    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+    async fn send<D>(mut self, dest: &mut D, protocol: &mut P) -> Result<()>
         where D: WriteExt
     {
-        if protocol.version() == 2 {
-            assert!(self == Self::None);
+        if !protocol.version().has_security_flags() {
+            if !self.is_empty() {
+                bail!("Protocol version {} cannot answer a second factor", protocol.version());
+            }
+
             return Ok(());
         }
 
-        let kind = self.identifier() as u8;
-        dest.write_u8(kind).await?;
-
-        Ok(match self {
-            SecurityProof::None => (),
-            SecurityProof::Pin { salt, hash } => {
-                dest.write_slice(&salt).await?;
-                dest.write_slice(&hash).await?;
-            },
-            SecurityProof::Matrix { proof } => {
-                dest.write_slice(&proof).await?;
-            },
-            SecurityProof::Authenticator(str) => {
-                dest.write_u8(str.len() as u8).await?;
-                dest.write_slice(str.as_bytes()).await?;
-            },
-        })
+        write_flags(dest, &mut self, SecurityProof::identifier).await?;
+        for proof in self {
+            match proof {
+                SecurityProof::Pin { salt, hash } => {
+                    dest.write_slice(&salt).await?;
+                    dest.write_slice(&hash).await?;
+                },
+                SecurityProof::Matrix { proof } => {
+                    dest.write_slice(&proof).await?;
+                },
+                SecurityProof::Authenticator(str) => {
+                    dest.write_u8(str.len() as u8).await?;
+                    dest.write_slice(str.as_bytes()).await?;
+                },
+            };
+        }
+
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod test {
+    use crate::grunt::login::LoginProtocol;
+    use crate::grunt::protocol::{GruntVersion, SecurityChallenge, SecurityProof};
+    use crate::packets::Serializable;
+
+    #[tokio::test]
+    pub async fn test_authenticator_flag() {
+        let mut protocol = LoginProtocol::new(GruntVersion::V8, "secret", false);
+
+        let mut buffer = Vec::new();
+        vec![SecurityChallenge::Authenticator(1)].send(&mut buffer, &mut protocol).await.unwrap();
+        assert_eq!(buffer, [0x04, 0x01]);
+        let challenges = Vec::<SecurityChallenge>::recv(&mut &buffer[..], &mut protocol).await.unwrap();
+        assert_eq!(challenges, [SecurityChallenge::Authenticator(1)]);
+
+        let mut buffer = Vec::new();
+        vec![SecurityProof::Authenticator("123456".to_string())].send(&mut buffer, &mut protocol).await.unwrap();
+        assert_eq!(buffer[..2], [0x04, 0x06]);
+        let proofs = Vec::<SecurityProof>::recv(&mut &buffer[..], &mut protocol).await.unwrap();
+        assert_eq!(proofs, [SecurityProof::Authenticator("123456".to_string())]);
+    }
+
+    #[tokio::test]
+    pub async fn test_combined_flags() {
+        let mut protocol = LoginProtocol::new(GruntVersion::V8, "secret", false);
+        let pin = SecurityChallenge::Pin { seed: 0x01020304, salt: [0xAA; 16] };
+        let matrix = SecurityChallenge::Matrix { width: 8, height: 10, digits: 3, challenges: 2, seed: 0x42 };
+
+        // Factors are sent in the order of their flags.
+        let mut buffer = Vec::new();
+        vec![SecurityChallenge::Authenticator(1), matrix.clone(), pin.clone()].send(&mut buffer, &mut protocol).await.unwrap();
+        assert_eq!(buffer[..6], [0x07, 0x04, 0x03, 0x02, 0x01, 0xAA]);
+        assert_eq!(buffer.len(), 1 + 20 + 12 + 1);
+        let challenges = Vec::<SecurityChallenge>::recv(&mut &buffer[..], &mut protocol).await.unwrap();
+        assert_eq!(challenges, [pin.clone(), matrix.clone(), SecurityChallenge::Authenticator(1)]);
+
+        let mut buffer = Vec::new();
+        vec![SecurityProof::Matrix { proof: [2; 20] }, SecurityProof::Pin { salt: [1; 16], hash: [3; 20] }]
+            .send(&mut buffer, &mut protocol)
+            .await
+            .unwrap();
+        assert_eq!(buffer[..2], [0x03, 0x01]);
+        let proofs = Vec::<SecurityProof>::recv(&mut &buffer[..], &mut protocol).await.unwrap();
+        assert_eq!(proofs, [SecurityProof::Pin { salt: [1; 16], hash: [3; 20] }, SecurityProof::Matrix { proof: [2; 20] }]);
+
+        // A factor cannot be asked for twice.
+        let result = vec![pin.clone(), pin].send(&mut Vec::new(), &mut protocol).await;
+        assert!(result.is_err());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    pub async fn test_invalid_flags() {
+        let mut protocol = LoginProtocol::new(GruntVersion::V8, "secret", false);
+        let err = Vec::<SecurityChallenge>::recv(&mut &[0x09, 0x00][..], &mut protocol).await.unwrap_err();
+        assert_eq!(err.to_string(), "Unknown security flags 0x09");
+        assert!(Vec::<SecurityProof>::recv(&mut &[0x10][..], &mut protocol).await.is_err());
+
+        // Legacy clients cannot carry a second factor at all.
+        let mut protocol = LoginProtocol::new(GruntVersion::V2, "secret", false);
+        let mut buffer = Vec::new();
+        vec![SecurityChallenge::Authenticator(1)].send(&mut buffer, &mut protocol).await.unwrap_err();
+        vec![SecurityProof::Matrix { proof: [0; 20] }].send(&mut buffer, &mut protocol).await.unwrap_err();
+        Vec::<SecurityProof>::new().send(&mut buffer, &mut protocol).await.unwrap();
+        assert!(buffer.is_empty());
+    }
+}
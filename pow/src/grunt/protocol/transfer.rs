@@ -0,0 +1,147 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use crate::packets::{Payload, ReadExt, WriteExt};
+use crate::grunt::protocol::{GruntIdentifier, GruntProtocol, Role};
+
+/// Announces a file transfer. This is sent by the server after a [`LoginResult::DownloadFile`].
+///
+/// [`LoginResult::DownloadFile`]: crate::grunt::protocol::LoginResult::DownloadFile
+#[derive(PartialEq, Debug)]
+pub struct XferInitiate {
+    /// The kind of file being transferred. Clients only know about `"Patch"`.
+    pub filename: String,
+    pub size: u64,
+    pub md5: [u8; 16],
+}
+
+impl<P: GruntProtocol> Payload<P> for XferInitiate {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x30, Role::Server)
+    }
+
+    async fn recv<S>(source: &mut S, _: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let filename = {
+            let length = source.read_u8::<usize>().await?;
+            source.read_string(length).await?
+        };
+        let size = source.read_u64_le().await?;
+        let md5 = source.read_exact_slice().await?;
+
+        Ok(Self { filename, size, md5 })
+    }
+
+    async fn send<D>(self, dest: &mut D, _: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        dest.write_u8(self.filename.len() as u8).await?;
+        dest.write_string(&self.filename).await?;
+        dest.write_u64_le(self.size).await?;
+        dest.write_slice(&self.md5).await
+    }
+}
+
+/// A chunk of the file being transferred.
+#[derive(PartialEq, Debug)]
+pub struct XferData(pub Box<[u8]>);
+
+impl<P: GruntProtocol> Payload<P> for XferData {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x31, Role::Server)
+    }
+
+    async fn recv<S>(source: &mut S, _: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let size = source.read_u16_le::<u16>().await?;
+
+        Ok(Self(source.read_slice(size as usize).await?))
+    }
+
+    async fn send<D>(self, dest: &mut D, _: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        dest.write_u16_le(self.0.len() as u16).await?;
+        dest.write_slice(&self.0).await
+    }
+}
+
+/// Sent by the client to start downloading the file from the beginning.
+#[derive(PartialEq, Debug)]
+pub struct XferAccept;
+
+impl<P: GruntProtocol> Payload<P> for XferAccept {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x32, Role::Client)
+    }
+
+    async fn recv<S>(_: &mut S, _: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        Ok(Self)
+    }
+
+    async fn send<D>(self, _: &mut D, _: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        Ok(())
+    }
+}
+
+/// Sent by the client to resume downloading a partially downloaded file.
+#[derive(PartialEq, Debug)]
+pub struct XferResume {
+    pub offset: u64,
+}
+
+impl<P: GruntProtocol> Payload<P> for XferResume {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x33, Role::Client)
+    }
+
+    async fn recv<S>(source: &mut S, _: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        Ok(Self { offset: source.read_u64_le().await? })
+    }
+
+    async fn send<D>(self, dest: &mut D, _: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        dest.write_u64_le(self.offset).await
+    }
+}
+
+/// Sent by the client to decline or abort a transfer.
+#[derive(PartialEq, Debug)]
+pub struct XferCancel;
+
+impl<P: GruntProtocol> Payload<P> for XferCancel {
+    type Identifier = GruntIdentifier;
+
+    fn identifier(&self) -> Self::Identifier {
+        GruntIdentifier(0x34, Role::Client)
+    }
+
+    async fn recv<S>(_: &mut S, _: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        Ok(Self)
+    }
+
+    async fn send<D>(self, _: &mut D, _: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        Ok(())
+    }
+}
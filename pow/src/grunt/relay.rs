@@ -0,0 +1,478 @@
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::grunt::authenticator::{AuthenticatorGate, AuthenticatorSecrets};
+use crate::grunt::bans::BanList;
+use crate::grunt::builds::ClientBuild;
+use crate::grunt::lockout::LoginThrottle;
+use crate::grunt::protocol::{
+    GruntProtocol, GruntVersion, LoginResult, LogonChallengeRequest, LogonChallengeResponse, LogonProofRequest,
+    LogonProofResponse, RealmlistRequest, RealmlistResponse, ReconnectChallengeRequest, ReconnectChallengeResponse,
+    ReconnectProofRequest, ReconnectProofResponse, Role, XferAccept, XferCancel, XferData, XferInitiate, XferResume
+};
+use crate::grunt::rewrite::RealmRewriter;
+use crate::grunt::state::AuthState;
+use crate::grunt::translate::Translate;
+use crate::network::connection::Client;
+use crate::network::server::Server;
+use crate::packets::{Payload, Protocol, WriteExt};
+
+/// The revision of the Grunt protocol spoken to upstream servers.
+const UPSTREAM_VERSION: GruntVersion = GruntVersion::V8;
+
+/// What a [`RelayServer`] does with the patches the upstream server offers to its clients.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PatchPolicy {
+    /// The offer is forwarded, and so is the patch if the client accepts it.
+    #[default]
+    Relay,
+    /// The client is turned away with [`LoginResult::InvalidVersion`] instead.
+    Block,
+}
+
+/// A proxy that forwards the logins of its clients to an upstream authentication server,
+/// translating messages in both directions.
+///
+/// SRP is not terminated: proofs are forwarded untouched, so the relay never learns the
+/// session keys of its clients and cannot read their world traffic.
+pub struct RelayServer {
+    address: String,
+    upstream: String,
+    token: CancellationToken,
+    authenticator: AuthenticatorSecrets,
+    realms: Option<RealmRewriter>,
+    throttle: LoginThrottle,
+    bans: BanList,
+    patches: PatchPolicy,
+}
+
+impl RelayServer {
+    /// # Arguments
+    ///
+    /// - `address`: The address to listen on.
+    /// - `upstream`: The address of the authentication server logins are forwarded to.
+    /// - `token`: A token that stops this server once signalled.
+    pub fn new(address: &str, upstream: &str, token: CancellationToken) -> Self {
+        Self {
+            address: address.to_string(),
+            upstream: upstream.to_string(),
+            token,
+            authenticator: AuthenticatorSecrets::default(),
+            realms: None,
+            throttle: LoginThrottle::default(),
+            bans: BanList::default(),
+            patches: PatchPolicy::default(),
+        }
+    }
+
+    /// Replaces the default brute-force protection of this server.
+    pub fn with_throttle(mut self, throttle: LoginThrottle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Enforces the given bans, both on the addresses of clients and on their accounts, before
+    /// their logins are forwarded.
+    pub fn with_bans(mut self, bans: BanList) -> Self {
+        self.bans = bans;
+        self
+    }
+
+    /// Requires an authenticator code from the given accounts, on behalf of the upstream server.
+    pub fn with_authenticator(mut self, secrets: AuthenticatorSecrets) -> Self {
+        self.authenticator = secrets;
+        self
+    }
+
+    /// Rewrites the addresses of the realms advertised by the upstream server.
+    pub fn with_realms(mut self, realms: RealmRewriter) -> Self {
+        self.realms = Some(realms);
+        self
+    }
+
+    /// Decides what happens to the patches the upstream server offers.
+    pub fn with_patches(mut self, patches: PatchPolicy) -> Self {
+        self.patches = patches;
+        self
+    }
+}
+
+impl Server for RelayServer {
+    type Protocol = RelayProtocol;
+
+    fn addr(&self) -> String { self.address.clone() }
+
+    fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    fn make_protocol(&self, addr: SocketAddr) -> Self::Protocol {
+        RelayProtocol {
+            address: addr.ip(),
+            version: UPSTREAM_VERSION,
+            state: AuthState::default(),
+            upstream_address: self.upstream.clone(),
+            token: self.token.child_token(),
+            gate: AuthenticatorGate::new(self.authenticator.clone()),
+            realms: self.realms.clone(),
+            throttle: self.throttle.clone(),
+            bans: self.bans.clone(),
+            patches: self.patches,
+            account: None,
+            build: None,
+            upstream: None,
+            offered: None,
+            transfer: None,
+        }
+    }
+
+    fn throttle(&self) -> &LoginThrottle {
+        &self.throttle
+    }
+
+    fn accepts(&self, addr: SocketAddr) -> bool {
+        self.bans.allows(addr.ip())
+    }
+}
+
+/// The upstream half of a [`RelayProtocol`]. It keeps the responses of the server until
+/// the relay forwards them.
+#[derive(Default)]
+pub struct UpstreamProtocol {
+    challenge: Option<LogonChallengeResponse>,
+    proof: Option<LogonProofResponse>,
+    realmlist: Option<RealmlistResponse>,
+    reconnect_challenge: Option<ReconnectChallengeResponse>,
+    reconnect_proof: Option<ReconnectProofResponse>,
+    offer: Option<XferInitiate>,
+    data: Option<XferData>,
+}
+
+impl GruntProtocol for UpstreamProtocol {
+    fn version(&self) -> GruntVersion { UPSTREAM_VERSION }
+    fn set_version(&mut self, _: GruntVersion) { }
+    fn role(&self) -> Role { Role::Client }
+
+    async fn handle_logon_challenge_response<D>(&mut self, msg: LogonChallengeResponse, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.challenge = Some(msg);
+        Ok(())
+    }
+
+    async fn handle_logon_proof_response<D>(&mut self, msg: LogonProofResponse, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.proof = Some(msg);
+        Ok(())
+    }
+
+    async fn handle_realmlist_response<D>(&mut self, msg: RealmlistResponse, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.realmlist = Some(msg);
+        Ok(())
+    }
+
+    async fn handle_reconnect_challenge_response<D>(&mut self, msg: ReconnectChallengeResponse, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.reconnect_challenge = Some(msg);
+        Ok(())
+    }
+
+    async fn handle_reconnect_proof_response<D>(&mut self, msg: ReconnectProofResponse, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.reconnect_proof = Some(msg);
+        Ok(())
+    }
+
+    async fn handle_xfer_initiate<D>(&mut self, msg: XferInitiate, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.offer = Some(msg);
+        Ok(())
+    }
+
+    async fn handle_xfer_data<D>(&mut self, msg: XferData, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.data = Some(msg);
+        Ok(())
+    }
+}
+
+/// The protocol of a single connection to a [`RelayServer`].
+pub struct RelayProtocol {
+    address: IpAddr,
+    version: GruntVersion,
+    state: AuthState,
+    upstream_address: String,
+    token: CancellationToken,
+    gate: AuthenticatorGate,
+    realms: Option<RealmRewriter>,
+    throttle: LoginThrottle,
+    bans: BanList,
+    patches: PatchPolicy,
+    /// The account the client is logging into, once it sent its challenge.
+    account: Option<String>,
+    /// The build of the client, once it sent its challenge, if it is known.
+    build: Option<&'static ClientBuild>,
+    upstream: Option<Client<UpstreamProtocol>>,
+    /// The size of the patch that was offered and that the client has yet to accept or decline.
+    offered: Option<u64>,
+    /// The amount of bytes of the patch left to forward, once the client accepted it.
+    transfer: Option<u64>,
+}
+
+impl RelayProtocol {
+    fn upstream(&mut self) -> Result<&mut Client<UpstreamProtocol>> {
+        self.upstream.as_mut().ok_or_else(|| anyhow!("Not connected upstream"))
+    }
+
+    /// Lets the client that just logged in into the world servers of the realms this relay
+    /// advertises.
+    fn admit(&self) {
+        if let (Some(realms), Some(account), Some(build)) = (&self.realms, &self.account, self.build) {
+            realms.logins().insert(self.address, account, build);
+        }
+    }
+
+    /// Opens the connection to the upstream server that a new login is forwarded to.
+    async fn connect_upstream(&mut self) -> Result<&mut Client<UpstreamProtocol>> {
+        let upstream = Client::connect(&self.upstream_address, UpstreamProtocol::default(), self.token.child_token()).await?;
+        Ok(self.upstream.insert(upstream))
+    }
+
+    /// Handles the patch the upstream server offers once it told the client to download it.
+    async fn offer_patch<D>(&mut self, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let upstream = self.upstream()?;
+        upstream.process_incoming().await?;
+        let offer = upstream.protocol_mut().offer.take()
+            .ok_or_else(|| anyhow!("The upstream server did not offer a patch"))?;
+
+        match self.patches {
+            PatchPolicy::Relay => {
+                info!("Relaying a patch of {} bytes to {}", offer.size, self.address);
+                self.offered = Some(offer.size);
+                self.state.reject();
+                self.send(dest, LogonProofResponse::Err(LoginResult::DownloadFile).translate(self.version)).await?;
+                self.send(dest, offer).await
+            },
+            PatchPolicy::Block => {
+                info!("Blocked a patch offered to {}", self.address);
+                self.upstream = None;
+                self.state.reject();
+                self.send(dest, LogonProofResponse::Err(LoginResult::InvalidVersion).translate(self.version)).await
+            },
+        }
+    }
+
+    /// Forwards the acceptance of a patch offer, and starts forwarding the patch from the
+    /// given offset.
+    async fn accept_offer<Packet>(&mut self, answer: Packet, offset: u64) -> Result<()>
+        where Packet: Payload<UpstreamProtocol>
+    {
+        let Some(size) = self.offered.take() else {
+            return Ok(());
+        };
+
+        self.upstream()?.send(answer).await?;
+        self.transfer = Some(size.saturating_sub(offset));
+        Ok(())
+    }
+}
+
+impl GruntProtocol for RelayProtocol {
+    fn version(&self) -> GruntVersion { self.version }
+    fn set_version(&mut self, version: GruntVersion) { self.version = version; }
+    fn role(&self) -> Role { Role::Server }
+    fn auth_state(&mut self) -> Option<&mut AuthState> { Some(&mut self.state) }
+
+    async fn handle_logon_challenge_request<D>(&mut self, msg: LogonChallengeRequest, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        if let Err(result) = self.throttle.check(self.address, &msg.account_name, self.version) {
+            return self.send(dest, LogonChallengeResponse::Err(result)).await;
+        }
+
+        // Banned accounts are refused without bothering the upstream server. Its own bans
+        // are enforced by the server itself.
+        if let Some(result) = self.bans.account_ban(&msg.account_name, None, self.version) {
+            return self.send(dest, LogonChallengeResponse::Err(result)).await;
+        }
+
+        self.gate.challenge_request(&msg);
+        self.account = Some(msg.account_name.clone());
+        self.build = msg.version.client_build();
+
+        let upstream = self.connect_upstream().await?;
+        upstream.send(msg.translate(UPSTREAM_VERSION)).await?;
+        upstream.process_incoming().await?;
+        let response = upstream.protocol_mut().challenge.take()
+            .ok_or_else(|| anyhow!("The upstream server did not answer the challenge"))?;
+
+        if let LogonChallengeResponse::Err(LoginResult::UnknownAccount) = response {
+            self.throttle.record_failure(self.address, None);
+        }
+
+        let response = self.gate.challenge_response(response.translate(self.version), self.version);
+        self.send(dest, response).await
+    }
+
+    async fn handle_logon_proof_request<D>(&mut self, msg: LogonProofRequest, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        // The code is checked and stripped before the server sees the proof.
+        let request = match self.gate.proof_request(msg) {
+            Ok(request) => request,
+            Err(response) => {
+                self.throttle.record_failure(self.address, self.account.as_deref());
+                self.state.reject();
+                return self.send(dest, response.translate(self.version)).await;
+            },
+        };
+
+        let upstream = self.upstream()?;
+        upstream.send(request.translate(UPSTREAM_VERSION)).await?;
+        upstream.process_incoming().await?;
+        let response = upstream.protocol_mut().proof.take()
+            .ok_or_else(|| anyhow!("The upstream server did not answer the proof"))?;
+
+        match &response {
+            LogonProofResponse::Err(LoginResult::DownloadFile) => return self.offer_patch(dest).await,
+            LogonProofResponse::Ok { .. } => {
+                if let Some(account) = &self.account {
+                    self.throttle.record_success(account);
+                }
+                self.admit();
+                self.state.authenticate();
+            },
+            LogonProofResponse::Err(_) => {
+                self.throttle.record_failure(self.address, self.account.as_deref());
+                self.state.reject();
+            },
+        }
+
+        self.send(dest, response.translate(self.version)).await
+    }
+
+    async fn handle_realmlist_request<D>(&mut self, msg: RealmlistRequest, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let upstream = self.upstream()?;
+        upstream.send(msg.translate(UPSTREAM_VERSION)).await?;
+        upstream.process_incoming().await?;
+        let mut response = upstream.protocol_mut().realmlist.take()
+            .ok_or_else(|| anyhow!("The upstream server did not answer the realm list request"))?;
+
+        if let Some(realms) = &self.realms {
+            response = realms.rewrite(response).await?;
+        }
+
+        self.send(dest, response.translate(self.version)).await
+    }
+
+    async fn handle_reconnect_challenge_request<D>(&mut self, msg: ReconnectChallengeRequest, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        if let Err(result) = self.throttle.check(self.address, &msg.0.account_name, self.version) {
+            return self.send(dest, ReconnectChallengeResponse::Err(result)).await;
+        }
+
+        if let Some(result) = self.bans.account_ban(&msg.0.account_name, None, self.version) {
+            return self.send(dest, ReconnectChallengeResponse::Err(result)).await;
+        }
+
+        self.account = Some(msg.0.account_name.clone());
+        self.build = msg.0.version.client_build();
+
+        let upstream = self.connect_upstream().await?;
+        upstream.send(msg.translate(UPSTREAM_VERSION)).await?;
+        upstream.process_incoming().await?;
+        let response = upstream.protocol_mut().reconnect_challenge.take()
+            .ok_or_else(|| anyhow!("The upstream server did not answer the reconnect challenge"))?;
+
+        self.send(dest, response.translate(self.version)).await
+    }
+
+    async fn handle_reconnect_proof_request<D>(&mut self, msg: ReconnectProofRequest, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let upstream = self.upstream()?;
+        upstream.send(msg.translate(UPSTREAM_VERSION)).await?;
+        upstream.process_incoming().await?;
+        let response = upstream.protocol_mut().reconnect_proof.take()
+            .ok_or_else(|| anyhow!("The upstream server did not answer the reconnect proof"))?;
+
+        if response.0 == LoginResult::Success {
+            if let Some(account) = &self.account {
+                self.throttle.record_success(account);
+            }
+            self.admit();
+            self.state.authenticate();
+        } else {
+            self.throttle.record_failure(self.address, self.account.as_deref());
+            self.state.reject();
+        }
+
+        self.send(dest, response.translate(self.version)).await
+    }
+
+    async fn handle_xfer_accept<D>(&mut self, msg: XferAccept, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.accept_offer(msg, 0).await
+    }
+
+    async fn handle_xfer_resume<D>(&mut self, msg: XferResume, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let offset = msg.offset;
+        self.accept_offer(msg, offset).await
+    }
+
+    async fn handle_xfer_cancel<D>(&mut self, msg: XferCancel, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let offered = self.offered.take().is_some();
+        let transferring = self.transfer.take().is_some();
+        if !offered && !transferring {
+            return Ok(());
+        }
+
+        // A chunk may have been partially read from the server, so the connection is of no
+        // use anymore once it heard of the cancellation.
+        if let Some(mut upstream) = self.upstream.take() {
+            upstream.send(msg).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn send_queued<D>(&mut self, dest: &mut D) -> Result<bool>
+        where D: WriteExt
+    {
+        let Some(remaining) = self.transfer.take().filter(|remaining| *remaining > 0) else {
+            return Ok(false);
+        };
+
+        let upstream = self.upstream()?;
+        upstream.process_incoming().await?;
+        let chunk = upstream.protocol_mut().data.take()
+            .ok_or_else(|| anyhow!("The upstream server did not send the patch"))?;
+        let remaining = remaining.checked_sub(chunk.0.len() as u64)
+            .ok_or_else(|| anyhow!("The upstream server sent more than the patch"))?;
+
+        self.transfer = Some(remaining);
+        self.send(dest, chunk).await.map(|_| true)
+    }
+}
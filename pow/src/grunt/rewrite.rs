@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::grunt::protocol::RealmlistResponse;
+use crate::world::proxy::{RelayedLogins, open_world_proxy};
+
+/// Rewrites the addresses of the realms an upstream server advertises, so that clients
+/// connect to `pow` instead of the world servers themselves.
+///
+/// Addresses are either replaced with a fixed address, or, if the rewriter has a public
+/// host, with a listener allocated the first time the realm is advertised. These listeners
+/// only let in the clients whose logins were recorded in [`RealmRewriter::logins`]. See
+/// [`open_world_proxy`].
+#[derive(Clone)]
+pub struct RealmRewriter {
+    addresses: Arc<HashMap<String, String>>,
+    public_host: Option<String>,
+    bind_host: String,
+    listeners: Arc<Mutex<HashMap<String, String>>>,
+    logins: RelayedLogins,
+    token: CancellationToken,
+}
+
+impl RealmRewriter {
+    /// # Arguments
+    ///
+    /// - `addresses`: Fixed replacements, keyed by upstream address.
+    /// - `public_host`: The host clients reach `pow` at. Without it, realms that have no fixed
+    ///   replacement are left untouched.
+    /// - `bind_host`: The host the allocated listeners bind to.
+    /// - `token`: A token that closes every allocated listener once signalled.
+    pub fn new(addresses: HashMap<String, String>, public_host: Option<String>, bind_host: &str, token: CancellationToken) -> Self {
+        Self {
+            addresses: Arc::new(addresses),
+            public_host,
+            bind_host: bind_host.to_string(),
+            listeners: Default::default(),
+            logins: RelayedLogins::default(),
+            token,
+        }
+    }
+
+    /// The logins allowed into the world servers this rewriter allocated listeners for.
+    pub fn logins(&self) -> &RelayedLogins {
+        &self.logins
+    }
+
+    /// Returns the address clients should use to reach the given upstream world server.
+    pub async fn address_for(&self, upstream: &str) -> Result<String> {
+        if let Some(address) = self.addresses.get(upstream) {
+            return Ok(address.clone());
+        }
+
+        let Some(public_host) = &self.public_host else {
+            return Ok(upstream.to_string());
+        };
+
+        // The lock is held while binding so that concurrent logins share a single listener.
+        let mut listeners = self.listeners.lock().await;
+        if let Some(address) = listeners.get(upstream) {
+            return Ok(address.clone());
+        }
+
+        let bind = format!("{}:0", self.bind_host);
+        let local_addr = open_world_proxy(&bind, upstream.to_string(), self.logins.clone(), self.token.child_token()).await?;
+        let address = format!("{}:{}", public_host, local_addr.port());
+        listeners.insert(upstream.to_string(), address.clone());
+
+        Ok(address)
+    }
+
+    /// Rewrites the address of every realm in the given response.
+    pub async fn rewrite(&self, response: RealmlistResponse) -> Result<RealmlistResponse> {
+        let mut realms = response.realms;
+        for realm in &mut realms {
+            realm.address = self.address_for(&realm.address).await?;
+        }
+
+        Ok(RealmlistResponse { realms })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::sync::CancellationToken;
+
+    use crate::grunt::protocol::{Realm, RealmFlags, RealmlistResponse};
+    use crate::grunt::rewrite::RealmRewriter;
+
+    fn realm(id: u8, address: &str) -> Realm {
+        Realm {
+            realm_type: 1,
+            locked: false,
+            flags: RealmFlags::NONE,
+            name: format!("Realm {}", id),
+            address: address.to_string(),
+            population: 0.0,
+            characters: 0,
+            category: 1,
+            id,
+            build: None,
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_rewrite() {
+        let world = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let world_address = world.local_addr().unwrap().to_string();
+
+        let token = CancellationToken::new();
+        let addresses = HashMap::from([("10.0.0.1:8085".to_string(), "pow.example:8085".to_string())]);
+        let rewriter = RealmRewriter::new(addresses, Some("127.0.0.1".to_string()), "127.0.0.1", token.clone());
+
+        let response = rewriter.rewrite(RealmlistResponse {
+            realms: vec![realm(1, "10.0.0.1:8085"), realm(2, &world_address), realm(3, &world_address)],
+        }).await.expect("Realms should be rewritten");
+
+        assert_eq!(response.realms[0].address, "pow.example:8085");
+        assert_ne!(response.realms[1].address, world_address);
+        assert_eq!(response.realms[1].address, response.realms[2].address);
+
+        // Clients that did not log in are turned away before the world server hears of them.
+        let mut client = TcpStream::connect(&response.realms[1].address).await.expect("The listener should be open");
+        assert_eq!(client.read(&mut [0; 1]).await.expect("The connection should be closed"), 0);
+
+        // Without a public host, realms without a fixed replacement are left untouched.
+        let rewriter = RealmRewriter::new(HashMap::new(), None, "127.0.0.1", token.clone());
+        assert_eq!(rewriter.address_for(&world_address).await.unwrap(), world_address);
+
+        token.cancel();
+    }
+}
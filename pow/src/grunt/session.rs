@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use sha1::{Digest, Sha1};
+use subtle::ConstantTimeEq;
+
+use crate::grunt::protocol::{ReconnectChallengeResponse, ReconnectProofRequest};
+
+/// The key both ends of a Grunt connection agree on after a successful login.
+pub type SessionKey = [u8; 40];
+
+/// Session keys of the accounts that logged in, shared between all connections of a server.
+///
+/// Account names are case-insensitive, as they are in Grunt.
+#[derive(Clone, Default)]
+pub struct SessionKeys {
+    keys: Arc<RwLock<HashMap<String, SessionKey>>>,
+}
+
+impl SessionKeys {
+    pub fn insert(&self, account: &str, key: SessionKey) {
+        self.keys.write().unwrap().insert(account.to_uppercase(), key);
+    }
+
+    pub fn get(&self, account: &str) -> Option<SessionKey> {
+        self.keys.read().unwrap().get(&account.to_uppercase()).copied()
+    }
+
+    pub fn remove(&self, account: &str) -> Option<SessionKey> {
+        self.keys.write().unwrap().remove(&account.to_uppercase())
+    }
+}
+
+/// Computes the proof a client sends to reuse its session key.
+///
+/// # Arguments
+///
+/// - `account`: The name of the account, as sent in the reconnect challenge.
+/// - `proof_data`: Random data generated by the client.
+/// - `challenge`: Random data generated by the server.
+/// - `session_key`: The session key of the previous login.
+pub fn reconnect_proof(account: &str, proof_data: &[u8; 16], challenge: &[u8; 16], session_key: &SessionKey) -> [u8; 20] {
+    Sha1::new()
+        .chain_update(account.as_bytes())
+        .chain_update(proof_data)
+        .chain_update(challenge)
+        .chain_update(session_key)
+        .finalize()
+        .into()
+}
+
+/// The server side of a reconnection attempt.
+pub struct ReconnectChallenge {
+    account: String,
+    session_key: SessionKey,
+    challenge: [u8; 16],
+}
+
+impl ReconnectChallenge {
+    /// Creates a new challenge for an account that previously logged in with the given session key.
+    pub fn new(account: &str, session_key: SessionKey) -> Self {
+        Self {
+            account: account.to_string(),
+            session_key,
+            challenge: rand::random(),
+        }
+    }
+
+    /// Returns the response to send to the client.
+    pub fn response(&self) -> ReconnectChallengeResponse {
+        ReconnectChallengeResponse::Ok {
+            challenge: self.challenge,
+            checksum_salt: rand::random(),
+        }
+    }
+
+    /// Verifies that the client knows the session key of the account.
+    ///
+    /// The proofs are compared in constant time, so the time taken doesn't tell how much matched.
+    pub fn verify(&self, request: &ReconnectProofRequest) -> bool {
+        let expected = reconnect_proof(&self.account, &request.proof_data, &self.challenge, &self.session_key);
+        expected.ct_eq(&request.proof).into()
+    }
+
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    pub fn session_key(&self) -> &SessionKey {
+        &self.session_key
+    }
+}
+
+/// Answers a server's reconnect challenge. This is the client side of a reconnection attempt.
+///
+/// # Arguments
+///
+/// - `account`: The name of the account, as sent in the reconnect challenge.
+/// - `challenge`: The challenge received from the server.
+/// - `session_key`: The session key of the previous login.
+pub fn answer_reconnect_challenge(account: &str, challenge: &[u8; 16], session_key: &SessionKey) -> ReconnectProofRequest {
+    let proof_data = rand::random();
+
+    ReconnectProofRequest {
+        proof_data,
+        proof: reconnect_proof(account, &proof_data, challenge, session_key),
+        checksum: [0; 20],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::session::{ReconnectChallenge, answer_reconnect_challenge};
+    use crate::grunt::protocol::ReconnectChallengeResponse;
+
+    #[test]
+    pub fn test_reconnect_proof() {
+        let key = [0x42; 40];
+        let server = ReconnectChallenge::new("POW", key);
+        let ReconnectChallengeResponse::Ok { challenge, .. } = server.response() else {
+            panic!("Expected a challenge");
+        };
+
+        assert!(server.verify(&answer_reconnect_challenge("POW", &challenge, &key)));
+        assert!(!server.verify(&answer_reconnect_challenge("POW", &challenge, &[0x43; 40])));
+        assert!(!server.verify(&answer_reconnect_challenge("OTHER", &challenge, &key)));
+        assert!(!server.verify(&answer_reconnect_challenge("POW", &[0; 16], &key)));
+    }
+}
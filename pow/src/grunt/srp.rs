@@ -0,0 +1,224 @@
+#![allow(unused_imports)]
+
+mod client;
+mod server;
+
+pub use client::*;
+pub use server::*;
+
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+
+use crate::grunt::session::SessionKey;
+
+/// The large safe prime used by every Grunt server, in little-endian order.
+pub const LARGE_SAFE_PRIME: [u8; 32] = [
+    0xB7, 0x9B, 0x3E, 0x2A, 0x87, 0x82, 0x3C, 0xAB, 0x8F, 0x5E, 0xBF, 0xBF, 0x8E, 0xB1, 0x01, 0x08,
+    0x53, 0x50, 0x06, 0x29, 0x8B, 0x5B, 0xAD, 0xBD, 0x5B, 0x53, 0xE1, 0x89, 0x5E, 0x64, 0x4B, 0x89,
+];
+
+/// The generator used by every Grunt server.
+pub const GENERATOR: u8 = 7;
+
+/// The multiplier parameter `k`. Grunt predates SRP6a and uses a constant instead of `H(N, g)`.
+const MULTIPLIER: u8 = 3;
+
+/// A salt, a verifier or a public key. All of these are sent in little-endian order.
+pub type Key = [u8; 32];
+
+/// A proof, either `M1` (sent by the client) or `M2` (sent by the server).
+pub type Proof = [u8; 20];
+
+fn large_safe_prime() -> BigUint {
+    BigUint::from_bytes_le(&LARGE_SAFE_PRIME)
+}
+
+/// Converts a number to a little-endian array, padding it with zeroes.
+fn to_array<const N: usize>(value: &BigUint) -> [u8; N] {
+    let mut array = [0; N];
+    let bytes = value.to_bytes_le();
+    array[..bytes.len()].copy_from_slice(&bytes);
+    array
+}
+
+/// Returns a random number in little-endian order.
+fn random_key() -> Key {
+    rand::random()
+}
+
+/// Computes `x = H(s | H(USERNAME | ":" | PASSWORD))`.
+fn private_key(username: &str, password: &str, salt: &Key) -> BigUint {
+    let credentials = Sha1::new()
+        .chain_update(username.to_uppercase())
+        .chain_update(":")
+        .chain_update(password.to_uppercase())
+        .finalize();
+
+    let digest = Sha1::new()
+        .chain_update(salt)
+        .chain_update(credentials)
+        .finalize();
+
+    BigUint::from_bytes_le(&digest)
+}
+
+/// Computes the verifier `v = g^x` of an account.
+///
+/// # Arguments
+///
+/// - `username`: The name of the account. This is case-insensitive.
+/// - `password`: The password of the account. This is case-insensitive.
+/// - `salt`: The salt of the account.
+pub fn compute_verifier(username: &str, password: &str, salt: &Key) -> Key {
+    let x = private_key(username, password, salt);
+    to_array(&BigUint::from(GENERATOR).modpow(&x, &large_safe_prime()))
+}
+
+/// Generates a random salt and computes the matching verifier. The password does
+/// not need to be stored once this is done.
+///
+/// Returns a tuple of `(salt, verifier)`.
+pub fn generate_verifier(username: &str, password: &str) -> (Key, Key) {
+    let salt = random_key();
+    (salt, compute_verifier(username, password, &salt))
+}
+
+/// Computes the scrambling parameter `u = H(A | B)`.
+fn scrambler(client_public_key: &Key, server_public_key: &Key) -> BigUint {
+    let digest = Sha1::new()
+        .chain_update(client_public_key)
+        .chain_update(server_public_key)
+        .finalize();
+
+    BigUint::from_bytes_le(&digest)
+}
+
+/// Derives the session key `K` from the shared secret `S`.
+///
+/// Grunt does not hash `S` directly. Leading zeroes are skipped, the remaining bytes are
+/// split into even and odd bytes, each half is hashed and the two digests are interleaved.
+fn interleave(secret: &Key) -> SessionKey {
+    let mut start = secret.iter().position(|&b| b != 0).unwrap_or(secret.len());
+    if start % 2 == 1 {
+        start += 1;
+    }
+
+    let even = secret[start..].iter().step_by(2).copied().collect::<Vec<_>>();
+    let odd = secret[start..].iter().skip(1).step_by(2).copied().collect::<Vec<_>>();
+
+    let even = Sha1::digest(&even);
+    let odd = Sha1::digest(&odd);
+
+    let mut session_key = [0; 40];
+    for i in 0..20 {
+        session_key[i * 2] = even[i];
+        session_key[i * 2 + 1] = odd[i];
+    }
+    session_key
+}
+
+/// Computes the client proof `M1 = H(H(N) ^ H(g) | H(USERNAME) | s | A | B | K)`.
+fn client_proof(
+    username: &str,
+    large_safe_prime: &[u8],
+    generator: &[u8],
+    salt: &Key,
+    client_public_key: &Key,
+    server_public_key: &Key,
+    session_key: &SessionKey
+) -> Proof {
+    let prime = Sha1::digest(large_safe_prime);
+    let generator = Sha1::digest(generator);
+
+    let mut parameters = [0; 20];
+    for (i, byte) in parameters.iter_mut().enumerate() {
+        *byte = prime[i] ^ generator[i];
+    }
+
+    Sha1::new()
+        .chain_update(parameters)
+        .chain_update(Sha1::digest(username.to_uppercase()))
+        .chain_update(salt)
+        .chain_update(client_public_key)
+        .chain_update(server_public_key)
+        .chain_update(session_key)
+        .finalize()
+        .into()
+}
+
+/// Computes the server proof `M2 = H(A | M1 | K)`.
+fn server_proof(client_public_key: &Key, client_proof: &Proof, session_key: &SessionKey) -> Proof {
+    Sha1::new()
+        .chain_update(client_public_key)
+        .chain_update(client_proof)
+        .chain_update(session_key)
+        .finalize()
+        .into()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::srp::{compute_verifier, interleave};
+
+    pub(super) const USERNAME: &str = "username123";
+    pub(super) const PASSWORD: &str = "PASSWORD123";
+
+    pub(super) const SALT: [u8; 32] = [
+        0x03, 0x0A, 0x11, 0x18, 0x1F, 0x26, 0x2D, 0x34, 0x3B, 0x42, 0x49, 0x50, 0x57, 0x5E, 0x65, 0x6C,
+        0x73, 0x7A, 0x81, 0x88, 0x8F, 0x96, 0x9D, 0xA4, 0xAB, 0xB2, 0xB9, 0xC0, 0xC7, 0xCE, 0xD5, 0xDC,
+    ];
+    pub(super) const VERIFIER: [u8; 32] = [
+        0x95, 0x78, 0xA0, 0x05, 0x87, 0x3A, 0x23, 0x2A, 0x61, 0xC5, 0x89, 0x43, 0x86, 0x11, 0xBE, 0x67,
+        0x6B, 0x8E, 0xC4, 0x94, 0xA4, 0xBF, 0x19, 0xD3, 0xE5, 0x4B, 0x2C, 0x18, 0x64, 0x60, 0xC8, 0x78,
+    ];
+    pub(super) const SERVER_PRIVATE_KEY: [u8; 32] = [
+        0x05, 0x12, 0x1F, 0x2C, 0x39, 0x46, 0x53, 0x60, 0x6D, 0x7A, 0x87, 0x94, 0xA1, 0xAE, 0xBB, 0xC8,
+        0xD5, 0xE2, 0xEF, 0xFC, 0x09, 0x16, 0x23, 0x30, 0x3D, 0x4A, 0x57, 0x64, 0x71, 0x7E, 0x8B, 0x98,
+    ];
+    pub(super) const SERVER_PUBLIC_KEY: [u8; 32] = [
+        0x72, 0x46, 0xE1, 0x08, 0xF9, 0x09, 0x46, 0xD2, 0x41, 0xAB, 0xA4, 0xDA, 0x52, 0x51, 0x7A, 0xC8,
+        0x57, 0xCE, 0xBC, 0x11, 0x2B, 0x1D, 0x22, 0x7D, 0x04, 0x83, 0xB3, 0xE8, 0xBF, 0xF6, 0x94, 0x55,
+    ];
+    pub(super) const CLIENT_PRIVATE_KEY: [u8; 32] = [
+        0x0B, 0x1C, 0x2D, 0x3E, 0x4F, 0x60, 0x71, 0x82, 0x93, 0xA4, 0xB5, 0xC6, 0xD7, 0xE8, 0xF9, 0x0A,
+        0x1B, 0x2C, 0x3D, 0x4E, 0x5F, 0x70, 0x81, 0x92, 0xA3, 0xB4, 0xC5, 0xD6, 0xE7, 0xF8, 0x09, 0x1A,
+    ];
+    pub(super) const CLIENT_PUBLIC_KEY: [u8; 32] = [
+        0xCC, 0x54, 0x58, 0xE3, 0x35, 0xC0, 0x5A, 0x9B, 0x63, 0xC4, 0xF2, 0xC8, 0x64, 0x54, 0xD2, 0x28,
+        0x1C, 0x4C, 0xC1, 0x9B, 0x89, 0x52, 0x0F, 0x12, 0x96, 0x5A, 0xB0, 0x48, 0xEF, 0xF7, 0x57, 0x31,
+    ];
+    pub(super) const SESSION_KEY: [u8; 40] = [
+        0xC6, 0x3E, 0x4C, 0x37, 0x3B, 0x3A, 0x86, 0xE2, 0x9A, 0x23, 0x80, 0xC3, 0x8F, 0xAC, 0x84, 0x74,
+        0xE2, 0x0B, 0x8B, 0xBA, 0xC2, 0x7A, 0xE6, 0x8F, 0xE4, 0xE7, 0x3C, 0x77, 0xC0, 0x11, 0x1D, 0x4E,
+        0x91, 0x4C, 0x1C, 0x35, 0xCD, 0x0B, 0x06, 0x74,
+    ];
+    pub(super) const CLIENT_PROOF: [u8; 20] = [
+        0x96, 0xD4, 0x9A, 0x0C, 0x39, 0xEF, 0x82, 0x67, 0xA0, 0x2B, 0x2C, 0x65, 0x0D, 0xFF, 0xC4, 0x9C,
+        0xAC, 0xDC, 0x21, 0xAF,
+    ];
+    pub(super) const SERVER_PROOF: [u8; 20] = [
+        0xDD, 0x71, 0xD4, 0x18, 0x5C, 0x00, 0x9D, 0x39, 0x85, 0xB6, 0x28, 0x6C, 0x4F, 0x7A, 0x45, 0xF9,
+        0x08, 0x25, 0x40, 0xF7,
+    ];
+
+    #[test]
+    pub fn test_verifier() {
+        assert_eq!(compute_verifier(USERNAME, PASSWORD, &SALT), VERIFIER);
+        assert_eq!(compute_verifier(USERNAME, "password123", &SALT), VERIFIER);
+        assert_ne!(compute_verifier(USERNAME, "PASSWORD124", &SALT), VERIFIER);
+    }
+
+    #[test]
+    pub fn test_interleave_skips_leading_zeroes() {
+        let mut secret = [0; 32];
+        for (i, byte) in secret.iter_mut().enumerate().skip(3) {
+            *byte = i as u8 - 2;
+        }
+
+        assert_eq!(interleave(&secret), [
+            0x6D, 0x32, 0xC8, 0x9C, 0x1F, 0x54, 0xE5, 0xAF, 0x2D, 0xCE, 0xC0, 0x68, 0x5B, 0x56, 0xE4, 0xCC,
+            0xC2, 0x78, 0xCC, 0x01, 0xCD, 0xA3, 0x35, 0x96, 0x74, 0x5A, 0x2A, 0xEF, 0x74, 0x98, 0x04, 0x45,
+            0xFC, 0x11, 0xEE, 0xD8, 0x3A, 0xBB, 0xAC, 0x49,
+        ]);
+    }
+}
@@ -0,0 +1,183 @@
+use anyhow::{Result, bail};
+use num_bigint::BigUint;
+
+use crate::grunt::protocol::{LogonChallengeResponse, LogonProofRequest, SecurityProof};
+use crate::grunt::session::SessionKey;
+use crate::grunt::srp::{
+    Key, MULTIPLIER, Proof,
+    client_proof, interleave, private_key, random_key, scrambler, server_proof, to_array
+};
+
+/// The client side of an SRP6 exchange.
+pub struct SrpClient {
+    username: String,
+    password: String,
+}
+
+/// The state of the client once it answered the server's challenge.
+pub struct SrpClientProof {
+    /// The ephemeral public key `A`.
+    pub public_key: Key,
+    /// `M1`, which proves to the server that the client knows the password.
+    pub proof: Proof,
+    /// The key both ends will share if the server accepts the proof.
+    pub session_key: SessionKey,
+    server_proof: Proof,
+}
+
+impl SrpClient {
+    /// # Arguments
+    ///
+    /// - `username`: The name of the account. This is case-insensitive.
+    /// - `password`: The password of the account. This is case-insensitive.
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    /// Answers the parameters of a successful [`LogonChallengeResponse`].
+    pub fn respond(&self, response: &LogonChallengeResponse) -> Result<SrpClientProof> {
+        match response {
+            LogonChallengeResponse::Ok { public_key, generator, large_safe_prime, salt, .. } => {
+                self.respond_with_private_key(public_key, generator, large_safe_prime, salt, random_key())
+            },
+            LogonChallengeResponse::Err(result) => bail!("The server rejected the challenge: {:?}", result),
+        }
+    }
+
+    fn respond_with_private_key(
+        &self,
+        server_public_key: &Key,
+        generator: &[u8],
+        large_safe_prime: &[u8],
+        salt: &Key,
+        ephemeral_key: Key
+    ) -> Result<SrpClientProof> {
+        if large_safe_prime.len() != 32 {
+            bail!("Large safe primes of {} bytes are not supported", large_safe_prime.len());
+        }
+
+        let prime = BigUint::from_bytes_le(large_safe_prime);
+        let g = BigUint::from_bytes_le(generator);
+
+        // Weak parameters would let the server predict the shared secret.
+        if prime == BigUint::ZERO {
+            bail!("The server sent an invalid large safe prime");
+        }
+
+        if g <= BigUint::from(1u8) {
+            bail!("The server sent an invalid generator");
+        }
+        let server_key = BigUint::from_bytes_le(server_public_key);
+
+        // A server that sends a multiple of N forces the shared secret to zero.
+        if &server_key % &prime == BigUint::ZERO {
+            bail!("The server sent an invalid public key");
+        }
+
+        let a = BigUint::from_bytes_le(&ephemeral_key);
+        let public_key: Key = to_array(&g.modpow(&a, &prime));
+
+        let x = private_key(&self.username, &self.password, salt);
+        let u = scrambler(&public_key, server_public_key);
+
+        // S = (B - k * g^x)^(a + u * x)
+        let base = (&server_key % &prime + &prime - BigUint::from(MULTIPLIER) * g.modpow(&x, &prime) % &prime) % &prime;
+        let secret = base.modpow(&(a + u * x), &prime);
+
+        let session_key = interleave(&to_array(&secret));
+        let proof = client_proof(
+            &self.username,
+            large_safe_prime,
+            generator,
+            salt,
+            &public_key,
+            server_public_key,
+            &session_key
+        );
+
+        Ok(SrpClientProof {
+            public_key,
+            proof,
+            session_key,
+            server_proof: server_proof(&public_key, &proof, &session_key),
+        })
+    }
+}
+
+impl SrpClientProof {
+    /// Returns the request that carries this proof.
+    ///
+    /// # Arguments
+    ///
+    /// - `security`: The answer to the second factor requested by the server, if any.
+    pub fn request(&self, security: Vec<SecurityProof>) -> LogonProofRequest {
+        LogonProofRequest {
+            public_key: self.public_key,
+            proof: self.proof,
+            crc: [0; 20],
+            telemetry_keys: vec![],
+            security,
+        }
+    }
+
+    /// Verifies the proof `M2` sent by the server.
+    pub fn verify(&self, server_proof: &Proof) -> bool {
+        &self.server_proof == server_proof
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::srp::{GENERATOR, LARGE_SAFE_PRIME, SrpClient, SrpServer};
+    use crate::grunt::srp::test::*;
+
+    #[test]
+    pub fn test_client_vectors() {
+        let client = SrpClient::new(USERNAME, PASSWORD);
+        let proof = client.respond_with_private_key(
+            &SERVER_PUBLIC_KEY,
+            &[GENERATOR],
+            &LARGE_SAFE_PRIME,
+            &SALT,
+            CLIENT_PRIVATE_KEY
+        ).expect("Parameters should be valid");
+
+        assert_eq!(proof.public_key, CLIENT_PUBLIC_KEY);
+        assert_eq!(proof.session_key, SESSION_KEY);
+        assert_eq!(proof.proof, CLIENT_PROOF);
+        assert!(proof.verify(&SERVER_PROOF));
+        assert!(!proof.verify(&[0; 20]));
+    }
+
+    #[test]
+    pub fn test_weak_parameters() {
+        let client = SrpClient::new(USERNAME, PASSWORD);
+        for (generator, prime) in [
+            (&[GENERATOR][..], &LARGE_SAFE_PRIME[..16]),
+            (&[GENERATOR][..], &[0; 32][..]),
+            (&[0][..], &LARGE_SAFE_PRIME[..]),
+            (&[1][..], &LARGE_SAFE_PRIME[..]),
+            (&[][..], &LARGE_SAFE_PRIME[..]),
+        ] {
+            let result = client.respond_with_private_key(&SERVER_PUBLIC_KEY, generator, prime, &SALT, CLIENT_PRIVATE_KEY);
+            assert!(result.is_err(), "Parameters should be rejected");
+        }
+    }
+
+    #[test]
+    pub fn test_client_server_agree() {
+        let server = SrpServer::new(USERNAME, SALT, VERIFIER);
+        let response = server.challenge(vec![]);
+
+        let proof = SrpClient::new(USERNAME, PASSWORD).respond(&response).expect("Parameters should be valid");
+        let session = server.verify_proof(&proof.public_key, &proof.proof).expect("Proof should be valid");
+        assert_eq!(session.session_key, proof.session_key);
+        assert!(proof.verify(&session.proof));
+
+        let proof = SrpClient::new(USERNAME, "wrong").respond(&response).expect("Parameters should be valid");
+        assert!(server.verify_proof(&proof.public_key, &proof.proof).is_none());
+    }
+}
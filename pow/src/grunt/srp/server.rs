@@ -0,0 +1,155 @@
+use num_bigint::BigUint;
+use subtle::ConstantTimeEq;
+
+use crate::grunt::protocol::{LogonChallengeResponse, LogonProofRequest, SecurityChallenge};
+use crate::grunt::session::SessionKey;
+use crate::grunt::srp::{
+    GENERATOR, Key, LARGE_SAFE_PRIME, MULTIPLIER, Proof,
+    client_proof, interleave, large_safe_prime, random_key, scrambler, server_proof, to_array
+};
+
+/// The server side of an SRP6 exchange.
+///
+/// One instance is created per logon challenge and kept until the client sends its proof.
+pub struct SrpServer {
+    username: String,
+    salt: Key,
+    verifier: BigUint,
+    private_key: BigUint,
+    public_key: Key,
+}
+
+/// The outcome of a successful SRP6 exchange.
+pub struct SrpServerSession {
+    /// The key both ends now share.
+    pub session_key: SessionKey,
+    /// `M2`, which proves to the client that the server knows the verifier.
+    pub proof: Proof,
+}
+
+impl SrpServer {
+    /// Creates the server side of an exchange for the given account.
+    ///
+    /// # Arguments
+    ///
+    /// - `username`: The name of the account, as sent in the logon challenge.
+    /// - `salt`: The salt of the account.
+    /// - `verifier`: The verifier of the account.
+    pub fn new(username: &str, salt: Key, verifier: Key) -> Self {
+        Self::with_private_key(username, salt, verifier, random_key())
+    }
+
+    fn with_private_key(username: &str, salt: Key, verifier: Key, private_key: Key) -> Self {
+        let prime = large_safe_prime();
+        let verifier = BigUint::from_bytes_le(&verifier);
+        let private_key = BigUint::from_bytes_le(&private_key);
+
+        // B = k * v + g^b
+        let public_key = (BigUint::from(MULTIPLIER) * &verifier
+            + BigUint::from(GENERATOR).modpow(&private_key, &prime)) % &prime;
+
+        Self {
+            username: username.to_string(),
+            salt,
+            verifier,
+            private_key,
+            public_key: to_array(&public_key),
+        }
+    }
+
+    /// The ephemeral public key `B`.
+    pub fn public_key(&self) -> &Key {
+        &self.public_key
+    }
+
+    pub fn salt(&self) -> &Key {
+        &self.salt
+    }
+
+    /// Returns the response to the logon challenge that initiated this exchange.
+    ///
+    /// # Arguments
+    ///
+    /// - `security`: The second factor the client will have to provide along with its proof.
+    pub fn challenge(&self, security: Vec<SecurityChallenge>) -> LogonChallengeResponse {
+        LogonChallengeResponse::Ok {
+            public_key: self.public_key,
+            generator: Box::new([GENERATOR]),
+            large_safe_prime: Box::new(LARGE_SAFE_PRIME),
+            salt: self.salt,
+            crc: rand::random(),
+            security,
+        }
+    }
+
+    /// Verifies the proof `M1` sent by the client.
+    ///
+    /// Returns the session key and the server proof `M2` if the client knows the password
+    /// of the account, or `None` otherwise.
+    pub fn verify(&self, request: &LogonProofRequest) -> Option<SrpServerSession> {
+        self.verify_proof(&request.public_key, &request.proof)
+    }
+
+    /// Verifies a client's public key `A` and its proof `M1`.
+    pub fn verify_proof(&self, client_public_key: &Key, proof: &Proof) -> Option<SrpServerSession> {
+        let prime = large_safe_prime();
+        let client_key = BigUint::from_bytes_le(client_public_key);
+
+        // A client that sends a multiple of N forces the shared secret to zero.
+        if &client_key % &prime == BigUint::ZERO {
+            return None;
+        }
+
+        // S = (A * v^u)^b
+        let u = scrambler(client_public_key, &self.public_key);
+        let secret = (client_key * self.verifier.modpow(&u, &prime)).modpow(&self.private_key, &prime);
+
+        let session_key = interleave(&to_array(&secret));
+        let expected = client_proof(
+            &self.username,
+            &LARGE_SAFE_PRIME,
+            &[GENERATOR],
+            &self.salt,
+            client_public_key,
+            &self.public_key,
+            &session_key
+        );
+
+        // Compared in constant time, so the time taken doesn't tell how much of the proof matched.
+        if !bool::from(expected.ct_eq(proof)) {
+            return None;
+        }
+
+        Some(SrpServerSession {
+            session_key,
+            proof: server_proof(client_public_key, proof, &session_key),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::srp::SrpServer;
+    use crate::grunt::srp::test::*;
+
+    #[test]
+    pub fn test_server_vectors() {
+        let server = SrpServer::with_private_key(USERNAME, SALT, VERIFIER, SERVER_PRIVATE_KEY);
+        assert_eq!(server.public_key(), &SERVER_PUBLIC_KEY);
+
+        let session = server.verify_proof(&CLIENT_PUBLIC_KEY, &CLIENT_PROOF).expect("Proof should be valid");
+        assert_eq!(session.session_key, SESSION_KEY);
+        assert_eq!(session.proof, SERVER_PROOF);
+
+        let mut invalid = CLIENT_PROOF;
+        invalid[0] ^= 1;
+        assert!(server.verify_proof(&CLIENT_PUBLIC_KEY, &invalid).is_none());
+    }
+
+    #[test]
+    pub fn test_server_rejects_zero_public_key() {
+        let server = SrpServer::new(USERNAME, SALT, VERIFIER);
+        assert!(server.verify_proof(&[0; 32], &CLIENT_PROOF).is_none());
+        assert!(server.verify_proof(&crate::grunt::srp::LARGE_SAFE_PRIME, &CLIENT_PROOF).is_none());
+    }
+}
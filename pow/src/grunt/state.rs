@@ -0,0 +1,135 @@
+use core::fmt;
+
+/// The progress of a client through authentication, as seen by a server.
+///
+/// Servers have every command checked against the state returned by
+/// [`GruntProtocol::auth_state`] before it is dispatched; commands sent out of order are
+/// rejected with an [`UnexpectedCommand`] error, which closes the connection.
+///
+/// [`GruntProtocol::auth_state`]: crate::grunt::protocol::GruntProtocol::auth_state
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum AuthState {
+    /// Nothing was received yet, or the last attempt was rejected or told to download a
+    /// patch. The client may log in, reconnect, or answer a patch offer.
+    #[default]
+    AwaitingChallenge,
+    /// The client sent a logon challenge and must now send its proof.
+    AwaitingProof,
+    /// The client sent a reconnect challenge and must now send its proof.
+    AwaitingReconnectProof,
+    /// The client sent its proof and the server has yet to accept it with
+    /// [`AuthState::authenticate`] or to reject it with [`AuthState::reject`].
+    Verifying,
+    /// The client proved its identity and may request the realm list.
+    Authenticated,
+}
+
+impl AuthState {
+    /// Moves to the next state upon receiving the given command from the client.
+    pub fn advance(&mut self, command: u8) -> Result<(), UnexpectedCommand> {
+        *self = match (*self, command) {
+            (Self::AwaitingChallenge, 0x00) => Self::AwaitingProof,
+            (Self::AwaitingChallenge, 0x02) => Self::AwaitingReconnectProof,
+            (Self::AwaitingProof, 0x01) => Self::Verifying,
+            (Self::AwaitingReconnectProof, 0x03) => Self::Verifying,
+            (Self::AwaitingChallenge | Self::Authenticated, 0x32..=0x34) => *self,
+            (Self::Authenticated, 0x10) => Self::Authenticated,
+            (state, command) => return Err(UnexpectedCommand { command, state }),
+        };
+
+        Ok(())
+    }
+
+    /// Accepts the proof of the client. This is called by the server once it verified it.
+    pub fn authenticate(&mut self) {
+        if *self == Self::Verifying {
+            *self = Self::Authenticated;
+        }
+    }
+
+    /// Rejects the proof of the client, which may then start over with a new challenge. This
+    /// is also how a client that has to download a patch is sent back to the start.
+    pub fn reject(&mut self) {
+        if *self == Self::Verifying {
+            *self = Self::AwaitingChallenge;
+        }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        *self == Self::Authenticated
+    }
+}
+
+/// A client sent a command its [`AuthState`] does not allow.
+#[derive(Debug)]
+pub struct UnexpectedCommand {
+    pub command: u8,
+    pub state: AuthState,
+}
+
+impl fmt::Display for UnexpectedCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unexpected command {:#04X} in state {:?}", self.command, self.state)
+    }
+}
+
+impl std::error::Error for UnexpectedCommand { }
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::state::AuthState;
+
+    #[test]
+    pub fn test_login() {
+        let mut state = AuthState::default();
+        state.advance(0x00).expect("Challenge should be accepted");
+        assert_eq!(state, AuthState::AwaitingProof);
+        state.advance(0x01).expect("Proof should be accepted");
+        assert_eq!(state, AuthState::Verifying);
+        state.advance(0x10).expect_err("Realm list should require authentication");
+
+        let mut state = AuthState::AwaitingProof;
+        state.advance(0x01).expect("Proof should be accepted");
+        state.authenticate();
+        assert!(state.is_authenticated());
+        state.advance(0x10).expect("Realm list should be accepted");
+        state.advance(0x10).expect("Realm list should be accepted again");
+    }
+
+    #[test]
+    pub fn test_reconnect() {
+        let mut state = AuthState::default();
+        state.advance(0x02).expect("Reconnect challenge should be accepted");
+        state.advance(0x01).expect_err("Logon proof should be rejected");
+
+        let mut state = AuthState::AwaitingReconnectProof;
+        state.advance(0x03).expect("Reconnect proof should be accepted");
+        state.authenticate();
+        assert!(state.is_authenticated());
+    }
+
+    #[test]
+    pub fn test_out_of_order() {
+        let err = AuthState::default().advance(0x01).expect_err("Proof should require a challenge");
+        assert_eq!(err.to_string(), "Unexpected command 0x01 in state AwaitingChallenge");
+
+        let mut state = AuthState::AwaitingProof;
+        state.advance(0x00).expect_err("Challenges should not be repeated");
+        AuthState::Authenticated.advance(0x00).expect_err("Challenges should not be repeated");
+        AuthState::AwaitingProof.advance(0x32).expect_err("Transfers should not interrupt a login");
+        AuthState::Verifying.advance(0x34).expect_err("Transfers should not interrupt a login");
+
+        // A rejected proof starts over.
+        let mut state = AuthState::AwaitingProof;
+        state.advance(0x01).expect("Proof should be accepted");
+        state.reject();
+        assert_eq!(state, AuthState::AwaitingChallenge);
+        state.advance(0x10).expect_err("Realm list should require authentication");
+        state.advance(0x00).expect("Challenge should be accepted again");
+
+        // The authentication is not accepted until a proof was received.
+        let mut state = AuthState::AwaitingProof;
+        state.authenticate();
+        assert!(!state.is_authenticated());
+    }
+}
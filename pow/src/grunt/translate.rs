@@ -0,0 +1,234 @@
+use crate::grunt::builds::ClientBuild;
+use crate::grunt::protocol::{
+    GruntVersion, LoginResult, LogonChallengeRequest, LogonChallengeResponse, LogonProofRequest, LogonProofResponse,
+    Realm, RealmFlags, RealmlistRequest, RealmlistResponse, ReconnectChallengeRequest, ReconnectChallengeResponse,
+    ReconnectProofRequest, ReconnectProofResponse
+};
+
+/// A Grunt message that can be forwarded to a peer speaking another protocol revision.
+///
+/// Messages are decoded with the version of the peer that sent them, translated, and then
+/// encoded with the version of the peer they are forwarded to. Encoding already takes care
+/// of the layout of each revision; translating drops or replaces whatever the target
+/// revision cannot represent.
+pub trait Translate: Sized {
+    /// # Arguments
+    ///
+    /// - `target`: The version of the peer the message is forwarded to.
+    fn translate(self, target: GruntVersion) -> Self;
+}
+
+impl Translate for LoginResult {
+    fn translate(self, target: GruntVersion) -> Self {
+        self.nearest(target)
+    }
+}
+
+impl Translate for LogonChallengeRequest {
+    /// Servers check that the version of a client matches the revision it speaks, so clients
+    /// of another revision are passed off as the most recent known build that speaks it.
+    /// Revisions without a known build are left as they are.
+    fn translate(self, target: GruntVersion) -> Self {
+        match ClientBuild::find(self.version.build) {
+            Some(build) if build.protocol == Some(target) => self,
+            _ => match ClientBuild::latest_for(target) {
+                Some(build) => LogonChallengeRequest { version: build.version, ..self },
+                None => self,
+            },
+        }
+    }
+}
+
+impl Translate for LogonChallengeResponse {
+    /// Clients without security flags cannot answer a second factor, and are turned away
+    /// with [`LoginResult::InvalidVersion`] if the server requires one.
+    fn translate(self, target: GruntVersion) -> Self {
+        match self {
+            LogonChallengeResponse::Ok { security, .. }
+                if !security.is_empty() && !target.has_security_flags() =>
+            {
+                LogonChallengeResponse::Err(LoginResult::InvalidVersion.translate(target))
+            },
+            LogonChallengeResponse::Ok { .. } => self,
+            LogonChallengeResponse::Err(result) => LogonChallengeResponse::Err(result.translate(target)),
+        }
+    }
+}
+
+impl Translate for LogonProofRequest {
+    fn translate(self, target: GruntVersion) -> Self {
+        if target.has_security_flags() {
+            self
+        } else {
+            LogonProofRequest { security: vec![], ..self }
+        }
+    }
+}
+
+impl Translate for LogonProofResponse {
+    fn translate(self, target: GruntVersion) -> Self {
+        match self {
+            LogonProofResponse::Ok { proof, account_flags, hardware_survey_id, unknown_flags } => LogonProofResponse::Ok {
+                proof,
+                account_flags: if target.has_account_flags() { account_flags } else { 0 },
+                hardware_survey_id,
+                unknown_flags: if target.has_proof_flags() { unknown_flags } else { 0 },
+            },
+            LogonProofResponse::Err(result) => LogonProofResponse::Err(result.translate(target)),
+        }
+    }
+}
+
+impl Translate for RealmlistRequest {
+    fn translate(self, _: GruntVersion) -> Self {
+        self
+    }
+}
+
+impl Translate for Realm {
+    fn translate(self, target: GruntVersion) -> Self {
+        if target.has_extended_realmlist() {
+            self
+        } else {
+            Realm {
+                locked: false,
+                flags: self.flags.without(RealmFlags::SPECIFY_BUILD),
+                build: None,
+                ..self
+            }
+        }
+    }
+}
+
+impl Translate for RealmlistResponse {
+    /// Legacy clients count realms with a single byte; realms past the 255th are dropped.
+    fn translate(self, target: GruntVersion) -> Self {
+        let limit = if target.has_extended_realmlist() { u16::MAX as usize } else { u8::MAX as usize };
+
+        RealmlistResponse {
+            realms: self.realms.into_iter()
+                .take(limit)
+                .map(|realm| realm.translate(target))
+                .collect(),
+        }
+    }
+}
+
+impl Translate for ReconnectChallengeRequest {
+    fn translate(self, target: GruntVersion) -> Self {
+        Self(self.0.translate(target))
+    }
+}
+
+impl Translate for ReconnectChallengeResponse {
+    fn translate(self, target: GruntVersion) -> Self {
+        match self {
+            ReconnectChallengeResponse::Err(result) => ReconnectChallengeResponse::Err(result.translate(target)),
+            _ => self,
+        }
+    }
+}
+
+impl Translate for ReconnectProofRequest {
+    fn translate(self, _: GruntVersion) -> Self {
+        self
+    }
+}
+
+impl Translate for ReconnectProofResponse {
+    fn translate(self, target: GruntVersion) -> Self {
+        Self(self.0.translate(target))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::protocol::{
+        GruntVersion, LoginResult, LogonChallengeRequest, LogonChallengeResponse, LogonProofResponse, Realm, RealmFlags,
+        RealmlistResponse, SecurityChallenge
+    };
+    use crate::grunt::test::challenge_request;
+    use crate::grunt::translate::Translate;
+
+    fn realm(id: u8) -> Realm {
+        Realm {
+            realm_type: 1,
+            locked: true,
+            flags: RealmFlags::RECOMMENDED,
+            name: format!("Realm {}", id),
+            address: "127.0.0.1:8085".to_string(),
+            population: 1.0,
+            characters: 2,
+            category: 3,
+            id,
+            build: Some("3.3.5.12340".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    pub fn test_challenge_response() {
+        let response = LogonChallengeResponse::Ok {
+            public_key: [1; 32],
+            generator: Box::new([7]),
+            large_safe_prime: Box::new([2; 32]),
+            salt: [3; 32],
+            crc: [4; 16],
+            security: vec![SecurityChallenge::Authenticator(1)],
+        };
+
+        assert!(matches!(response.translate(GruntVersion::V2), LogonChallengeResponse::Err(LoginResult::InvalidVersion)));
+        assert!(matches!(
+            LogonChallengeResponse::Err(LoginResult::LockedEnforced).translate(GruntVersion::V3),
+            LogonChallengeResponse::Err(LoginResult::Suspended)
+        ));
+    }
+
+    #[test]
+    pub fn test_challenge_request() {
+        let vanilla = || LogonChallengeRequest { version: "1.12.1.5875".parse().unwrap(), ..challenge_request("pow") };
+
+        let translated = vanilla().translate(GruntVersion::V8);
+        assert_eq!(translated.version, "4.3.4.15595".parse().unwrap());
+        assert_eq!(translated.account_name, "pow");
+        assert_eq!(vanilla().translate(GruntVersion::V3).version, vanilla().version);
+        assert_eq!(vanilla().translate(GruntVersion::V2).version, vanilla().version);
+
+        assert_eq!(challenge_request("pow").translate(GruntVersion::V8).version, challenge_request("pow").version);
+        assert_eq!(challenge_request("pow").translate(GruntVersion::V3).version, "1.12.3.6141".parse().unwrap());
+    }
+
+    #[test]
+    pub fn test_proof_response() {
+        let response = LogonProofResponse::Ok {
+            proof: [1; 20],
+            account_flags: 0x01,
+            hardware_survey_id: 2,
+            unknown_flags: 3,
+        };
+
+        assert_eq!(response.translate(GruntVersion::V3), LogonProofResponse::Ok {
+            proof: [1; 20],
+            account_flags: 0,
+            hardware_survey_id: 2,
+            unknown_flags: 0,
+        });
+        assert_eq!(
+            LogonProofResponse::Err(LoginResult::LockedEnforced).translate(GruntVersion::V3),
+            LogonProofResponse::Err(LoginResult::Suspended)
+        );
+    }
+
+    #[test]
+    pub fn test_realmlist_response() {
+        let response = || RealmlistResponse { realms: (0..257).map(|id| realm(id as u8)).collect() };
+
+        let legacy = response().translate(GruntVersion::V3);
+        assert_eq!(legacy.realms.len(), 255);
+        assert!(legacy.realms.iter().all(|realm| !realm.locked && realm.build.is_none()));
+        assert_eq!(legacy.realms[0].flags, RealmFlags::RECOMMENDED);
+
+        let modern = response().translate(GruntVersion::V8);
+        assert_eq!(modern.realms.len(), 257);
+        assert!(modern.realms.iter().all(|realm| realm.locked && realm.build.is_some()));
+    }
+}
@@ -1,17 +1,33 @@
-use std::{fs::File, io::BufReader, path::PathBuf};
-use anyhow::Result;
-use clap::Parser;
+// The codebase deliberately spells out `impl Future<...> + Send` in trait implementations.
+#![allow(clippy::manual_async_fn)]
+
+use std::{fs::File, io::{BufReader, IsTerminal}, net::{IpAddr, Ipv4Addr}, path::PathBuf, sync::Arc, time::Duration};
+use anyhow::{Result, anyhow, bail};
+use clap::{Parser, Subcommand};
 use console_subscriber::ConsoleLayer;
 use tokio::{runtime::Builder, task::JoinSet};
+use tokio_util::sync::CancellationToken;
 use tracing::{Level, error, info, level_filters::LevelFilter};
 use tracing_subscriber::{fmt, prelude::*};
 
-use crate::{options::{Configuration, Pipe, Protocol}};
+use crate::{options::{Configuration, MatrixCardOptions, Pipe, Protocol}};
+use crate::grunt::accounts::{Account, AccountStore, JsonAccountStore};
+use crate::grunt::bans::{Ban, BanList};
+use crate::grunt::builds::ClientBuild;
+use crate::grunt::local_auth::LocalAuthServer;
+use crate::grunt::login::LoginProtocol;
+use crate::grunt::patch::PatchDirectory;
+use crate::grunt::protocol::{Game, Locale, LogonChallengeRequest, Os, Platform};
+use crate::grunt::relay::RelayServer;
+use crate::network::{LocalPeer, Service};
+use crate::network::connection::Client;
+use crate::world::client::enter_world;
 
 mod packets;
 mod options;
 mod grunt;
 mod network;
+mod world;
 
 // Use of a mod or pub mod is not actually necessary.
 pub mod built_info {
@@ -37,6 +53,66 @@ struct CommandLine {
     /// A path to the configuration file for this instance of the `pow` proxy.
     #[arg(long, value_name = "FILE")]
     config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints the matrix card of an account, as configured in the `matrix_card` section.
+    MatrixCard {
+        /// The name of the account.
+        account: String,
+    },
+    /// Logs into an authentication server and enters the world of one of its realms, to check
+    /// that both can be reached. The password is prompted for, or read from the standard input.
+    EnterWorld {
+        /// The address of the authentication server, such as `127.0.0.1:3724`.
+        server: String,
+        /// The name of the account.
+        account: String,
+        /// The client to pass off as, by its common name.
+        #[arg(long, default_value = "3.3.5a")]
+        client: String,
+        /// The name of the realm to enter. The first realm advertised is entered otherwise.
+        #[arg(long)]
+        realm: Option<String>,
+    },
+    /// Manages the accounts of local authentication servers. Running servers pick up the
+    /// changes within a few seconds.
+    #[command(subcommand)]
+    Account(AccountCommand),
+}
+
+#[derive(Subcommand)]
+enum AccountCommand {
+    /// Creates an account. The password is prompted for, or read from the standard input.
+    Add {
+        name: String,
+        /// A PIN of 4 to 10 digits the account must provide after its password.
+        #[arg(long)]
+        pin: Option<String>,
+    },
+    /// Changes the password of an account. The password is prompted for, or read from the
+    /// standard input.
+    Passwd {
+        name: String,
+    },
+    /// Bans an account, or lifts its ban.
+    Ban {
+        name: String,
+        /// Lifts the ban after this many days. Bans are permanent otherwise.
+        #[arg(long, conflicts_with = "lift")]
+        days: Option<u64>,
+        #[arg(long, conflicts_with = "lift")]
+        reason: Option<String>,
+        /// Lifts the ban instead.
+        #[arg(long)]
+        lift: bool,
+    },
+    /// Lists all accounts.
+    List,
 }
 
 fn open_configuration(path: Option<PathBuf>) -> anyhow::Result<Configuration> {
@@ -50,8 +126,18 @@ fn open_configuration(path: Option<PathBuf>) -> anyhow::Result<Configuration> {
 }
 
 async fn main_impl(configuration: Configuration) -> anyhow::Result<()> {
+    let bans = match &configuration.bans {
+        Some(path) => {
+            let bans = BanList::load(path)?;
+            tokio::spawn(bans.clone().watch(Duration::from_secs(5)));
+            bans
+        },
+        None => BanList::default(),
+    };
+
+    let matrix_cards = configuration.matrix_card.map(Arc::new);
     let tasks: JoinSet<_> = configuration.pipes.into_iter()
-        .map(create_pipe)
+        .map(|pipe| create_pipe(pipe, configuration.accounts.clone(), bans.clone(), matrix_cards.clone()))
         .collect();
 
     // Check the return codes
@@ -108,6 +194,10 @@ fn main() -> Result<()> {
         }
     };
 
+    if let Some(command) = command_line.command {
+        return run_command(command, configuration);
+    }
+
     Builder::new_current_thread()
         .thread_name("main")
         .enable_all()
@@ -116,13 +206,185 @@ fn main() -> Result<()> {
         .block_on(main_impl(configuration))
 }
 
-async fn create_pipe(pipe: Pipe) -> Result<()> {
-    let handler = match pipe.source {
-        Protocol::Grunt { host } => {
-            unimplemented!("Rewrite in progress")
+fn run_command(command: Command, configuration: Configuration) -> Result<()> {
+    match command {
+        Command::MatrixCard { account } => {
+            let Some(options) = configuration.matrix_card else {
+                bail!("The configuration file does not have a `matrix_card` section");
+            };
+
+            print!("{}", options.card(&account)?);
+        },
+        Command::EnterWorld { server, account, client, realm } => {
+            let password = read_password(&account, false)?;
+            Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(run_enter_world(&server, &account, &password, &client, realm.as_deref()))?;
+        },
+        Command::Account(command) => run_account_command(command, &JsonAccountStore::open(&configuration.accounts)?)?,
+    }
+
+    Ok(())
+}
+
+async fn run_enter_world(server: &str, account: &str, password: &str, client: &str, realm: Option<&str>) -> Result<()> {
+    let Some(build) = ClientBuild::find_by_name(client) else {
+        bail!("Unknown client {}", client);
+    };
+    let Some(version) = build.protocol else {
+        bail!("{} clients do not log in through Grunt", build.name);
+    };
+
+    let mut login_client = Client::connect(server, LoginProtocol::new(version, password, true), CancellationToken::new()).await?;
+    let address = match login_client.ip() {
+        IpAddr::V4(address) => address,
+        IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+    };
+    let login = login_client.login(LogonChallengeRequest {
+        game: Game::WorldOfWarcraft,
+        version: build.version,
+        platform: Platform::X86,
+        os: Os::Windows,
+        locale: Locale::EnUs,
+        timezone: 0,
+        address,
+        account_name: account.to_string(),
+    }).await?;
+    login_client.disconnect().await?;
+
+    let realm = match realm {
+        Some(name) => login.realms.iter().find(|realm| realm.name.eq_ignore_ascii_case(name)),
+        None => login.realms.first(),
+    };
+    let realm = realm.ok_or_else(|| anyhow!("The realm is not advertised by {}", server))?;
+
+    info!("Logged in as {}, entering {} at {}", account.to_uppercase(), realm.name, realm.address);
+    let result = enter_world(&realm.address, build, account, login.session_key, CancellationToken::new()).await?;
+    println!("{:?}", result);
+
+    Ok(())
+}
+
+fn run_account_command(command: AccountCommand, store: &impl AccountStore) -> Result<()> {
+    match command {
+        AccountCommand::Add { name, pin } => {
+            if store.get(&name).is_some() {
+                bail!("The account {} already exists", name);
+            }
+
+            let mut account = Account::new(&name, &read_password(&name, true)?);
+            if let Some(pin) = pin {
+                account = account.with_pin(&pin)?;
+            }
+            store.put(account)?;
+        },
+        AccountCommand::Passwd { name } => {
+            let Some(mut account) = store.get(&name) else {
+                bail!("The account {} does not exist", name);
+            };
+
+            account.set_password(&read_password(&name, true)?);
+            store.put(account)?;
+        },
+        AccountCommand::Ban { name, days, reason, lift } => {
+            let Some(mut account) = store.get(&name) else {
+                bail!("The account {} does not exist", name);
+            };
+
+            account.ban = if lift {
+                None
+            } else {
+                Some(Ban::new(days.map(|days| Duration::from_secs(days * 86400)), reason))
+            };
+            store.put(account)?;
         },
-        Protocol::BattleNET { .. } => unimplemented!("Battle.NET servers are not implemented"),
+        AccountCommand::List => {
+            for account in store.list() {
+                let mut traits = Vec::new();
+                if account.pin.is_some() {
+                    traits.push("PIN");
+                }
+                if let Some(ban) = account.ban.as_ref().filter(|ban| ban.is_active()) {
+                    traits.push(if ban.expires.is_some() { "suspended" } else { "banned" });
+                }
+
+                if traits.is_empty() {
+                    println!("{}", account.name);
+                } else {
+                    println!("{} ({})", account.name, traits.join(", "));
+                }
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Prompts for the password of an account, twice if it is being set so that typos are caught.
+/// When the standard input is not a terminal, the password is read from its first line instead.
+fn read_password(name: &str, confirm: bool) -> Result<String> {
+    let password = if std::io::stdin().is_terminal() {
+        let password = rpassword::prompt_password(format!("Password for {}: ", name))?;
+        if confirm && rpassword::prompt_password("Repeat the password: ")? != password {
+            bail!("The passwords do not match");
+        }
+
+        password
+    } else {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
     };
 
-    // handler.await
+    if password.is_empty() {
+        bail!("The password cannot be empty");
+    }
+
+    Ok(password)
+}
+
+async fn create_pipe(pipe: Pipe, accounts: PathBuf, bans: BanList, matrix_cards: Option<Arc<MatrixCardOptions>>) -> Result<()> {
+    // Decoded upfront so that invalid secrets are reported before any client connects.
+    let authenticator = pipe.authenticator_secrets()?;
+
+    let throttle = pipe.lockout.throttle();
+
+    match (pipe.source, pipe.destination) {
+        (Protocol::Grunt { host }, Protocol::LocalAuth { realms, patches }) => {
+            let accounts = Arc::new(JsonAccountStore::open(accounts)?);
+            tokio::spawn(accounts.clone().watch(Duration::from_secs(5)));
+
+            let realms = realms.iter()
+                .map(|realm| realm.realm())
+                .collect::<Result<Vec<_>>>()?;
+
+            info!("Serving {} realm(s) on {}", realms.len(), host);
+            let mut server = LocalAuthServer::new(&host, accounts, realms, CancellationToken::new())
+                .with_throttle(throttle)
+                .with_bans(bans);
+            if let Some(patches) = patches {
+                server = server.with_patches(PatchDirectory::new(patches));
+            }
+            if let Some(matrix_cards) = matrix_cards {
+                server = server.with_matrix_cards(matrix_cards);
+            }
+
+            server.run().await
+        },
+        (Protocol::Grunt { host }, Protocol::Grunt { host: upstream }) => {
+            info!("Relaying logins from {} to {}", host, upstream);
+            let token = CancellationToken::new();
+            RelayServer::new(&host, &upstream, token.clone())
+                .with_throttle(throttle)
+                .with_bans(bans)
+                .with_authenticator(authenticator)
+                .with_realms(pipe.realm_addresses.rewriter(token.child_token()))
+                .with_patches(pipe.patches)
+                .run()
+                .await
+        },
+        (Protocol::Grunt { .. } | Protocol::BattleNET { .. }, _) => unimplemented!("Battle.NET servers are not implemented"),
+        (Protocol::LocalAuth { .. }, _) => bail!("A local authentication server can only be a destination"),
+    }
 }
@@ -1,24 +1,33 @@
+use std::io::ErrorKind;
+use std::marker::PhantomData;
 use std::net::{IpAddr, SocketAddr};
 use anyhow::Result;
 use crate::network::LocalPeer;
-use tokio::{io::{AsyncWriteExt, BufReader, BufWriter}, net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpStream, ToSocketAddrs}};
+use futures::FutureExt;
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter}, net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpStream, ToSocketAddrs}};
 use tokio_util::sync::CancellationToken;
 use crate::network::RemotePeer;
-use crate::{grunt::protocol::GruntProtocol, packets::{Payload, Protocol}};
+use crate::{grunt::protocol::GruntIdentifier, packets::{Payload, Protocol}};
+use crate::world::protocol::{WorldIdentifier, WorldProtocol};
 
 /// A [`Client`] is a client able to communicate with a [`Server`].
 /// It is both:
 /// - a [`RemotePeer`] because it can be managed by a [`Server`] to model a remote.
 /// - a [`LocalPeer`] because it can be created manually to connect to a [`Server`].
-pub struct Client<P> {
+///
+/// A client speaks any [`Protocol`], whose packets are told apart by `Id`. Grunt is spoken
+/// unless another identifier is given, such as
+/// [`WorldIdentifier`](crate::world::protocol::WorldIdentifier).
+pub struct Client<P, Id = GruntIdentifier> {
     pub(crate) addr: SocketAddr,
     pub(crate) token: CancellationToken,
     pub(crate) sender: BufWriter<OwnedWriteHalf>,
     pub(crate) reader: BufReader<OwnedReadHalf>,
     pub(crate) protocol: P,
+    pub(crate) identifier: PhantomData<fn() -> Id>,
 }
 
-impl<P: GruntProtocol + Protocol> Client<P> {
+impl<P: Protocol<Id>, Id> Client<P, Id> {
     /// Connects to the provided server and uses the given protocol version.
     ///
     /// # Arguments
@@ -39,6 +48,7 @@ impl<P: GruntProtocol + Protocol> Client<P> {
             protocol,
             addr: local_address,
             token,
+            identifier: PhantomData,
         })
     }
 
@@ -46,6 +56,19 @@ impl<P: GruntProtocol + Protocol> Client<P> {
         self.addr.ip()
     }
 
+    pub fn protocol(&self) -> &P {
+        &self.protocol
+    }
+
+    pub fn protocol_mut(&mut self) -> &mut P {
+        &mut self.protocol
+    }
+
+    /// Reads and handles a single packet from the server this client is connected to.
+    pub fn process_incoming(&mut self) -> impl Future<Output = Result<()>> {
+        Protocol::<Id>::process_incoming(&mut self.protocol, &mut self.reader, &mut self.sender)
+    }
+
     /// Sends the given packet to the server this client is connected to.
     ///
     /// # Arguments
@@ -55,17 +78,56 @@ impl<P: GruntProtocol + Protocol> Client<P> {
     where
         for<'a> Packet: Payload<P>,
     {
-        self.protocol.send(&mut self.sender, packet)
+        Protocol::<Id>::send(&mut self.protocol, &mut self.sender, packet)
+    }
+}
+
+impl<P: WorldProtocol> Client<P, WorldIdentifier> {
+    /// Opens the connection to a world server, before any packet is exchanged. See
+    /// [`WorldConnection::open`](crate::world::protocol::WorldConnection::open).
+    pub async fn open(&mut self) -> Result<()> {
+        let (build, role) = (self.protocol.build(), self.protocol.role());
+        self.protocol.connection().open(&mut self.reader, &mut self.sender, build, role).await
     }
 }
 
-impl<P: Protocol> RemotePeer for Client<P> {
+/// Returns `true` if the error was caused by the remote end closing the connection.
+fn is_disconnect(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|err| matches!(err.kind(), ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset))
+}
+
+impl<P: Protocol<Id>, Id> RemotePeer for Client<P, Id> {
     fn update(&mut self) -> impl Future<Output = Result<()>>  {
         async move {
+            // Whether the protocol may have queued packets since it was last asked.
+            let mut queued = false;
+
             loop {
                 tokio::select! {
+                    biased;
+
                     _ = self.token.cancelled() => break,
-                    _ = self.protocol.process_incoming(&mut self.reader, &mut self.sender) => (),
+                    // Packets from the peer are handled before queued packets are sent, so
+                    // that the peer can interrupt a long answer.
+                    readable = self.reader.fill_buf().map(|result| result.map(|buf| !buf.is_empty()).map_err(anyhow::Error::from)) => {
+                        match readable {
+                            Ok(true) => (),
+                            Ok(false) => break,
+                            Err(err) if is_disconnect(&err) => break,
+                            Err(err) => return Err(err),
+                        }
+
+                        tokio::select! {
+                            _ = self.token.cancelled() => break,
+                            result = Protocol::<Id>::process_incoming(&mut self.protocol, &mut self.reader, &mut self.sender) => match result {
+                                Ok(()) => queued = true,
+                                Err(err) if is_disconnect(&err) => break,
+                                Err(err) => return Err(err),
+                            },
+                        }
+                    },
+                    result = Protocol::<Id>::send_queued(&mut self.protocol, &mut self.sender), if queued => queued = result?,
                 };
             }
 
@@ -74,7 +136,7 @@ impl<P: Protocol> RemotePeer for Client<P> {
     }
 }
 
-impl<P> LocalPeer for Client<P> {
+impl<P, Id> LocalPeer for Client<P, Id> {
     fn disconnect(&mut self) -> impl Future<Output = Result<()>> {
         async {
             self.token.cancel();
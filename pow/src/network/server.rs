@@ -1,3 +1,6 @@
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+
 use anyhow::Result;
 use tokio::io::{BufReader, BufWriter};
 use tokio::net::TcpListener;
@@ -7,6 +10,7 @@ use tracing::{error, info};
 
 use crate::network::{Acceptor, RemotePeer, Service};
 use crate::network::connection::Client;
+use crate::grunt::lockout::LoginThrottle;
 use crate::grunt::protocol::GruntProtocol;
 
 /// A specialised trait for a server.
@@ -23,7 +27,20 @@ pub trait Server {
 
     /// Creates a new protocol. This function is used by the implementation of
     /// [`Acceptor`] to create a new [`Client`].
-    fn make_protocol(&self) -> Self::Protocol;
+    ///
+    /// # Arguments
+    ///
+    /// - `addr`: The address of the client the protocol is created for.
+    fn make_protocol(&self, addr: SocketAddr) -> Self::Protocol;
+
+    /// The brute-force protection shared by all connections of this server. Protocols record
+    /// the outcome of logins with it, and connections from addresses it locked out are
+    /// refused before a [`Client`] is created for them.
+    fn throttle(&self) -> &LoginThrottle;
+
+    /// Whether a client connecting from the given address may be served. Connections that
+    /// are refused are closed before a [`Client`] is created for them.
+    fn accepts(&self, _: SocketAddr) -> bool { true }
 }
 
 /// Blanket implementation of [`Acceptor`] for all [`Server`]s.
@@ -40,7 +57,16 @@ impl<T> Acceptor for T where T: Server
 
     fn next(&self, listener: &Self::Listener) -> impl Future<Output = Result<Self::Peer>> {
         async {
-            let (stream, addr) = listener.accept().await?;
+            let (stream, addr) = loop {
+                let (stream, addr) = listener.accept().await?;
+                if self.throttle().is_address_locked(addr.ip()) {
+                    info!("Refused a connection from {}, which is locked out", addr);
+                } else if self.accepts(addr) {
+                    break (stream, addr);
+                } else {
+                    info!("Refused a connection from {}", addr);
+                }
+            };
             let (tx, rx) = stream.into_split();
 
             Ok(Client {
@@ -48,7 +74,8 @@ impl<T> Acceptor for T where T: Server
                 token: self.token().child_token(),
                 sender: BufWriter::new(rx),
                 reader: BufReader::new(tx),
-                protocol: self.make_protocol(),
+                protocol: self.make_protocol(addr),
+                identifier: PhantomData,
             })
         }
     }
@@ -87,16 +114,11 @@ impl<T> Service for T where T: Server, T::Protocol: Send {
 
                         _ = queue_token.cancelled() => break,
                         Some(mut conn) = rx.recv() => {
-                            loop {
-                                match conn.update().await {
-                                    Ok(_) => (),
-                                    Err(err) => {
-                                        error!("An error occurred while processing a packet from {}: {}", conn.addr, err);
-
-                                        break;
-                                    }
+                            tokio::spawn(async move {
+                                if let Err(err) = conn.update().await {
+                                    error!("An error occurred while processing a packet from {}: {}", conn.addr, err);
                                 }
-                            }
+                            });
                         }
                     }
                 }
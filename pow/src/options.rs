@@ -1,16 +1,125 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
 use serde::{Deserialize, Serialize};
 
+use crate::grunt::authenticator::{AuthenticatorSecrets, Totp};
+use crate::grunt::lockout::{LockoutPolicy, LoginThrottle};
+use crate::grunt::matrix::MatrixCard;
+use crate::grunt::protocol::{Realm, RealmFlags};
+use crate::grunt::relay::PatchPolicy;
+use crate::grunt::rewrite::RealmRewriter;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Configuration {
     /// A collection of pipes the `pow` proxy will open.
     pub pipes: Vec<Pipe>,
+
+    /// The matrix cards handed out to accounts, if this second factor is offered. Local
+    /// authentication servers ask every account for its card.
+    pub matrix_card: Option<MatrixCardOptions>,
+
+    /// The file accounts are stored in. This is used by local authentication servers and
+    /// managed with `pow account`.
+    #[serde(default = "Configuration::default_accounts")]
+    pub accounts: PathBuf,
+
+    /// A file of banned addresses and accounts, enforced by local authentication servers and
+    /// relays alike. It is reloaded whenever it changes.
+    pub bans: Option<PathBuf>,
+}
+
+impl Configuration {
+    fn default_accounts() -> PathBuf { "accounts.json".into() }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MatrixCardOptions {
+    /// The secret every card is derived from. Changing it invalidates every card handed out.
+    pub secret: String,
+    /// The amount of columns of a card.
+    #[serde(default = "MatrixCardOptions::default_width")]
+    pub width: u8,
+    /// The amount of rows of a card.
+    #[serde(default = "MatrixCardOptions::default_height")]
+    pub height: u8,
+    /// The amount of digits in each cell.
+    #[serde(default = "MatrixCardOptions::default_digits")]
+    pub digits: u8,
+    /// The amount of cells a client is asked for.
+    #[serde(default = "MatrixCardOptions::default_challenges")]
+    pub challenges: u8,
+}
+
+impl MatrixCardOptions {
+    fn default_width() -> u8 { 8 }
+    fn default_height() -> u8 { 10 }
+    fn default_digits() -> u8 { 3 }
+    fn default_challenges() -> u8 { 3 }
+
+    /// Returns the card of the given account.
+    pub fn card(&self, account: &str) -> anyhow::Result<MatrixCard> {
+        MatrixCard::generate(self.secret.as_bytes(), account, self.width, self.height, self.digits)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Protocol {
     Grunt { host: String },
-    BattleNET { host: String, port: u16 }
+    BattleNET { host: String, port: u16 },
+    /// Answers logins itself instead of forwarding them to a server.
+    /// Accounts are read from the file configured in [`Configuration::accounts`].
+    #[serde(rename = "local-auth")]
+    LocalAuth {
+        /// The realm list served to every client.
+        realms: Vec<RealmOptions>,
+        /// A directory of patches offered to clients, named after the build and locale they
+        /// are made for (such as `5875enUS.mpq`).
+        patches: Option<PathBuf>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RealmOptions {
+    pub id: u8,
+    pub name: String,
+    /// The address of the world server, formatted as `host:port`.
+    pub address: String,
+    /// The kind of realm (0 for Normal, 1 for PvP, 6 for RP, 8 for RP-PvP...)
+    #[serde(rename = "type", default)]
+    pub realm_type: u8,
+    #[serde(default)]
+    pub flags: u8,
+    #[serde(default)]
+    pub population: f32,
+    /// The realm category, used by the client to group realms by timezone.
+    #[serde(default = "RealmOptions::default_category")]
+    pub category: u8,
+    /// The version the realm runs, such as `3.3.5.12340`.
+    pub build: Option<String>,
+}
+
+impl RealmOptions {
+    fn default_category() -> u8 { 1 }
+
+    pub fn realm(&self) -> anyhow::Result<Realm> {
+        Ok(Realm {
+            realm_type: self.realm_type,
+            locked: false,
+            flags: RealmFlags(self.flags),
+            name: self.name.clone(),
+            address: self.address.clone(),
+            population: self.population,
+            characters: 0,
+            category: self.category,
+            id: self.id,
+            build: self.build.as_deref().map(str::parse).transpose()?,
+        })
+    }
 }
 
 #[allow(unused)]
@@ -40,4 +149,110 @@ pub struct Pipe {
 
     /// The target server the `pow` proxy must impersonate.
     pub destination: Protocol,
+
+    /// Base 32 authenticator secrets of the accounts that must provide a code before the
+    /// `pow` proxy lets them through, keyed by account name.
+    #[serde(default)]
+    pub authenticator: HashMap<String, String>,
+
+    /// How many failed logins are tolerated before clients are locked out.
+    #[serde(default)]
+    pub lockout: LockoutOptions,
+
+    /// How the addresses of the realms advertised by the destination are rewritten.
+    #[serde(default)]
+    pub realm_addresses: RealmAddressOptions,
+
+    /// Whether the patches offered by the destination are passed on to clients (`relay`) or
+    /// turned down (`block`).
+    #[serde(default)]
+    pub patches: PatchPolicy,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RealmAddressOptions {
+    /// The host clients reach the `pow` proxy at. If set, a world listener is opened for
+    /// every realm that is not listed in `addresses`. It only lets in clients that logged in
+    /// through the proxy.
+    pub public_host: Option<String>,
+    /// The host world listeners bind to.
+    #[serde(default = "RealmAddressOptions::default_bind_host")]
+    pub bind_host: String,
+    /// Fixed replacements, keyed by the address advertised by the destination.
+    #[serde(default)]
+    pub addresses: HashMap<String, String>,
+}
+
+impl Default for RealmAddressOptions {
+    fn default() -> Self {
+        Self {
+            public_host: None,
+            bind_host: Self::default_bind_host(),
+            addresses: HashMap::new(),
+        }
+    }
+}
+
+impl RealmAddressOptions {
+    fn default_bind_host() -> String { "0.0.0.0".to_string() }
+
+    pub fn rewriter(&self, token: CancellationToken) -> RealmRewriter {
+        RealmRewriter::new(self.addresses.clone(), self.public_host.clone(), &self.bind_host, token)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LockoutOptions {
+    /// The amount of failed logins from a single address that locks it out.
+    #[serde(default = "LockoutOptions::default_address_failures")]
+    pub address_failures: u32,
+    /// The amount of failed logins into a single account that locks it out.
+    #[serde(default = "LockoutOptions::default_account_failures")]
+    pub account_failures: u32,
+    /// The amount of seconds after which failures are forgotten.
+    #[serde(default = "LockoutOptions::default_window")]
+    pub window: u64,
+    /// The amount of seconds a lockout lasts.
+    #[serde(default = "LockoutOptions::default_duration")]
+    pub duration: u64,
+}
+
+impl Default for LockoutOptions {
+    fn default() -> Self {
+        Self {
+            address_failures: Self::default_address_failures(),
+            account_failures: Self::default_account_failures(),
+            window: Self::default_window(),
+            duration: Self::default_duration(),
+        }
+    }
+}
+
+impl LockoutOptions {
+    fn default_address_failures() -> u32 { 20 }
+    fn default_account_failures() -> u32 { 5 }
+    fn default_window() -> u64 { 300 }
+    fn default_duration() -> u64 { 900 }
+
+    pub fn throttle(&self) -> LoginThrottle {
+        let policy = |max_failures| LockoutPolicy {
+            max_failures,
+            window: Duration::from_secs(self.window),
+            duration: Duration::from_secs(self.duration),
+        };
+
+        LoginThrottle::new(policy(self.address_failures), policy(self.account_failures))
+    }
+}
+
+impl Pipe {
+    /// Decodes the authenticator secrets of this pipe.
+    pub fn authenticator_secrets(&self) -> anyhow::Result<AuthenticatorSecrets> {
+        let secrets = AuthenticatorSecrets::default();
+        for (account, secret) in &self.authenticator {
+            secrets.insert(account, Totp::from_base32(secret)?);
+        }
+
+        Ok(secrets)
+    }
 }
@@ -31,7 +31,10 @@ pub trait Identifier<Protocol>: Sized {
 
 /// A protocol is in charge of controlling how [`Payload`]s are (de)serialized
 /// from a stream.
-pub trait Protocol: Sized {
+///
+/// The protocol is parameterized by the [`Identifier`] of its packets, so that a type may be
+/// given a blanket implementation for each family of protocols (Grunt, world...).
+pub trait Protocol<Id>: Sized {
     /// This function:
     /// - reads an [`Identifier`] by calling [`Identifier::recv`].
     /// - switches on the value of that identifier, parses the correct [`Payload`]
@@ -46,6 +49,18 @@ pub trait Protocol: Sized {
     fn process_incoming<Source, Dest>(&mut self, source: &mut Source, dest: &mut Dest) -> impl Future<Output = Result<()>> + Send
         where Source: ReadExt, Dest: WriteExt;
 
+    /// Sends the next packet this protocol queued on its own rather than in response to a
+    /// packet, such as the next chunk of a file transfer. Connections call this in between
+    /// reading packets, so that the peer can still be heard while a long answer is sent.
+    ///
+    /// Returns `false` if nothing was queued.
+    ///
+    /// # Arguments
+    ///
+    /// - `dest`: The stream to send the packet to.
+    fn send_queued<Dest>(&mut self, dest: &mut Dest) -> impl Future<Output = Result<bool>> + Send
+        where Dest: WriteExt;
+
     /// This function:
     /// - Extracts an [`Identifier`] derived from the [`Payload`] and immediately
     ///   writes that [`Identifier`] to the stream.
@@ -66,7 +81,7 @@ pub trait Protocol: Sized {
     }
 }
 
-pub trait Serializable<P: Protocol>: Sized {
+pub trait Serializable<P>: Sized {
     /// Reads this object from the given stream, using serialization parameters
     /// provided by the protocol.
     /// 
@@ -89,7 +104,7 @@ pub trait Serializable<P: Protocol>: Sized {
 }
 
 /// A payload is an object that can be serialized, and that is tied to an identifier.
-pub trait Payload<P: Protocol>: Sized {
+pub trait Payload<P>: Sized {
     type Identifier: Identifier<P>;
 
     fn identifier(&self) -> Self::Identifier;
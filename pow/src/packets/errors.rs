@@ -2,13 +2,13 @@ use core::fmt;
 
 #[derive(Debug)]
 pub enum Error {
-    EOF
+    Eof
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::EOF => write!(f, "Reached EOF")
+            Self::Eof => write!(f, "Reached EOF")
         }
     }
 }
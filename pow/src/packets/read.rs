@@ -36,7 +36,7 @@ macro_rules! parser {
             paste::paste! {
                 async fn [<read_ $ty _be>]<T: From<$ty>>(&mut self) -> Result<T> {
                     if self.limit < std::mem::size_of::<$ty>() {
-                        Err(Error::EOF.into())
+                        Err(Error::Eof.into())
                     } else {
                         self.limit -= std::mem::size_of::<$ty>();
                         self.inner.[<read_ $ty _be>]().await
@@ -45,7 +45,7 @@ macro_rules! parser {
 
                 async fn [<read_ $ty _le>]<T: From<$ty>>(&mut self) -> Result<T> {
                     if self.limit < std::mem::size_of::<$ty>() {
-                        Err(Error::EOF.into())
+                        Err(Error::Eof.into())
                     } else {
                         self.limit -= std::mem::size_of::<$ty>();
                         self.inner.[<read_ $ty _le>]().await
@@ -65,7 +65,7 @@ pub trait ReadExt: Send + Sized {
     /// Creates an adaptor which reads at most [`limit`] bytes from it.
     /// 
     /// This function returns a new instance of [`ReadExt`] which will read at most `limit` bytes, after which
-    /// it will always return EOF ([`Error::EOF`]). Any read error will not count towards the number of bytes read
+    /// it will always return EOF ([`Error::Eof`]). Any read error will not count towards the number of bytes read
     /// and future calls may succeed.
     fn take<'a>(&'a mut self, limit: usize) -> Take<'a, Self>;
 
@@ -135,11 +135,9 @@ impl<Target> ReadExt for Target where Target: AsyncRead + Unpin + Send {
 
     fn read_slice(&mut self, size: usize) -> impl Future<Output = Result<Box<[u8]>>> + Send {
         async move {
-            let mut buf = Vec::with_capacity(size);
-            // SAFETY: We will read from the socket.
-            unsafe { buf.set_len(size); }
+            let mut buf = vec![0u8; size];
 
-            let read_count = self.read_exact(&mut buf.as_mut()).await?;
+            let read_count = self.read_exact(buf.as_mut()).await?;
             debug_assert_eq!(read_count, size);
 
             Ok(buf.into_boxed_slice())
@@ -150,7 +148,7 @@ impl<Target> ReadExt for Target where Target: AsyncRead + Unpin + Send {
         async {
             let mut buf = [0u8; N];
             
-            let read_count = self.read_exact(&mut buf.as_mut()).await?;
+            let read_count = self.read_exact(buf.as_mut()).await?;
             debug_assert_eq!(read_count, N);
 
             Ok(buf)
@@ -175,7 +173,7 @@ impl<Inner> ReadExt for Take<'_, Inner>
     fn read_u8<T: From<u8>>(&mut self) -> impl Future<Output = Result<T>> {
         async move {
             if self.limit == 0 {
-                Err(Error::EOF.into())
+                Err(Error::Eof.into())
             } else {
                 let value = self.inner.read_u8().await?;
                 self.limit -= 1;
@@ -188,7 +186,7 @@ impl<Inner> ReadExt for Take<'_, Inner>
     fn read_i8<T: From<i8>>(&mut self) -> impl Future<Output = Result<T>> {
         async move {
             if self.limit == 0 {
-                Err(Error::EOF.into())
+                Err(Error::Eof.into())
             } else {
                 self.limit -= 1;
                 self.inner.read_i8().await
@@ -199,7 +197,7 @@ impl<Inner> ReadExt for Take<'_, Inner>
     fn read_slice(&mut self, size: usize) -> impl Future<Output = Result<Box<[u8]>>> {
         async move {
             if self.limit < size {
-                Err(Error::EOF.into())
+                Err(Error::Eof.into())
             } else {
                 self.limit -= size;
                 self.inner.read_slice(size).await
@@ -210,7 +208,7 @@ impl<Inner> ReadExt for Take<'_, Inner>
     fn read_exact_slice<const N: usize>(&mut self) -> impl Future<Output = Result<[u8; N]>> {
         async move {
             if self.limit < N {
-                Err(Error::EOF.into())
+                Err(Error::Eof.into())
             } else {
                 self.limit -= N;
                 self.inner.read_exact_slice().await
@@ -0,0 +1,444 @@
+pub mod auth;
+pub mod client;
+pub mod crypto;
+pub mod protocol;
+pub mod proxy;
+pub mod transport;
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use anyhow::{Result, anyhow};
+    use tokio::io::{BufReader, duplex};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::grunt::builds::{CLIENT_BUILDS, ClientBuild, Expansion};
+    use crate::grunt::protocol::Role;
+    use crate::grunt::session::{SessionKey, SessionKeys};
+    use crate::packets::{Protocol, WriteExt};
+    use crate::world::auth::{WorldChallenge, answer_auth_challenge, session_encryption_key};
+    use crate::world::client::enter_world;
+    use crate::world::crypto::{HeaderCipher, header_ciphers};
+    use crate::world::protocol::{AccountInfo, AuthChallenge, AuthResponse, AuthResult, AuthSession, SessionExtra};
+    use crate::world::protocol::{EnterEncryptedMode, EnterEncryptedModeAck, WorldConnection, WorldProtocol};
+    use crate::world::proxy::{RelayedLogins, open_world_proxy};
+    use crate::world::transport::{CLIENT_BANNER, SERVER_BANNER};
+
+    /// The server end of the handshake, checking sessions against known session keys.
+    struct ServerProtocol {
+        build: &'static ClientBuild,
+        connection: WorldConnection,
+        challenge: WorldChallenge,
+        keys: SessionKeys,
+        session: Option<AuthSession>,
+        result: Option<AuthResult>,
+        /// The key packets are encrypted with once a modern client acknowledges it.
+        encryption_key: Option<[u8; 16]>,
+    }
+
+    impl ServerProtocol {
+        fn new(build: &'static ClientBuild, keys: SessionKeys) -> Self {
+            Self { build, connection: WorldConnection::default(), challenge: WorldChallenge::new(), keys, session: None, result: None, encryption_key: None }
+        }
+    }
+
+    impl WorldProtocol for ServerProtocol {
+        fn build(&self) -> &'static ClientBuild { self.build }
+        fn role(&self) -> Role { Role::Server }
+        fn connection(&mut self) -> &mut WorldConnection { &mut self.connection }
+
+        async fn handle_auth_session<D>(&mut self, msg: AuthSession, dest: &mut D) -> Result<()>
+            where D: WriteExt
+        {
+            let verified = self.challenge.verify(&msg, &self.keys);
+            self.result = Some(verified.err().unwrap_or(AuthResult::Ok));
+
+            if self.build.expansion == Expansion::Modern {
+                // The modern layout of the response isn't supported, so failed sessions are
+                // left unanswered.
+                if let Ok(session_key) = verified {
+                    self.encryption_key = Some(session_encryption_key(&self.challenge.challenge(), &msg, &session_key)?);
+                    self.session = Some(msg);
+                    return self.send(dest, EnterEncryptedMode { signature: [0; 64], enabled: true }).await;
+                }
+
+                self.session = Some(msg);
+                return Ok(());
+            }
+
+            let response = match verified {
+                Ok(session_key) => {
+                    let (decryptor, encryptor) = header_ciphers(self.build, Role::Server, &session_key)?;
+                    self.connection.encrypt_headers(decryptor, encryptor);
+
+                    AuthResponse {
+                        result: AuthResult::Ok,
+                        account: Some(AccountInfo { billing_time_remaining: 7, billing_flags: 1, billing_time_rested: 3, expansion: 2 }),
+                        queue_position: None,
+                    }
+                },
+                Err(result) => AuthResponse::new(result),
+            };
+
+            self.session = Some(msg);
+            self.send(dest, response).await
+        }
+
+        async fn handle_enter_encrypted_mode_ack<D>(&mut self, _: EnterEncryptedModeAck, _: &mut D) -> Result<()>
+            where D: WriteExt
+        {
+            let key = self.encryption_key.ok_or_else(|| anyhow!("No session was accepted"))?;
+            self.connection.enter_encrypted_mode(Role::Server, &key)
+        }
+    }
+
+    /// The client end of the handshake.
+    struct ClientProtocol {
+        build: &'static ClientBuild,
+        connection: WorldConnection,
+        account: &'static str,
+        session_key: SessionKey,
+        challenge: Option<AuthChallenge>,
+        response: Option<AuthResponse>,
+        /// The key packets are encrypted with once the server accepts the session of a modern client.
+        encryption_key: Option<[u8; 16]>,
+    }
+
+    impl ClientProtocol {
+        fn new(build: &'static ClientBuild, session_key: SessionKey) -> Self {
+            Self { build, connection: WorldConnection::default(), account: "POW", session_key, challenge: None, response: None, encryption_key: None }
+        }
+    }
+
+    impl WorldProtocol for ClientProtocol {
+        fn build(&self) -> &'static ClientBuild { self.build }
+        fn role(&self) -> Role { Role::Client }
+        fn connection(&mut self) -> &mut WorldConnection { &mut self.connection }
+
+        async fn handle_auth_challenge<D>(&mut self, msg: AuthChallenge, dest: &mut D) -> Result<()>
+            where D: WriteExt
+        {
+            let addons = vec![0xA0, 0xB1, 0xC2].into_boxed_slice();
+            let session = answer_auth_challenge(self.build.version.build, self.account, &msg, &self.session_key, addons)?;
+
+            if self.build.expansion == Expansion::Modern {
+                self.encryption_key = Some(session_encryption_key(&msg, &session, &self.session_key)?);
+                self.challenge = Some(msg);
+                return self.send(dest, session).await;
+            }
+
+            self.challenge = Some(msg);
+            self.send(dest, session).await?;
+
+            let (decryptor, encryptor) = header_ciphers(self.build, Role::Client, &self.session_key)?;
+            self.connection.encrypt_headers(decryptor, encryptor);
+            Ok(())
+        }
+
+        async fn handle_auth_response<D>(&mut self, msg: AuthResponse, _: &mut D) -> Result<()>
+            where D: WriteExt
+        {
+            self.response = Some(msg);
+            Ok(())
+        }
+
+        async fn handle_enter_encrypted_mode<D>(&mut self, msg: EnterEncryptedMode, dest: &mut D) -> Result<()>
+            where D: WriteExt
+        {
+            let key = self.encryption_key.ok_or_else(|| anyhow!("No session was sent"))?;
+            if msg.enabled {
+                self.send(dest, EnterEncryptedModeAck).await?;
+                self.connection.enter_encrypted_mode(Role::Client, &key)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Runs a handshake between a client knowing `client_key` and a server knowing `server_key`,
+    /// and returns both ends once the server answered the session of the client.
+    async fn handshake(build: &'static ClientBuild, client_key: SessionKey, server_key: SessionKey) -> (ServerProtocol, ClientProtocol) {
+        let keys = SessionKeys::default();
+        keys.insert("pow", server_key);
+
+        let mut server = ServerProtocol::new(build, keys);
+        let mut client = ClientProtocol::new(build, client_key);
+
+        let (server_stream, client_stream) = duplex(1024);
+        let (server_read, mut server_write) = tokio::io::split(server_stream);
+        let (client_read, mut client_write) = tokio::io::split(client_stream);
+        let mut server_read = BufReader::new(server_read);
+        let mut client_read = BufReader::new(client_read);
+
+        let (server_opened, client_opened) = tokio::join!(
+            server.connection.open(&mut server_read, &mut server_write, build, Role::Server),
+            client.connection.open(&mut client_read, &mut client_write, build, Role::Client),
+        );
+        server_opened.expect("Server should accept the client banner");
+        client_opened.expect("Client should accept the server banner");
+
+        let challenge = server.challenge.challenge();
+        server.send(&mut server_write, challenge).await.expect("Challenge couldn't be sent");
+        client.process_incoming(&mut client_read, &mut client_write).await.expect("Challenge couldn't be handled");
+        server.process_incoming(&mut server_read, &mut server_write).await.expect("Session couldn't be handled");
+
+        // Failures are sent before the server starts encrypting headers, which the client can't tell.
+        if server.result == Some(AuthResult::Ok) {
+            client.process_incoming(&mut client_read, &mut client_write).await.expect("Response couldn't be handled");
+            if build.expansion == Expansion::Modern {
+                server.process_incoming(&mut server_read, &mut server_write).await.expect("Acknowledgement couldn't be handled");
+            }
+        }
+
+        (server, client)
+    }
+
+    #[tokio::test]
+    pub async fn test_handshake() {
+        for build in CLIENT_BUILDS {
+            let (server, client) = handshake(build, [0x42; 40], [0x42; 40]).await;
+            let session = server.session.expect("Server should have received a session");
+            assert_eq!(session.build, build.version.build, "{}", build.name);
+            assert_eq!(session.account, "POW", "{}", build.name);
+            assert_eq!(server.result, Some(AuthResult::Ok), "{}", build.name);
+
+            if build.expansion == Expansion::Modern {
+                // Modern clients send their addons later, and are only answered once packets are encrypted.
+                assert!(server.connection.is_encrypted(), "{}", build.name);
+                assert!(client.connection.is_encrypted(), "{}", build.name);
+            } else {
+                assert_eq!(&session.addons[..], [0xA0, 0xB1, 0xC2], "{}", build.name);
+
+                let response = client.response.expect("Client should have received a response");
+                assert_eq!(response.result, AuthResult::Ok, "{}", build.name);
+
+                let account = response.account.expect("Account details should have been sent");
+                assert_eq!(account.billing_time_remaining, 7, "{}", build.name);
+                assert_eq!(account.billing_time_rested, 3, "{}", build.name);
+            }
+
+            let (server, client) = handshake(build, [0x43; 40], [0x42; 40]).await;
+            assert_eq!(server.result, Some(AuthResult::Failed), "{}", build.name);
+            assert!(!client.connection.is_encrypted(), "{}", build.name);
+        }
+    }
+
+    /// Serves a single world connection from a client of the given build, knowing the session
+    /// key of `POW` only. Returns the address of the server, and the result of the handshake.
+    async fn serve_world(build: &'static ClientBuild) -> (SocketAddr, JoinHandle<Result<Option<AuthResult>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind");
+        let address = listener.local_addr().expect("Listener should have an address");
+
+        let server = tokio::spawn(async move {
+            let keys = SessionKeys::default();
+            keys.insert("pow", [0x42; 40]);
+            let mut server = ServerProtocol::new(build, keys);
+
+            let (stream, _) = listener.accept().await?;
+            let (read, mut write) = stream.into_split();
+            let mut read = BufReader::new(read);
+
+            server.connection.open(&mut read, &mut write, build, Role::Server).await?;
+            let challenge = server.challenge.challenge();
+            server.send(&mut write, challenge).await?;
+            server.process_incoming(&mut read, &mut write).await?;
+            if build.expansion == Expansion::Modern && server.result == Some(AuthResult::Ok) {
+                server.process_incoming(&mut read, &mut write).await?;
+                assert!(server.connection.is_encrypted());
+            }
+
+            Ok(server.result)
+        });
+
+        (address, server)
+    }
+
+    #[tokio::test]
+    pub async fn test_enter_world() {
+        for build in [5875, 12340, 15595, 54737] {
+            let build = ClientBuild::find(build).expect("Build should be known");
+            let (address, server) = serve_world(build).await;
+
+            let result = enter_world(address, build, "pow", [0x42; 40], CancellationToken::new())
+                .await
+                .expect("Client should have entered the world");
+            assert_eq!(result, AuthResult::Ok, "{}", build.name);
+            assert_eq!(server.await.unwrap().unwrap(), Some(AuthResult::Ok), "{}", build.name);
+        }
+
+        // Failures are sent before the server starts encrypting headers, so the client cannot
+        // read them.
+        let build = ClientBuild::find(12340).unwrap();
+        let (address, server) = serve_world(build).await;
+        let err = enter_world(address, build, "pow", [0x43; 40], CancellationToken::new()).await;
+        assert!(err.is_err());
+        assert_eq!(server.await.unwrap().unwrap(), Some(AuthResult::Failed));
+    }
+
+    #[tokio::test]
+    pub async fn test_proxied_handshake() {
+        let token = CancellationToken::new();
+
+        // Packets are forwarded as is once the handshake went through, encrypted headers included.
+        for build in [5875, 8606, 12340, 15595] {
+            let build = ClientBuild::find(build).expect("Build should be known");
+            let logins = RelayedLogins::default();
+            let (upstream, server) = serve_world(build).await;
+            let proxy = open_world_proxy("127.0.0.1:0", upstream.to_string(), logins.clone(), token.child_token())
+                .await
+                .expect("Proxy should be listening");
+
+            // Clients that did not log in through the relay are turned away.
+            let err = enter_world(proxy, build, "pow", [0x42; 40], CancellationToken::new()).await;
+            assert!(err.is_err(), "{}", build.name);
+
+            logins.insert("127.0.0.1".parse().unwrap(), "pow", build);
+            let result = enter_world(proxy, build, "pow", [0x42; 40], CancellationToken::new())
+                .await
+                .expect("Client should have entered the world");
+            assert_eq!(result, AuthResult::Ok, "{}", build.name);
+            assert_eq!(server.await.unwrap().unwrap(), Some(AuthResult::Ok), "{}", build.name);
+        }
+
+        // Clients may only enter the world as the account they logged in with.
+        let build = ClientBuild::find(12340).unwrap();
+        let logins = RelayedLogins::default();
+        let (upstream, server) = serve_world(build).await;
+        let proxy = open_world_proxy("127.0.0.1:0", upstream.to_string(), logins.clone(), token.child_token())
+            .await
+            .expect("Proxy should be listening");
+        logins.insert("127.0.0.1".parse().unwrap(), "other", build);
+        let err = enter_world(proxy, build, "pow", [0x42; 40], CancellationToken::new()).await;
+        assert!(err.is_err());
+        assert!(server.await.unwrap().is_err(), "The session should not have been forwarded");
+
+        token.cancel();
+    }
+
+    #[tokio::test]
+    pub async fn test_oversized_addons() {
+        let build = ClientBuild::find(15595).expect("4.3.4 should be known");
+        let mut client = ClientProtocol::new(build, [0; 40]);
+        let mut server = ServerProtocol::new(build, SessionKeys::default());
+
+        let mut buffer = Vec::new();
+        client.send(&mut buffer, AuthSession {
+            build: 15595,
+            account: "POW".to_string(),
+            client_seed: 0,
+            digest: [0; 20],
+            addons: vec![0; 4].into_boxed_slice(),
+            extra: SessionExtra::default_for(Expansion::Cataclysm),
+        }).await.expect("Session couldn't be sent");
+
+        // The size of the addons follows the 6-byte header and 52 bytes of fields.
+        buffer[58..62].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = server.process_incoming(&mut &buffer[..], &mut Vec::new()).await.expect_err("Session should be rejected");
+        assert!(err.to_string().starts_with("Addons are larger than the packet"));
+    }
+
+    #[tokio::test]
+    pub async fn test_oversized_ticket() {
+        let build = ClientBuild::find(54737).expect("4.4.0 should be known");
+        let mut client = ClientProtocol::new(build, [0; 40]);
+        let mut server = ServerProtocol::new(build, SessionKeys::default());
+        client.connection.open(&mut SERVER_BANNER.as_bytes(), &mut Vec::new(), build, Role::Client).await.expect("Connection couldn't be opened");
+        server.connection.open(&mut CLIENT_BANNER.as_bytes(), &mut Vec::new(), build, Role::Server).await.expect("Connection couldn't be opened");
+
+        let mut buffer = Vec::new();
+        client.send(&mut buffer, AuthSession {
+            build: 54737,
+            account: "POW".to_string(),
+            client_seed: 0,
+            digest: [0; 20],
+            addons: Box::new([]),
+            extra: SessionExtra::default_for(Expansion::Modern),
+        }).await.expect("Session couldn't be sent");
+
+        server.process_incoming(&mut &buffer[..], &mut Vec::new()).await.expect("Session couldn't be handled");
+        assert_eq!(server.session.take().map(|session| session.account), Some("POW".to_string()));
+
+        // The length of the ticket follows the 18-byte header and 77 bytes of fields.
+        buffer[95..99].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = server.process_incoming(&mut &buffer[..], &mut Vec::new()).await.expect_err("Session should be rejected");
+        assert!(err.to_string().starts_with("Realm join ticket is larger than the packet"));
+    }
+
+    #[tokio::test]
+    pub async fn test_queued_response() {
+        // The modern layout of the response isn't supported.
+        for build in CLIENT_BUILDS.iter().filter(|build| build.expansion != Expansion::Modern) {
+            let mut server = ServerProtocol::new(build, SessionKeys::default());
+            let mut client = ClientProtocol::new(build, [0; 40]);
+            let response = AuthResponse { result: AuthResult::WaitQueue, account: None, queue_position: Some(12) };
+
+            let mut buffer = Vec::new();
+            server.send(&mut buffer, response.clone()).await.expect("Response couldn't be sent");
+
+            client.process_incoming(&mut &buffer[..], &mut Vec::new()).await.expect("Response couldn't be handled");
+            assert_eq!(client.response, Some(response), "{}", build.name);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_encrypted_headers() {
+        let build = ClientBuild::find(5875).expect("1.12.1 should be known");
+        let key = [0x42; 40];
+
+        let mut server = ServerProtocol::new(build, SessionKeys::default());
+        let mut client = ClientProtocol::new(build, key);
+        server.connection.encrypt_headers(HeaderCipher::vanilla(&key), HeaderCipher::vanilla(&key));
+        client.connection.encrypt_headers(HeaderCipher::vanilla(&key), HeaderCipher::vanilla(&key));
+
+        let mut plain = Vec::new();
+        let mut unencrypted = ServerProtocol::new(build, SessionKeys::default());
+        unencrypted.send(&mut plain, AuthResponse::new(AuthResult::Banned)).await.expect("Response couldn't be sent");
+
+        for position in 0..10 {
+            let response = AuthResponse { result: AuthResult::WaitQueue, account: None, queue_position: Some(position) };
+
+            let mut buffer = Vec::new();
+            server.send(&mut buffer, AuthResponse::new(AuthResult::Banned)).await.expect("Response couldn't be sent");
+            assert_ne!(buffer[..4], plain[..4], "Header should be encrypted");
+            assert_eq!(buffer[4..], plain[4..], "Body should not be encrypted");
+            server.send(&mut buffer, response.clone()).await.expect("Response couldn't be sent");
+
+            let mut source = &buffer[..];
+            client.process_incoming(&mut source, &mut Vec::new()).await.expect("Response couldn't be handled");
+            assert_eq!(client.response, Some(AuthResponse::new(AuthResult::Banned)));
+            client.process_incoming(&mut source, &mut Vec::new()).await.expect("Response couldn't be handled");
+            assert_eq!(client.response, Some(response));
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_modern_transport() {
+        let build = ClientBuild::find(54737).expect("4.4.0 should be known");
+        let (mut server, mut client) = handshake(build, [0x42; 40], [0x42; 40]).await;
+
+        let challenge = server.challenge.challenge();
+        let plain = [&[0x48, 0x30][..], &challenge.seeds, &challenge.challenge, &[1]].concat();
+
+        // Each packet gets its own nonce, so the same packet is never encrypted the same way.
+        let mut buffer = Vec::new();
+        for _ in 0..2 {
+            server.send(&mut buffer, challenge.clone()).await.expect("Challenge couldn't be sent");
+        }
+        let size = 16 + plain.len();
+        assert_eq!(buffer.len(), size * 2);
+        assert_eq!(buffer[..4], (plain.len() as u32).to_le_bytes());
+        assert_ne!(buffer[16..size], plain[..], "Payload should be encrypted");
+        assert_ne!(buffer[16..size], buffer[size + 16..], "Nonces should not be reused");
+
+        let mut source = &buffer[..];
+        for _ in 0..2 {
+            client.challenge = None;
+            client.process_incoming(&mut source, &mut Vec::new()).await.expect("Challenge couldn't be handled");
+            // The seed isn't sent to modern clients.
+            assert_eq!(client.challenge, Some(AuthChallenge { server_seed: 0, ..challenge.clone() }));
+        }
+    }
+}
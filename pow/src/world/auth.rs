@@ -0,0 +1,215 @@
+use anyhow::{Result, bail};
+use hmac::{Hmac, Mac};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::grunt::builds::{ClientBuild, Expansion};
+use crate::grunt::session::SessionKey;
+#[cfg(test)]
+use crate::grunt::session::SessionKeys;
+#[cfg(test)]
+use crate::world::protocol::AuthResult;
+use crate::world::protocol::{AuthChallenge, AuthSession, SessionExtra};
+use crate::world::transport::encryption_key;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Mixed into the digest of modern clients, along with the challenges of both ends.
+const AUTH_CHECK_SEED: [u8; 16] = [
+    0xC5, 0xC6, 0x98, 0x95, 0x76, 0x3F, 0x1D, 0xCD,
+    0xB6, 0xA1, 0x37, 0x28, 0xB3, 0x12, 0xFF, 0x8A,
+];
+
+/// Computes the digest a client sends to prove it knows the session key of its account.
+///
+/// # Arguments
+///
+/// - `account`: The name of the account, as sent in the session.
+/// - `client_seed`: Random data generated by the client.
+/// - `server_seed`: The seed sent by the server in its challenge.
+/// - `session_key`: The session key agreed upon when logging in.
+pub fn session_digest(account: &str, client_seed: u32, server_seed: u32, session_key: &SessionKey) -> [u8; 20] {
+    Sha1::new()
+        .chain_update(account.as_bytes())
+        .chain_update([0; 4])
+        .chain_update(client_seed.to_le_bytes())
+        .chain_update(server_seed.to_le_bytes())
+        .chain_update(session_key)
+        .finalize()
+        .into()
+}
+
+/// Computes the digest a modern client sends to prove it knows the key of its account.
+///
+/// Retail servers key it with a hash of the Battle.net key of the game account and of the
+/// build of the client. Until `pow` speaks Battle.net, the session key of the account stands
+/// in for it on both ends, so real modern clients and servers reject this digest.
+///
+/// # Arguments
+///
+/// - `session_key`: The session key of the account.
+/// - `local_challenge`: Random data generated by the client.
+/// - `challenge`: The challenge sent by the server.
+pub fn modern_session_digest(session_key: &SessionKey, local_challenge: &[u8; 32], challenge: &[u8; 16]) -> Result<[u8; 24]> {
+    let digest = <HmacSha256 as Mac>::new_from_slice(session_key)?
+        .chain_update(local_challenge)
+        .chain_update(challenge)
+        .chain_update(AUTH_CHECK_SEED)
+        .finalize()
+        .into_bytes();
+
+    let mut truncated = [0; 24];
+    truncated.copy_from_slice(&digest[..24]);
+    Ok(truncated)
+}
+
+/// Derives the key a modern client and its server encrypt packets with once the session of the
+/// client was accepted.
+///
+/// # Arguments
+///
+/// - `challenge`: The challenge sent by the server.
+/// - `session`: The session the client answered with.
+/// - `session_key`: The session key of the account.
+pub fn session_encryption_key(challenge: &AuthChallenge, session: &AuthSession, session_key: &SessionKey) -> Result<[u8; 16]> {
+    let SessionExtra::Modern { local_challenge, .. } = &session.extra else {
+        bail!("Only modern clients encrypt packets");
+    };
+
+    encryption_key(session_key, local_challenge, &challenge.challenge)
+}
+
+/// The server side of a world authentication attempt.
+///
+/// `pow` does not serve worlds itself, so only tests stand in for a world server with it.
+#[cfg(test)]
+pub struct WorldChallenge {
+    server_seed: u32,
+    seeds: [u8; 32],
+    challenge: [u8; 16],
+}
+
+#[cfg(test)]
+impl Default for WorldChallenge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+impl WorldChallenge {
+    pub fn new() -> Self {
+        Self {
+            server_seed: rand::random(),
+            seeds: rand::random(),
+            challenge: rand::random(),
+        }
+    }
+
+    /// Returns the challenge to send to the client.
+    pub fn challenge(&self) -> AuthChallenge {
+        AuthChallenge {
+            server_seed: self.server_seed,
+            seeds: self.seeds,
+            challenge: self.challenge,
+        }
+    }
+
+    /// Verifies that the client knows the session key of the account it claims to be.
+    ///
+    /// Returns the session key of the account, or the result to reject the client with.
+    pub fn verify(&self, session: &AuthSession, keys: &SessionKeys) -> Result<SessionKey, AuthResult> {
+        let session_key = keys.get(&session.account).ok_or(AuthResult::UnknownAccount)?;
+
+        let valid = match &session.extra {
+            SessionExtra::Modern { local_challenge, digest, .. } => {
+                modern_session_digest(&session_key, local_challenge, &self.challenge).is_ok_and(|expected| &expected == digest)
+            },
+            _ => session_digest(&session.account, session.client_seed, self.server_seed, &session_key) == session.digest,
+        };
+
+        if !valid {
+            return Err(AuthResult::Failed);
+        }
+
+        Ok(session_key)
+    }
+}
+
+/// Answers a server's challenge. This is the client side of a world authentication attempt.
+///
+/// # Arguments
+///
+/// - `build`: The build of the client.
+/// - `account`: The name of the account the client logged in with.
+/// - `challenge`: The challenge received from the server.
+/// - `session_key`: The session key agreed upon when logging in.
+/// - `addons`: The compressed list of addons of the client.
+pub fn answer_auth_challenge(build: u16, account: &str, challenge: &AuthChallenge, session_key: &SessionKey, addons: Box<[u8]>) -> Result<AuthSession> {
+    let mut extra = SessionExtra::default_for(ClientBuild::find(build).map_or(Expansion::Vanilla, |client| client.expansion));
+
+    // Modern clients prove themselves with a digest of their own, and send no seed.
+    let (client_seed, digest) = match &mut extra {
+        SessionExtra::Modern { local_challenge, digest, .. } => {
+            *local_challenge = rand::random();
+            *digest = modern_session_digest(session_key, local_challenge, &challenge.challenge)?;
+            (0, [0; 20])
+        },
+        _ => {
+            let client_seed = rand::random();
+            (client_seed, session_digest(account, client_seed, challenge.server_seed, session_key))
+        },
+    };
+
+    Ok(AuthSession {
+        build,
+        account: account.to_string(),
+        client_seed,
+        digest,
+        addons,
+        extra,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grunt::session::SessionKeys;
+    use crate::world::auth::{WorldChallenge, answer_auth_challenge, session_encryption_key};
+    use crate::world::protocol::AuthResult;
+
+    #[test]
+    pub fn test_session_digest() {
+        let keys = SessionKeys::default();
+        keys.insert("pow", [0x42; 40]);
+
+        let server = WorldChallenge::new();
+        let challenge = server.challenge();
+
+        for build in [12340, 54737] {
+            let session = answer_auth_challenge(build, "POW", &challenge, &[0x42; 40], Box::new([])).unwrap();
+            assert_eq!(server.verify(&session, &keys), Ok([0x42; 40]), "{}", build);
+
+            let session = answer_auth_challenge(build, "POW", &challenge, &[0x43; 40], Box::new([])).unwrap();
+            assert_eq!(server.verify(&session, &keys), Err(AuthResult::Failed), "{}", build);
+
+            let session = answer_auth_challenge(build, "OTHER", &challenge, &[0x42; 40], Box::new([])).unwrap();
+            assert_eq!(server.verify(&session, &keys), Err(AuthResult::UnknownAccount), "{}", build);
+        }
+    }
+
+    #[test]
+    pub fn test_session_encryption_key() {
+        let challenge = WorldChallenge::new().challenge();
+
+        // Each session brings its own challenge, and so its own key.
+        let first = answer_auth_challenge(54737, "POW", &challenge, &[0x42; 40], Box::new([])).unwrap();
+        let second = answer_auth_challenge(54737, "POW", &challenge, &[0x42; 40], Box::new([])).unwrap();
+        assert_ne!(
+            session_encryption_key(&challenge, &first, &[0x42; 40]).unwrap(),
+            session_encryption_key(&challenge, &second, &[0x42; 40]).unwrap(),
+        );
+
+        let legacy = answer_auth_challenge(12340, "POW", &challenge, &[0x42; 40], Box::new([])).unwrap();
+        assert!(session_encryption_key(&challenge, &legacy, &[0x42; 40]).is_err());
+    }
+}
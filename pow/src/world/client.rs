@@ -0,0 +1,118 @@
+use anyhow::{Result, anyhow};
+use tokio::net::ToSocketAddrs;
+use tokio_util::sync::CancellationToken;
+
+use crate::grunt::builds::{ClientBuild, Expansion};
+use crate::grunt::protocol::Role;
+use crate::grunt::session::SessionKey;
+use crate::network::LocalPeer;
+use crate::network::connection::Client;
+use crate::packets::{Protocol, WriteExt};
+use crate::world::auth::{answer_auth_challenge, session_encryption_key};
+use crate::world::crypto::header_ciphers;
+use crate::world::protocol::{
+    AuthChallenge, AuthResponse, AuthResult, EnterEncryptedMode, EnterEncryptedModeAck, WorldConnection,
+    WorldIdentifier, WorldProtocol
+};
+
+/// A world protocol that enters the world with the session key of a login.
+pub struct EnterWorldProtocol {
+    build: &'static ClientBuild,
+    connection: WorldConnection,
+    account: String,
+    session_key: SessionKey,
+    /// The key packets are encrypted with once the server accepts the session of a modern client.
+    encryption_key: Option<[u8; 16]>,
+    result: Option<AuthResult>,
+}
+
+impl EnterWorldProtocol {
+    /// # Arguments
+    ///
+    /// - `build`: The build of the client to pass off as. It must be the build that logged in.
+    /// - `account`: The name of the account that logged in.
+    /// - `session_key`: The session key agreed upon when logging in.
+    pub fn new(build: &'static ClientBuild, account: &str, session_key: SessionKey) -> Self {
+        Self {
+            build,
+            connection: WorldConnection::default(),
+            account: account.to_uppercase(),
+            session_key,
+            encryption_key: None,
+            result: None,
+        }
+    }
+}
+
+impl WorldProtocol for EnterWorldProtocol {
+    fn build(&self) -> &'static ClientBuild { self.build }
+    fn role(&self) -> Role { Role::Client }
+    fn connection(&mut self) -> &mut WorldConnection { &mut self.connection }
+
+    async fn handle_auth_challenge<D>(&mut self, msg: AuthChallenge, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        // `pow` has no addons to announce.
+        let session = answer_auth_challenge(self.build.version.build, &self.account, &msg, &self.session_key, Box::new([]))?;
+
+        if self.build.expansion == Expansion::Modern {
+            self.encryption_key = Some(session_encryption_key(&msg, &session, &self.session_key)?);
+            return self.send(dest, session).await;
+        }
+
+        // The server obfuscates the header of its response already.
+        self.send(dest, session).await?;
+        let (decryptor, encryptor) = header_ciphers(self.build, Role::Client, &self.session_key)?;
+        self.connection.encrypt_headers(decryptor, encryptor);
+        Ok(())
+    }
+
+    async fn handle_auth_response<D>(&mut self, msg: AuthResponse, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.result = Some(msg.result);
+        Ok(())
+    }
+
+    async fn handle_enter_encrypted_mode<D>(&mut self, msg: EnterEncryptedMode, dest: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        let key = self.encryption_key.ok_or_else(|| anyhow!("No session was sent"))?;
+        if msg.enabled {
+            self.send(dest, EnterEncryptedModeAck).await?;
+            self.connection.enter_encrypted_mode(Role::Client, &key)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Enters the world server at the given address with the session key of a login, and returns
+/// the answer of the server.
+///
+/// Modern servers only answer once packets are encrypted, with a layout `pow` does not
+/// support yet. Being let into encrypted mode is reported as [`AuthResult::Ok`] instead. As
+/// `pow` does not derive modern keys the way retail ends do, this only works against another
+/// `pow`. See [`modern_session_digest`](crate::world::auth::modern_session_digest).
+///
+/// # Arguments
+///
+/// - `address`: The address of the world server, as advertised in the realm list.
+/// - `token`: A token that closes the connection once signalled.
+///
+/// See [`EnterWorldProtocol::new`] for the other arguments.
+pub async fn enter_world<A>(address: A, build: &'static ClientBuild, account: &str, session_key: SessionKey, token: CancellationToken) -> Result<AuthResult>
+    where A: ToSocketAddrs
+{
+    let protocol = EnterWorldProtocol::new(build, account, session_key);
+    let mut client = Client::<_, WorldIdentifier>::connect(address, protocol, token).await?;
+    client.open().await?;
+
+    while client.protocol().result.is_none() && !client.protocol_mut().connection().is_encrypted() {
+        client.process_incoming().await?;
+    }
+
+    let result = client.protocol().result.unwrap_or(AuthResult::Ok);
+    client.disconnect().await?;
+    Ok(result)
+}
@@ -0,0 +1,246 @@
+mod auth_challenge;
+mod auth_response;
+mod auth_session;
+mod enter_encrypted_mode;
+mod opcode;
+
+pub use auth_challenge::*;
+pub use auth_response::*;
+pub use auth_session::*;
+pub use enter_encrypted_mode::*;
+pub use opcode::*;
+
+use anyhow::{Result, bail};
+use pow_macro::protocol;
+
+use crate::grunt::builds::{ClientBuild, Expansion};
+use crate::grunt::protocol::Role;
+use crate::packets::{Identifier, Protocol, ReadExt, WriteExt};
+use crate::world::crypto::HeaderCipher;
+use crate::world::transport::{ModernFraming, exchange_banners, verify_connectivity};
+
+#[protocol(identifier = WorldIdentifier, handlers = [
+     handler(ty = AuthChallenge, identifier = WorldIdentifier(Opcode::AuthChallenge, Role::Server)),
+     handler(ty = AuthSession, identifier = WorldIdentifier(Opcode::AuthSession, Role::Client)),
+     handler(ty = AuthResponse, identifier = WorldIdentifier(Opcode::AuthResponse, Role::Server)),
+     handler(ty = EnterEncryptedMode, identifier = WorldIdentifier(Opcode::EnterEncryptedMode, Role::Server)),
+     handler(ty = EnterEncryptedModeAck, identifier = WorldIdentifier(Opcode::EnterEncryptedModeAck, Role::Client))
+])]
+/// A world-specific [`Protocol`]. Note that using this type as a constraint
+/// does not imply for the given `T` to be [`Protocol`].
+pub trait WorldProtocol: Send + Sync + Unpin + 'static {
+    /// The build of the client on this connection. Packet layouts depend on it.
+    fn build(&self) -> &'static ClientBuild;
+
+    /// The side of the connection this protocol speaks for.
+    fn role(&self) -> Role;
+
+    /// The state of the connection shared by all packets.
+    fn connection(&mut self) -> &mut WorldConnection;
+}
+
+/// The state of a world connection that packets need to be read and written.
+#[derive(Default)]
+pub struct WorldConnection {
+    /// The size of the body of the packet being read.
+    body_size: usize,
+    /// Decrypts the headers of the packets read from the peer.
+    decryptor: HeaderCipher,
+    /// Encrypts the headers of the packets sent to the peer.
+    encryptor: HeaderCipher,
+    /// The framing of modern clients, which replaces the legacy headers when set.
+    modern: Option<ModernFraming>,
+    /// The body of the packet being read, when the framing needs to read it with the header.
+    body: Option<Box<[u8]>>,
+}
+
+impl WorldConnection {
+    /// Opens a connection with a client of the given build, before any packet is sent.
+    ///
+    /// Cataclysm clients verify the connectivity of the server, and modern clients exchange
+    /// banners with it before switching to their own framing. Other clients send nothing.
+    ///
+    /// # Arguments
+    ///
+    /// - `build`: The build of the client.
+    /// - `role`: The end of the connection this is called by.
+    pub async fn open<S, D>(&mut self, source: &mut S, dest: &mut D, build: &ClientBuild, role: Role) -> Result<()>
+        where S: ReadExt, D: WriteExt
+    {
+        match build.expansion {
+            Expansion::Cataclysm => verify_connectivity(source, dest, role).await,
+            Expansion::Modern => {
+                exchange_banners(source, dest, role).await?;
+                self.use_modern_framing();
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts obfuscating packet headers once the client is authenticated.
+    ///
+    /// Servers must call this before sending their [`AuthResponse`], and clients right after
+    /// sending their [`AuthSession`].
+    ///
+    /// # Arguments
+    ///
+    /// - `decryptor`: The cipher of the headers sent by the peer.
+    /// - `encryptor`: The cipher of the headers sent to the peer.
+    pub fn encrypt_headers(&mut self, decryptor: HeaderCipher, encryptor: HeaderCipher) {
+        self.decryptor = decryptor;
+        self.encryptor = encryptor;
+    }
+
+    /// Switches to the framing of modern clients, once both ends exchanged their
+    /// [banners](crate::world::transport::exchange_banners).
+    pub fn use_modern_framing(&mut self) {
+        self.modern = Some(ModernFraming::default());
+    }
+
+    /// Starts encrypting the packets of a modern client.
+    ///
+    /// Servers must call this once they received the [`EnterEncryptedModeAck`] of the client,
+    /// and clients right after sending it.
+    ///
+    /// # Arguments
+    ///
+    /// - `role`: The end of the connection this is called by.
+    /// - `key`: The key returned by [`session_encryption_key`](crate::world::auth::session_encryption_key).
+    pub fn enter_encrypted_mode(&mut self, role: Role, key: &[u8; 16]) -> Result<()> {
+        let Some(framing) = &mut self.modern else {
+            bail!("Only modern clients encrypt packets");
+        };
+
+        framing.enter_encrypted_mode(role, key);
+        Ok(())
+    }
+
+    /// Whether the packets of this connection are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.modern.as_ref().is_some_and(ModernFraming::is_encrypted)
+    }
+}
+
+/// Identifies a world packet, along with the end of the connection that sent it.
+#[derive(Debug)]
+pub struct WorldIdentifier(pub Opcode, pub Role);
+
+/// Whether the given expansion sends packets too large for a 15-bit size from the server.
+fn has_large_packets(expansion: Expansion) -> bool {
+    expansion >= Expansion::WrathOfTheLichKing
+}
+
+impl<P: WorldProtocol> Identifier<P> for WorldIdentifier {
+    /// Reads the header of a packet.
+    ///
+    /// Servers send the size of the packet as a big-endian 16-bit value, followed by a 16-bit
+    /// opcode. Clients send a 32-bit opcode instead. From Wrath of the Lich King on, servers
+    /// set the highest bit of the size to announce a 24-bit size.
+    fn recv<S>(source: &mut S, protocol: &mut P) -> impl Future<Output = Result<Self>> + Send
+        where S: ReadExt
+    {
+        let sender = protocol.role().peer();
+        let expansion = protocol.build().expansion;
+
+        async move {
+            if let Some(framing) = &mut protocol.connection().modern {
+                let (opcode, body) = framing.read(source).await?;
+                protocol.connection().body = Some(body);
+                return Ok(WorldIdentifier(Opcode::from_code(opcode as u32, expansion), sender));
+            }
+
+            let mut size = u16::from_be_bytes(read_header(source, protocol).await?) as usize;
+            if sender == Role::Server && has_large_packets(expansion) && size & 0x8000 != 0 {
+                let [low] = read_header(source, protocol).await?;
+                size = ((size & 0x7FFF) << 8) | low as usize;
+            }
+
+            let (opcode, opcode_size) = match sender {
+                Role::Server => (u16::from_le_bytes(read_header(source, protocol).await?) as u32, 2),
+                Role::Client => (u32::from_le_bytes(read_header(source, protocol).await?), 4),
+            };
+
+            let Some(body_size) = size.checked_sub(opcode_size) else {
+                bail!("Packet {:#06X} is too short ({} bytes)", opcode, size);
+            };
+
+            protocol.connection().body_size = body_size;
+            Ok(WorldIdentifier(Opcode::from_code(opcode, expansion), sender))
+        }
+    }
+
+    /// The header is written along with the body of the packet by [`write_packet`], once the
+    /// size of the body is known.
+    fn send<D>(self, _: &mut D, _: &mut P) -> impl Future<Output = Result<()>> + Send
+        where D: WriteExt
+    {
+        async { Ok(()) }
+    }
+}
+
+/// Reads and decrypts the next bytes of a packet header.
+async fn read_header<S, P, const N: usize>(source: &mut S, protocol: &mut P) -> Result<[u8; N]>
+    where S: ReadExt, P: WorldProtocol
+{
+    let mut bytes = source.read_exact_slice().await?;
+    protocol.connection().decryptor.decrypt(&mut bytes);
+    Ok(bytes)
+}
+
+/// Reads the body of the packet whose header was just read.
+pub(crate) async fn read_body<S, P>(source: &mut S, protocol: &mut P) -> Result<Box<[u8]>>
+    where S: ReadExt, P: WorldProtocol
+{
+    if let Some(body) = protocol.connection().body.take() {
+        return Ok(body);
+    }
+
+    let size = protocol.connection().body_size;
+    source.read_slice(size).await
+}
+
+/// Writes a packet sent by the given end of the connection.
+pub(crate) async fn write_packet<D, P>(dest: &mut D, protocol: &mut P, identifier: WorldIdentifier, body: &[u8]) -> Result<()>
+    where D: WriteExt, P: WorldProtocol
+{
+    let WorldIdentifier(opcode, sender) = identifier;
+    let expansion = protocol.build().expansion;
+    let Some(code) = opcode.code(expansion) else {
+        bail!("Packet {:?} is not supported for {} clients", opcode, expansion);
+    };
+
+    if let Some(framing) = &mut protocol.connection().modern {
+        return framing.write(dest, code as u16, body).await;
+    }
+
+    let mut header = Vec::with_capacity(6);
+    match sender {
+        Role::Server => {
+            let size = body.len() + 2;
+            if size > 0x7FFF && has_large_packets(expansion) && size <= 0x7F_FFFF {
+                header.push(0x80 | (size >> 16) as u8);
+                header.extend_from_slice(&(size as u16).to_be_bytes());
+            } else if size <= 0x7FFF {
+                header.extend_from_slice(&(size as u16).to_be_bytes());
+            } else {
+                bail!("Packet {:?} is too large ({} bytes)", opcode, size);
+            }
+
+            header.extend_from_slice(&(code as u16).to_le_bytes());
+        },
+        Role::Client => {
+            let size = body.len() + 4;
+            if size > 0xFFFF {
+                bail!("Packet {:?} is too large ({} bytes)", opcode, size);
+            }
+
+            header.extend_from_slice(&(size as u16).to_be_bytes());
+            header.extend_from_slice(&code.to_le_bytes());
+        },
+    }
+
+    protocol.connection().encryptor.encrypt(&mut header);
+    dest.write_slice(&header).await?;
+    dest.write_slice(body).await
+}
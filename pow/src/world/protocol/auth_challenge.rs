@@ -0,0 +1,98 @@
+use anyhow::Result;
+
+use crate::grunt::builds::Expansion;
+use crate::grunt::protocol::Role;
+use crate::packets::{Payload, ReadExt, WriteExt};
+use crate::world::protocol::{Opcode, WorldIdentifier, WorldProtocol, read_body, write_packet};
+
+/// The first packet of a world connection, sent by the server.
+///
+/// # Layout
+///
+/// - Up to The Burning Crusade, only the seed is sent.
+/// - Wrath of the Lich King sends `1`, the seed, and 32 random bytes.
+/// - Cataclysm sends 32 random bytes, the seed, and `1`.
+/// - Modern clients are sent 32 random bytes, the challenge, and `1`, but no seed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AuthChallenge {
+    /// The seed the client mixes into the digest of its [`AuthSession`](crate::world::protocol::AuthSession).
+    /// Not sent to modern clients.
+    pub server_seed: u32,
+    /// Random data the client does not use. Not sent before Wrath of the Lich King.
+    pub seeds: [u8; 32],
+    /// The data modern clients mix into their digest and into the key that encrypts packets.
+    /// Only sent to modern clients.
+    pub challenge: [u8; 16],
+}
+
+impl<P: WorldProtocol> Payload<P> for AuthChallenge {
+    type Identifier = WorldIdentifier;
+
+    fn identifier(&self) -> WorldIdentifier {
+        WorldIdentifier(Opcode::AuthChallenge, Role::Server)
+    }
+
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let body = read_body(source, protocol).await?;
+        let mut body = &body[..];
+
+        match protocol.build().expansion {
+            Expansion::Vanilla | Expansion::BurningCrusade => Ok(Self {
+                server_seed: body.read_u32_le().await?,
+                seeds: [0; 32],
+                challenge: [0; 16],
+            }),
+            Expansion::WrathOfTheLichKing => {
+                let _ = body.read_u32_le::<u32>().await?;
+                let server_seed = body.read_u32_le().await?;
+                let seeds = body.read_exact_slice().await?;
+
+                Ok(Self { server_seed, seeds, challenge: [0; 16] })
+            },
+            Expansion::Cataclysm => {
+                let seeds = body.read_exact_slice().await?;
+                let server_seed = body.read_u32_le().await?;
+                let _ = body.read_u8::<u8>().await?;
+
+                Ok(Self { server_seed, seeds, challenge: [0; 16] })
+            },
+            Expansion::Modern => {
+                let seeds = body.read_exact_slice().await?;
+                let challenge = body.read_exact_slice().await?;
+                let _ = body.read_u8::<u8>().await?;
+
+                Ok(Self { server_seed: 0, seeds, challenge })
+            },
+        }
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        let mut body = Vec::new();
+        match protocol.build().expansion {
+            Expansion::Vanilla | Expansion::BurningCrusade => {
+                body.write_u32_le(self.server_seed).await?;
+            },
+            Expansion::WrathOfTheLichKing => {
+                body.write_u32_le(1u32).await?;
+                body.write_u32_le(self.server_seed).await?;
+                body.write_slice(&self.seeds).await?;
+            },
+            Expansion::Cataclysm => {
+                body.write_slice(&self.seeds).await?;
+                body.write_u32_le(self.server_seed).await?;
+                body.write_u8(1).await?;
+            },
+            Expansion::Modern => {
+                body.write_slice(&self.seeds).await?;
+                body.write_slice(&self.challenge).await?;
+                body.write_u8(1).await?;
+            },
+        }
+
+        write_packet(dest, protocol, Payload::<P>::identifier(&self), &body).await
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::Result;
+
+use crate::grunt::builds::Expansion;
+use crate::grunt::protocol::Role;
+use crate::packets::{Payload, ReadExt, WriteExt};
+use crate::world::protocol::{Opcode, WorldIdentifier, WorldProtocol, read_body, write_packet};
+
+/// The outcome of a world authentication attempt.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuthResult {
+    Ok,
+    Failed,
+    Reject,
+    BadServerProof,
+    Unavailable,
+    SystemError,
+    BillingError,
+    BillingExpired,
+    VersionMismatch,
+    UnknownAccount,
+    IncorrectPassword,
+    SessionExpired,
+    ServerShuttingDown,
+    AlreadyLoggingIn,
+    LoginServerNotFound,
+    WaitQueue,
+    Banned,
+    AlreadyOnline,
+    NoTime,
+    DbBusy,
+    Suspended,
+    ParentalControl,
+    /// A code `pow` does not know about.
+    Unknown(u8),
+}
+
+/// The code of each result. Codes did not change across the supported builds.
+const CODES: &[(AuthResult, u8)] = &[
+    (AuthResult::Ok, 0x0C),
+    (AuthResult::Failed, 0x0D),
+    (AuthResult::Reject, 0x0E),
+    (AuthResult::BadServerProof, 0x0F),
+    (AuthResult::Unavailable, 0x10),
+    (AuthResult::SystemError, 0x11),
+    (AuthResult::BillingError, 0x12),
+    (AuthResult::BillingExpired, 0x13),
+    (AuthResult::VersionMismatch, 0x14),
+    (AuthResult::UnknownAccount, 0x15),
+    (AuthResult::IncorrectPassword, 0x16),
+    (AuthResult::SessionExpired, 0x17),
+    (AuthResult::ServerShuttingDown, 0x18),
+    (AuthResult::AlreadyLoggingIn, 0x19),
+    (AuthResult::LoginServerNotFound, 0x1A),
+    (AuthResult::WaitQueue, 0x1B),
+    (AuthResult::Banned, 0x1C),
+    (AuthResult::AlreadyOnline, 0x1D),
+    (AuthResult::NoTime, 0x1E),
+    (AuthResult::DbBusy, 0x1F),
+    (AuthResult::Suspended, 0x20),
+    (AuthResult::ParentalControl, 0x21),
+];
+
+impl AuthResult {
+    pub fn from_code(code: u8) -> Self {
+        CODES.iter()
+            .find(|(_, value)| *value == code)
+            .map_or(Self::Unknown(code), |(result, _)| *result)
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Unknown(code) => code,
+            _ => CODES.iter()
+                .find(|(result, _)| *result == self)
+                .map_or(0x0D, |(_, code)| *code),
+        }
+    }
+}
+
+/// Billing details of the account, sent along with a successful [`AuthResponse`].
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct AccountInfo {
+    pub billing_time_remaining: u32,
+    pub billing_flags: u8,
+    pub billing_time_rested: u32,
+    /// The most recent expansion the account may play. Not sent before The Burning Crusade.
+    pub expansion: u8,
+}
+
+/// Sent by the server once it verified the [`AuthSession`](crate::world::protocol::AuthSession)
+/// of the client.
+///
+/// # Layout
+///
+/// Up to Wrath of the Lich King, the result comes first, followed by the account details if
+/// the client is authenticated or by its position in the queue if it has to wait. Cataclysm
+/// sends a bit field announcing which of these follow, and the result last.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AuthResponse {
+    pub result: AuthResult,
+    pub account: Option<AccountInfo>,
+    pub queue_position: Option<u32>,
+}
+
+impl AuthResponse {
+    /// A response that only carries a result, such as a failure.
+    pub fn new(result: AuthResult) -> Self {
+        Self { result, account: None, queue_position: None }
+    }
+}
+
+impl<P: WorldProtocol> Payload<P> for AuthResponse {
+    type Identifier = WorldIdentifier;
+
+    fn identifier(&self) -> WorldIdentifier {
+        WorldIdentifier(Opcode::AuthResponse, Role::Server)
+    }
+
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let body = read_body(source, protocol).await?;
+        let mut body = &body[..];
+
+        let expansion = protocol.build().expansion;
+        if expansion == Expansion::Cataclysm {
+            let bits = body.read_u8::<u8>().await?;
+            let queued = bits & 0x80 != 0;
+            let has_account = bits & if queued { 0x20 } else { 0x40 } != 0;
+
+            let account = if has_account {
+                let billing_time_remaining = body.read_u32_le().await?;
+                let expansion = body.read_u8().await?;
+                let _ = body.read_u32_le::<u32>().await?;
+                let _ = body.read_u8::<u8>().await?;
+                let billing_time_rested = body.read_u32_le().await?;
+                let billing_flags = body.read_u8().await?;
+
+                Some(AccountInfo { billing_time_remaining, billing_flags, billing_time_rested, expansion })
+            } else {
+                None
+            };
+
+            let queue_position = if queued {
+                Some(body.read_u32_le().await?)
+            } else {
+                None
+            };
+
+            let result = AuthResult::from_code(body.read_u8().await?);
+            return Ok(Self { result, account, queue_position });
+        }
+
+        let result = AuthResult::from_code(body.read_u8().await?);
+        let mut response = Self::new(result);
+        match result {
+            AuthResult::Ok => {
+                let billing_time_remaining = body.read_u32_le().await?;
+                let billing_flags = body.read_u8().await?;
+                let billing_time_rested = body.read_u32_le().await?;
+                let expansion = match expansion {
+                    Expansion::Vanilla => 0,
+                    _ => body.read_u8().await?,
+                };
+
+                response.account = Some(AccountInfo { billing_time_remaining, billing_flags, billing_time_rested, expansion });
+            },
+            AuthResult::WaitQueue => response.queue_position = Some(body.read_u32_le().await?),
+            _ => { },
+        }
+
+        Ok(response)
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        let expansion = protocol.build().expansion;
+
+        let mut body = Vec::new();
+        if expansion == Expansion::Cataclysm {
+            let queued = self.queue_position.is_some();
+            let mut bits = 0u8;
+            if queued {
+                bits |= 0x80;
+            }
+            if self.account.is_some() {
+                bits |= if queued { 0x20 } else { 0x40 };
+            }
+            body.write_u8(bits).await?;
+
+            if let Some(account) = self.account {
+                body.write_u32_le(account.billing_time_remaining).await?;
+                body.write_u8(account.expansion).await?;
+                body.write_u32_le(0u32).await?;
+                body.write_u8(account.expansion).await?;
+                body.write_u32_le(account.billing_time_rested).await?;
+                body.write_u8(account.billing_flags).await?;
+            }
+
+            if let Some(position) = self.queue_position {
+                body.write_u32_le(position).await?;
+            }
+
+            body.write_u8(self.result.code()).await?;
+        } else {
+            body.write_u8(self.result.code()).await?;
+            match self.result {
+                AuthResult::Ok => {
+                    let account = self.account.unwrap_or_default();
+                    body.write_u32_le(account.billing_time_remaining).await?;
+                    body.write_u8(account.billing_flags).await?;
+                    body.write_u32_le(account.billing_time_rested).await?;
+                    if expansion != Expansion::Vanilla {
+                        body.write_u8(account.expansion).await?;
+                    }
+                },
+                AuthResult::WaitQueue => {
+                    body.write_u32_le(self.queue_position.unwrap_or_default()).await?;
+                    if expansion == Expansion::WrathOfTheLichKing {
+                        body.write_u8(0).await?;
+                    }
+                },
+                _ => { },
+            }
+        }
+
+        write_packet(dest, protocol, Payload::<P>::identifier(&self), &body).await
+    }
+}
@@ -0,0 +1,316 @@
+use anyhow::{Result, bail};
+
+use crate::grunt::builds::Expansion;
+use crate::grunt::protocol::Role;
+use crate::packets::{Payload, ReadExt, WriteExt};
+use crate::world::protocol::{Opcode, WorldIdentifier, WorldProtocol, read_body, write_packet};
+
+/// Sent by the client in response to an [`AuthChallenge`](crate::world::protocol::AuthChallenge)
+/// to prove it knows the session key of the account.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AuthSession {
+    /// Not sent by modern clients, whose build is known from their connection.
+    pub build: u16,
+    /// The name of the account. Modern clients send their realm join ticket in its place.
+    pub account: String,
+    /// Not sent by modern clients.
+    pub client_seed: u32,
+    /// `SHA1(account | 0 | client_seed | server_seed | session_key)`. Modern clients send
+    /// another digest, along with the other fields of their session.
+    pub digest: [u8; 20],
+    /// The compressed list of addons the client has, forwarded as is. Modern clients send
+    /// them in a later packet.
+    pub addons: Box<[u8]>,
+    /// The fields whose layout depends on the build of the client.
+    pub extra: SessionExtra,
+}
+
+/// The fields of an [`AuthSession`] whose layout depends on the build of the client.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SessionExtra {
+    /// Sent up to The Burning Crusade.
+    Legacy { server_id: u32 },
+    /// Sent by Wrath of the Lich King.
+    Wrath {
+        server_id: u32,
+        login_server_type: u32,
+        region_id: u32,
+        battlegroup_id: u32,
+        realm_id: u32,
+        dos_response: u64,
+    },
+    /// Sent by Cataclysm. The meaning of most of these fields is unknown.
+    Cataclysm {
+        values: [u32; 4],
+        bytes: [u8; 2],
+        dos_response: u64,
+    },
+    /// Sent by modern clients.
+    Modern {
+        dos_response: u64,
+        region_id: u32,
+        battlegroup_id: u32,
+        realm_id: u32,
+        /// Random data the client mixes into its digest and into the key that encrypts packets.
+        local_challenge: [u8; 32],
+        /// `HMAC-SHA256(key, local_challenge | challenge | seed)`, truncated to 24 bytes.
+        digest: [u8; 24],
+        use_ipv6: bool,
+    },
+}
+
+impl SessionExtra {
+    /// The fields a client of the given expansion sends when none are known.
+    pub fn default_for(expansion: Expansion) -> Self {
+        match expansion {
+            Expansion::Vanilla | Expansion::BurningCrusade => Self::Legacy { server_id: 0 },
+            Expansion::WrathOfTheLichKing => Self::Wrath {
+                server_id: 0,
+                login_server_type: 0,
+                region_id: 0,
+                battlegroup_id: 0,
+                realm_id: 0,
+                dos_response: 0,
+            },
+            Expansion::Cataclysm => Self::Cataclysm { values: [0; 4], bytes: [0; 2], dos_response: 0 },
+            Expansion::Modern => Self::Modern {
+                dos_response: 0,
+                region_id: 0,
+                battlegroup_id: 0,
+                realm_id: 0,
+                local_challenge: [0; 32],
+                digest: [0; 24],
+                use_ipv6: false,
+            },
+        }
+    }
+}
+
+/// The order in which Cataclysm clients scatter the bytes of the digest in the packet.
+/// Each group is sent between two other fields.
+const CATACLYSM_DIGEST_ORDER: [&[usize]; 6] = [
+    &[10, 18, 12, 5],
+    &[15, 9, 19, 4, 7, 16, 3],
+    &[8],
+    &[17, 6, 0, 1, 11],
+    &[2],
+    &[14, 13],
+];
+
+async fn read_digest_bytes(body: &mut &[u8], digest: &mut [u8; 20], group: usize) -> Result<()> {
+    for &index in CATACLYSM_DIGEST_ORDER[group] {
+        digest[index] = body.read_u8().await?;
+    }
+
+    Ok(())
+}
+
+async fn write_digest_bytes(body: &mut Vec<u8>, digest: &[u8; 20], group: usize) -> Result<()> {
+    for &index in CATACLYSM_DIGEST_ORDER[group] {
+        body.write_u8(digest[index]).await?;
+    }
+
+    Ok(())
+}
+
+impl<P: WorldProtocol> Payload<P> for AuthSession {
+    type Identifier = WorldIdentifier;
+
+    fn identifier(&self) -> WorldIdentifier {
+        WorldIdentifier(Opcode::AuthSession, Role::Client)
+    }
+
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let body = read_body(source, protocol).await?;
+        let mut body = &body[..];
+
+        match protocol.build().expansion {
+            Expansion::Cataclysm => return recv_cataclysm(&mut body).await,
+            Expansion::Modern => return recv_modern(&mut body, protocol.build().version.build).await,
+            _ => { },
+        }
+
+        let build = body.read_u32_le::<u32>().await? as u16;
+        let server_id = body.read_u32_le().await?;
+        let account = body.read_cstring(None).await?;
+
+        let (client_seed, extra) = match protocol.build().expansion {
+            Expansion::WrathOfTheLichKing => {
+                let login_server_type = body.read_u32_le().await?;
+                let client_seed = body.read_u32_le().await?;
+                let extra = SessionExtra::Wrath {
+                    server_id,
+                    login_server_type,
+                    region_id: body.read_u32_le().await?,
+                    battlegroup_id: body.read_u32_le().await?,
+                    realm_id: body.read_u32_le().await?,
+                    dos_response: body.read_u64_le().await?,
+                };
+
+                (client_seed, extra)
+            },
+            _ => (body.read_u32_le().await?, SessionExtra::Legacy { server_id }),
+        };
+
+        let digest = body.read_exact_slice().await?;
+
+        Ok(Self { build, account, client_seed, digest, addons: body.into(), extra })
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        let expansion = protocol.build().expansion;
+        let extra = match (&self.extra, expansion) {
+            (SessionExtra::Legacy { .. }, Expansion::Vanilla | Expansion::BurningCrusade)
+                | (SessionExtra::Wrath { .. }, Expansion::WrathOfTheLichKing)
+                | (SessionExtra::Cataclysm { .. }, Expansion::Cataclysm)
+                | (SessionExtra::Modern { .. }, Expansion::Modern) => self.extra.clone(),
+            _ => SessionExtra::default_for(expansion),
+        };
+
+        let mut body = Vec::new();
+        match extra {
+            SessionExtra::Legacy { server_id } => {
+                body.write_u32_le(self.build as u32).await?;
+                body.write_u32_le(server_id).await?;
+                body.write_cstring(&self.account).await?;
+                body.write_u32_le(self.client_seed).await?;
+                body.write_slice(&self.digest).await?;
+                body.write_slice(&self.addons).await?;
+            },
+            SessionExtra::Wrath { server_id, login_server_type, region_id, battlegroup_id, realm_id, dos_response } => {
+                body.write_u32_le(self.build as u32).await?;
+                body.write_u32_le(server_id).await?;
+                body.write_cstring(&self.account).await?;
+                body.write_u32_le(login_server_type).await?;
+                body.write_u32_le(self.client_seed).await?;
+                body.write_u32_le(region_id).await?;
+                body.write_u32_le(battlegroup_id).await?;
+                body.write_u32_le(realm_id).await?;
+                body.write_u64_le(dos_response).await?;
+                body.write_slice(&self.digest).await?;
+                body.write_slice(&self.addons).await?;
+            },
+            SessionExtra::Cataclysm { values, bytes, dos_response } => {
+                send_cataclysm(&mut body, &self, values, bytes, dos_response).await?;
+            },
+            SessionExtra::Modern { dos_response, region_id, battlegroup_id, realm_id, local_challenge, digest, use_ipv6 } => {
+                body.write_u64_le(dos_response).await?;
+                body.write_u32_le(region_id).await?;
+                body.write_u32_le(battlegroup_id).await?;
+                body.write_u32_le(realm_id).await?;
+                body.write_slice(&local_challenge).await?;
+                body.write_slice(&digest).await?;
+                body.write_u8(if use_ipv6 { 0x80 } else { 0 }).await?;
+                body.write_u32_le(self.account.len() as u32).await?;
+                body.write_string(&self.account).await?;
+            },
+        }
+
+        write_packet(dest, protocol, Payload::<P>::identifier(&self), &body).await
+    }
+}
+
+/// Cataclysm clients scatter the digest across the packet and send the account name last,
+/// prefixed with its length on 12 bits.
+async fn recv_cataclysm(body: &mut &[u8]) -> Result<AuthSession> {
+    let mut digest = [0; 20];
+    let mut values = [0; 4];
+    let mut bytes = [0; 2];
+
+    values[0] = body.read_u32_le().await?;
+    values[1] = body.read_u32_le().await?;
+    bytes[0] = body.read_u8().await?;
+    read_digest_bytes(body, &mut digest, 0).await?;
+    let dos_response = body.read_u64_le().await?;
+    read_digest_bytes(body, &mut digest, 1).await?;
+    let build = body.read_u16_le().await?;
+    read_digest_bytes(body, &mut digest, 2).await?;
+    values[2] = body.read_u32_le().await?;
+    bytes[1] = body.read_u8().await?;
+    read_digest_bytes(body, &mut digest, 3).await?;
+    let client_seed = body.read_u32_le().await?;
+    read_digest_bytes(body, &mut digest, 4).await?;
+    values[3] = body.read_u32_le().await?;
+    read_digest_bytes(body, &mut digest, 5).await?;
+
+    // The size is checked against the body, which is already in memory, before anything is
+    // allocated for the addons.
+    let addons_size = body.read_u32_le::<u32>().await? as usize;
+    if addons_size > body.len() {
+        bail!("Addons are larger than the packet ({} bytes)", addons_size);
+    }
+    let addons = body.read_slice(addons_size).await?;
+
+    // One unknown bit, followed by the length of the account name.
+    let bits = body.read_u16_be::<u16>().await?;
+    let length = ((bits >> 3) & 0x0FFF) as usize;
+    let account = body.read_string(length).await?;
+
+    Ok(AuthSession {
+        build,
+        account,
+        client_seed,
+        digest,
+        addons,
+        extra: SessionExtra::Cataclysm { values, bytes, dos_response },
+    })
+}
+
+async fn send_cataclysm(body: &mut Vec<u8>, session: &AuthSession, values: [u32; 4], bytes: [u8; 2], dos_response: u64) -> Result<()> {
+    if session.account.len() > 0x0FFF {
+        bail!("Account name is too long ({} bytes)", session.account.len());
+    }
+
+    body.write_u32_le(values[0]).await?;
+    body.write_u32_le(values[1]).await?;
+    body.write_u8(bytes[0]).await?;
+    write_digest_bytes(body, &session.digest, 0).await?;
+    body.write_u64_le(dos_response).await?;
+    write_digest_bytes(body, &session.digest, 1).await?;
+    body.write_u16_le(session.build).await?;
+    write_digest_bytes(body, &session.digest, 2).await?;
+    body.write_u32_le(values[2]).await?;
+    body.write_u8(bytes[1]).await?;
+    write_digest_bytes(body, &session.digest, 3).await?;
+    body.write_u32_le(session.client_seed).await?;
+    write_digest_bytes(body, &session.digest, 4).await?;
+    body.write_u32_le(values[3]).await?;
+    write_digest_bytes(body, &session.digest, 5).await?;
+
+    body.write_u32_le(session.addons.len() as u32).await?;
+    body.write_slice(&session.addons).await?;
+
+    body.write_u16_be((session.account.len() << 3) as u16).await?;
+    body.write_string(&session.account).await
+}
+
+/// Modern clients send their realm join ticket last, prefixed with its length. The flag that
+/// precedes it is a single bit.
+async fn recv_modern(body: &mut &[u8], build: u16) -> Result<AuthSession> {
+    let dos_response = body.read_u64_le().await?;
+    let region_id = body.read_u32_le().await?;
+    let battlegroup_id = body.read_u32_le().await?;
+    let realm_id = body.read_u32_le().await?;
+    let local_challenge = body.read_exact_slice().await?;
+    let digest = body.read_exact_slice().await?;
+    let use_ipv6 = body.read_u8::<u8>().await? & 0x80 != 0;
+
+    let length = body.read_u32_le::<u32>().await? as usize;
+    if length > body.len() {
+        bail!("Realm join ticket is larger than the packet ({} bytes)", length);
+    }
+    let account = body.read_string(length).await?;
+
+    Ok(AuthSession {
+        build,
+        account,
+        client_seed: 0,
+        digest: [0; 20],
+        addons: Box::new([]),
+        extra: SessionExtra::Modern { dos_response, region_id, battlegroup_id, realm_id, local_challenge, digest, use_ipv6 },
+    })
+}
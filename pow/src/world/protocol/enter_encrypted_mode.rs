@@ -0,0 +1,74 @@
+use anyhow::Result;
+
+use crate::grunt::protocol::Role;
+use crate::packets::{Payload, ReadExt, WriteExt};
+use crate::world::protocol::{Opcode, WorldIdentifier, WorldProtocol, read_body, write_packet};
+
+/// Sent by the server to modern clients once it accepted their
+/// [`AuthSession`](crate::world::protocol::AuthSession), in the clear.
+///
+/// The client answers with an [`EnterEncryptedModeAck`], after which both ends encrypt
+/// every packet.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EnterEncryptedMode {
+    /// The Ed25519 signature of the key that encrypts packets, which clients check against
+    /// the key they ship with. `pow` neither signs nor checks it, so real modern clients
+    /// refuse the signatures it sends.
+    pub signature: [u8; 64],
+    pub enabled: bool,
+}
+
+impl<P: WorldProtocol> Payload<P> for EnterEncryptedMode {
+    type Identifier = WorldIdentifier;
+
+    fn identifier(&self) -> WorldIdentifier {
+        WorldIdentifier(Opcode::EnterEncryptedMode, Role::Server)
+    }
+
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        let body = read_body(source, protocol).await?;
+        let mut body = &body[..];
+
+        let signature = body.read_exact_slice().await?;
+        let enabled = body.read_u8::<u8>().await? & 0x80 != 0;
+
+        Ok(Self { signature, enabled })
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        let mut body = Vec::with_capacity(65);
+        body.write_slice(&self.signature).await?;
+        body.write_u8(if self.enabled { 0x80 } else { 0 }).await?;
+
+        write_packet(dest, protocol, Payload::<P>::identifier(&self), &body).await
+    }
+}
+
+/// Sent by modern clients in the clear, right before they start encrypting packets.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EnterEncryptedModeAck;
+
+impl<P: WorldProtocol> Payload<P> for EnterEncryptedModeAck {
+    type Identifier = WorldIdentifier;
+
+    fn identifier(&self) -> WorldIdentifier {
+        WorldIdentifier(Opcode::EnterEncryptedModeAck, Role::Client)
+    }
+
+    async fn recv<S>(source: &mut S, protocol: &mut P) -> Result<Self>
+        where S: ReadExt
+    {
+        read_body(source, protocol).await?;
+        Ok(Self)
+    }
+
+    async fn send<D>(self, dest: &mut D, protocol: &mut P) -> Result<()>
+        where D: WriteExt
+    {
+        write_packet(dest, protocol, Payload::<P>::identifier(&self), &[]).await
+    }
+}
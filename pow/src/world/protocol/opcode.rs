@@ -0,0 +1,63 @@
+use crate::grunt::builds::Expansion;
+
+/// The world packets `pow` understands.
+///
+/// Opcodes were renumbered in Cataclysm and again by modern clients; the same packet has a
+/// different code depending on the build of the client.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Opcode {
+    /// `SMSG_AUTH_CHALLENGE`
+    AuthChallenge,
+    /// `CMSG_AUTH_SESSION`
+    AuthSession,
+    /// `SMSG_AUTH_RESPONSE`
+    AuthResponse,
+    /// `SMSG_ENTER_ENCRYPTED_MODE`, only sent to modern clients.
+    EnterEncryptedMode,
+    /// `CMSG_ENTER_ENCRYPTED_MODE_ACK`, only sent by modern clients.
+    EnterEncryptedModeAck,
+    /// An opcode `pow` does not know about.
+    Unknown(u32),
+}
+
+/// An opcode, and its code up to Wrath of the Lich King, in Cataclysm, and for modern
+/// clients, if the packet exists for them.
+type Codes = (Opcode, Option<u32>, Option<u32>, Option<u32>);
+
+/// The codes of every known opcode.
+///
+/// The modern layout of `SMSG_AUTH_RESPONSE` is not supported, so it has no modern code.
+const CODES: &[Codes] = &[
+    (Opcode::AuthChallenge, Some(0x01EC), Some(0x4542), Some(0x3048)),
+    (Opcode::AuthSession, Some(0x01ED), Some(0x0449), Some(0x3765)),
+    (Opcode::AuthResponse, Some(0x01EE), Some(0x5DB6), None),
+    (Opcode::EnterEncryptedMode, None, None, Some(0x3049)),
+    (Opcode::EnterEncryptedModeAck, None, None, Some(0x3767)),
+];
+
+impl Opcode {
+    /// Decodes an opcode sent by a client of the given expansion.
+    pub fn from_code(code: u32, expansion: Expansion) -> Self {
+        CODES.iter()
+            .find(|(_, legacy, cataclysm, modern)| Self::select(*legacy, *cataclysm, *modern, expansion) == Some(code))
+            .map_or(Self::Unknown(code), |(opcode, ..)| *opcode)
+    }
+
+    /// Encodes this opcode for a client of the given expansion, if the packet exists for it.
+    pub fn code(self, expansion: Expansion) -> Option<u32> {
+        match self {
+            Self::Unknown(code) => Some(code),
+            _ => CODES.iter()
+                .find(|(opcode, ..)| *opcode == self)
+                .and_then(|(_, legacy, cataclysm, modern)| Self::select(*legacy, *cataclysm, *modern, expansion)),
+        }
+    }
+
+    fn select(legacy: Option<u32>, cataclysm: Option<u32>, modern: Option<u32>, expansion: Expansion) -> Option<u32> {
+        match expansion {
+            Expansion::Modern => modern,
+            Expansion::Cataclysm => cataclysm,
+            _ => legacy,
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use tokio::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::grunt::builds::ClientBuild;
+use crate::grunt::protocol::Role;
+use crate::network::connection::Client;
+use crate::packets::{Protocol, WriteExt};
+use crate::world::protocol::{AuthChallenge, AuthSession, WorldConnection, WorldIdentifier, WorldProtocol};
+
+/// A login forwarded by a relay, with which the client may enter the world.
+#[derive(Clone, Debug)]
+pub struct RelayedLogin {
+    /// The name of the account, in uppercase.
+    pub account: String,
+    pub build: &'static ClientBuild,
+}
+
+/// The logins forwarded by a relay, by address of the client, shared with its world proxies.
+///
+/// Only the latest login of each address is kept.
+#[derive(Clone, Default)]
+pub struct RelayedLogins {
+    logins: Arc<RwLock<HashMap<IpAddr, RelayedLogin>>>,
+}
+
+impl RelayedLogins {
+    pub fn insert(&self, address: IpAddr, account: &str, build: &'static ClientBuild) {
+        let login = RelayedLogin { account: account.to_uppercase(), build };
+        self.logins.write().unwrap().insert(address.to_canonical(), login);
+    }
+
+    pub fn get(&self, address: IpAddr) -> Option<RelayedLogin> {
+        self.logins.read().unwrap().get(&address.to_canonical()).cloned()
+    }
+}
+
+/// One end of a proxied world connection. It keeps the handshake packets it reads until they
+/// are forwarded to the other end.
+struct HandshakeProtocol {
+    build: &'static ClientBuild,
+    role: Role,
+    connection: WorldConnection,
+    challenge: Option<AuthChallenge>,
+    session: Option<AuthSession>,
+}
+
+impl HandshakeProtocol {
+    fn new(build: &'static ClientBuild, role: Role) -> Self {
+        Self { build, role, connection: WorldConnection::default(), challenge: None, session: None }
+    }
+}
+
+impl WorldProtocol for HandshakeProtocol {
+    fn build(&self) -> &'static ClientBuild { self.build }
+    fn role(&self) -> Role { self.role }
+    fn connection(&mut self) -> &mut WorldConnection { &mut self.connection }
+
+    async fn handle_auth_challenge<D>(&mut self, msg: AuthChallenge, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.challenge = Some(msg);
+        Ok(())
+    }
+
+    async fn handle_auth_session<D>(&mut self, msg: AuthSession, _: &mut D) -> Result<()>
+        where D: WriteExt
+    {
+        self.session = Some(msg);
+        Ok(())
+    }
+}
+
+/// Listens on the given address and forwards every world connection to `upstream`.
+///
+/// Only clients that logged in through the relay are let in, and only as the account they
+/// logged in with: the handshake is read from both ends before the rest of the connection is
+/// forwarded as is. Packets are not translated, so the world server must speak the build of
+/// the client.
+///
+/// Returns the address the listener is bound to, which differs from `bind` if it asks for
+/// an ephemeral port.
+///
+/// # Arguments
+///
+/// - `bind`: The address to listen on.
+/// - `upstream`: The address of the world server every connection is forwarded to.
+/// - `logins`: The logins the relay forwarded.
+/// - `token`: A token that closes the listener and every connection once signalled.
+pub async fn open_world_proxy(bind: &str, upstream: String, logins: RelayedLogins, token: CancellationToken) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(bind).await?;
+    let local_addr = listener.local_addr()?;
+    info!("Forwarding world connections from {} to {}", local_addr, upstream);
+
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                _ = token.cancelled() => break,
+                accepted = listener.accept() => accepted,
+            };
+
+            let (client, addr) = match accepted {
+                Ok(connection) => connection,
+                Err(err) => {
+                    // Failures such as running out of file descriptors are usually temporary.
+                    error!("Unable to accept a connection on {}: {}", local_addr, err);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                },
+            };
+
+            let Some(login) = logins.get(addr.ip()) else {
+                info!("Refused a world connection from {}, which did not log in through the relay", addr);
+                continue;
+            };
+
+            let upstream = upstream.clone();
+            let token = token.child_token();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = token.cancelled() => (),
+                    result = forward(client, &upstream, login, token.clone()) => if let Err(err) = result {
+                        error!("Unable to forward {} to {}: {:#}", addr, upstream, err);
+                    },
+                }
+            });
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// Forwards the handshake of a client to the world server, then the rest of the connection.
+async fn forward(client: TcpStream, upstream: &str, login: RelayedLogin, token: CancellationToken) -> Result<()> {
+    client.set_nodelay(true)?;
+    let (read, write) = client.into_split();
+    let mut reader = BufReader::new(read);
+    let mut sender = BufWriter::new(write);
+    let mut protocol = HandshakeProtocol::new(login.build, Role::Server);
+
+    let mut server = Client::<_, WorldIdentifier>::connect(upstream, HandshakeProtocol::new(login.build, Role::Client), token).await?;
+    let (opened, server_opened) = tokio::join!(
+        protocol.connection.open(&mut reader, &mut sender, login.build, Role::Server),
+        server.open(),
+    );
+    opened?;
+    server_opened?;
+
+    server.process_incoming().await?;
+    let challenge = server.protocol_mut().challenge.take()
+        .ok_or_else(|| anyhow!("The world server did not send a challenge"))?;
+    protocol.send(&mut sender, challenge).await?;
+
+    protocol.process_incoming(&mut reader, &mut sender).await?;
+    let session = protocol.session.take()
+        .ok_or_else(|| anyhow!("The client did not answer the challenge"))?;
+    if !session.account.eq_ignore_ascii_case(&login.account) {
+        bail!("The client logged in as {} but tried to enter the world as {}", login.account, session.account);
+    }
+    server.send(session).await?;
+
+    // From now on, packets are obfuscated with the session key, which only the client and the
+    // servers know. Both halves were flushed by the last packet they sent.
+    let Client { reader: mut server_reader, sender: server_sender, .. } = server;
+    tokio::try_join!(
+        pipe(&mut reader, server_sender.into_inner()),
+        pipe(&mut server_reader, sender.into_inner()),
+    )?;
+
+    Ok(())
+}
+
+/// Forwards everything read from `source` to `dest`, and closes `dest` once `source` is closed.
+async fn pipe<S, D>(source: &mut S, mut dest: D) -> Result<()>
+    where S: AsyncBufRead + Unpin, D: AsyncWrite + Unpin
+{
+    tokio::io::copy_buf(source, &mut dest).await?;
+    dest.shutdown().await?;
+    Ok(())
+}
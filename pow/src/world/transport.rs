@@ -0,0 +1,366 @@
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aes::Aes128;
+use aes_gcm::{AesGcm, KeyInit, Nonce, Tag};
+use anyhow::{Result, anyhow, bail};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::grunt::protocol::Role;
+use crate::grunt::session::SessionKey;
+use crate::packets::{ReadExt, WriteExt};
+
+type HmacSha256 = Hmac<Sha256>;
+type Aes128Gcm12 = AesGcm<Aes128, U12, U12>;
+
+/// The first bytes sent by a server to a modern client.
+pub const SERVER_BANNER: &str = "WORLD OF WARCRAFT CONNECTION - SERVER TO CLIENT - V2\n";
+
+/// The first bytes sent by a modern client to a server.
+pub const CLIENT_BANNER: &str = "WORLD OF WARCRAFT CONNECTION - CLIENT TO SERVER - V2\n";
+
+/// The banner a Cataclysm server sends before its challenge.
+pub const SERVER_CONNECTIVITY_BANNER: &str = "WORLD OF WARCRAFT CONNECTION - SERVER TO CLIENT";
+
+/// The banner a Cataclysm client answers the banner of the server with.
+pub const CLIENT_CONNECTIVITY_BANNER: &str = "WORLD OF WARCRAFT CONNECTION - CLIENT TO SERVER";
+
+/// Mixed into the key that encrypts packets, along with the challenges of both ends.
+const ENCRYPTION_KEY_SEED: [u8; 16] = [
+    0xE9, 0x75, 0x3C, 0x50, 0x90, 0x93, 0x61, 0xDA,
+    0x3B, 0x07, 0xEE, 0xFA, 0xFF, 0x9D, 0x41, 0xB8,
+];
+
+/// Completes the nonce of the packets sent by the server.
+const SERVER_TO_CLIENT: u32 = 0x52565253;
+
+/// Completes the nonce of the packets sent by the client.
+const CLIENT_TO_SERVER: u32 = 0x544E4C43;
+
+/// The size of the authentication tag sent in the header of each packet.
+const TAG_SIZE: usize = 12;
+
+/// The largest payload accepted from a peer. Sizes are read before anything is allocated for
+/// the payload, so they must be bounded.
+const MAX_PACKET_SIZE: usize = 0x40000;
+
+/// Sends the banner of the given end of the connection, and checks the banner of its peer.
+///
+/// Both ends send their banner without waiting for the other, so the order in which they
+/// call this does not matter.
+pub async fn exchange_banners<S, D>(source: &mut S, dest: &mut D, role: Role) -> Result<()>
+    where S: ReadExt, D: WriteExt
+{
+    let (banner, expected) = match role {
+        Role::Server => (SERVER_BANNER, CLIENT_BANNER),
+        Role::Client => (CLIENT_BANNER, SERVER_BANNER),
+    };
+
+    dest.write_string(banner).await?;
+    dest.flush().await?;
+
+    let received = source.read_slice(expected.len()).await?;
+    if &received[..] != expected.as_bytes() {
+        bail!("Unexpected connection banner {:?}", String::from_utf8_lossy(&received));
+    }
+
+    Ok(())
+}
+
+/// Exchanges the `MSG_VERIFY_CONNECTIVITY` banners of Cataclysm, before the server sends its
+/// [`AuthChallenge`](crate::world::protocol::AuthChallenge).
+///
+/// The server speaks first, and the client answers once it received the banner of the
+/// server. Each banner is sent as a packet with an unencrypted header, whose opcode is made of
+/// the first bytes of the banner itself: both ends simply send the size of the banner,
+/// followed by the banner and a null terminator.
+pub async fn verify_connectivity<S, D>(source: &mut S, dest: &mut D, role: Role) -> Result<()>
+    where S: ReadExt, D: WriteExt
+{
+    let (banner, expected) = match role {
+        Role::Server => (SERVER_CONNECTIVITY_BANNER, CLIENT_CONNECTIVITY_BANNER),
+        Role::Client => (CLIENT_CONNECTIVITY_BANNER, SERVER_CONNECTIVITY_BANNER),
+    };
+
+    if role == Role::Server {
+        write_connectivity_banner(dest, banner).await?;
+    }
+
+    let size = source.read_u16_be::<u16>().await? as usize;
+    if size != expected.len() + 1 {
+        bail!("Unexpected connectivity banner of {} bytes", size);
+    }
+
+    let received = source.read_slice(size).await?;
+    if &received[..expected.len()] != expected.as_bytes() || received[expected.len()] != 0 {
+        bail!("Unexpected connectivity banner {:?}", String::from_utf8_lossy(&received));
+    }
+
+    if role == Role::Client {
+        write_connectivity_banner(dest, banner).await?;
+    }
+
+    Ok(())
+}
+
+async fn write_connectivity_banner<D: WriteExt>(dest: &mut D, banner: &str) -> Result<()> {
+    dest.write_u16_be((banner.len() + 1) as u16).await?;
+    dest.write_cstring(banner).await?;
+    dest.flush().await
+}
+
+/// Derives the key that encrypts packets once both ends exchanged their challenges.
+///
+/// Retail ends derive it from a key generated from the Battle.net session of the account,
+/// which `pow` does not implement. Keys derived here only match those of another `pow`.
+///
+/// # Arguments
+///
+/// - `session_key`: The session key of the account.
+/// - `client_challenge`: Random data sent by the client in its session.
+/// - `server_challenge`: Random data sent by the server in its challenge.
+pub fn encryption_key(session_key: &SessionKey, client_challenge: &[u8; 32], server_challenge: &[u8; 16]) -> Result<[u8; 16]> {
+    let digest = <HmacSha256 as Mac>::new_from_slice(session_key)?
+        .chain_update(client_challenge)
+        .chain_update(server_challenge)
+        .chain_update(ENCRYPTION_KEY_SEED)
+        .finalize()
+        .into_bytes();
+
+    let mut key = [0; 16];
+    key.copy_from_slice(&digest[..16]);
+    Ok(key)
+}
+
+/// Encrypts the packets flowing in one direction of a connection with AES-128-GCM.
+///
+/// The nonce of each packet is a counter of the packets sent in that direction, followed
+/// by a constant that identifies the direction.
+pub struct PacketCipher {
+    cipher: Aes128Gcm12,
+    counter: u64,
+    direction: u32,
+}
+
+impl PacketCipher {
+    /// Creates the cipher of the packets sent by the given end of the connection.
+    pub fn new(key: &[u8; 16], sender: Role) -> Self {
+        Self {
+            cipher: Aes128Gcm12::new(key.into()),
+            counter: 0,
+            direction: match sender {
+                Role::Server => SERVER_TO_CLIENT,
+                Role::Client => CLIENT_TO_SERVER,
+            },
+        }
+    }
+
+    fn next_nonce(&mut self) -> Nonce<U12> {
+        let mut nonce = Nonce::<U12>::default();
+        nonce[..8].copy_from_slice(&self.counter.to_le_bytes());
+        nonce[8..].copy_from_slice(&self.direction.to_le_bytes());
+
+        self.counter += 1;
+        nonce
+    }
+
+    /// Encrypts the given packet in place, and returns its tag.
+    pub fn encrypt(&mut self, data: &mut [u8]) -> Result<[u8; TAG_SIZE]> {
+        let nonce = self.next_nonce();
+        let tag = self.cipher.encrypt_in_place_detached(&nonce, &[], data)
+            .map_err(|_| anyhow!("Packet could not be encrypted"))?;
+
+        Ok(tag.into())
+    }
+
+    /// Decrypts the given packet in place, failing if it does not match its tag.
+    pub fn decrypt(&mut self, data: &mut [u8], tag: &[u8; TAG_SIZE]) -> Result<()> {
+        let nonce = self.next_nonce();
+        self.cipher.decrypt_in_place_detached(&nonce, &[], data, Tag::<U12>::from_slice(tag))
+            .map_err(|_| anyhow!("Packet could not be authenticated"))
+    }
+}
+
+/// The framing of modern world packets.
+///
+/// Each packet starts with the size of its payload and the authentication tag of its payload.
+/// The payload holds a 16-bit opcode followed by the body of the packet. Packets are sent in
+/// the clear, with an empty tag, until both ends enter encrypted mode.
+#[derive(Default)]
+pub struct ModernFraming {
+    /// Encrypts the packets sent to the peer.
+    encryptor: Option<PacketCipher>,
+    /// Decrypts the packets read from the peer.
+    decryptor: Option<PacketCipher>,
+}
+
+impl ModernFraming {
+    /// Starts encrypting packets in both directions.
+    ///
+    /// # Arguments
+    ///
+    /// - `role`: The end of the connection this framing speaks for.
+    /// - `key`: The key returned by [`encryption_key`].
+    pub fn enter_encrypted_mode(&mut self, role: Role, key: &[u8; 16]) {
+        self.encryptor = Some(PacketCipher::new(key, role));
+        self.decryptor = Some(PacketCipher::new(key, role.peer()));
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encryptor.is_some()
+    }
+
+    /// Reads the next packet, returning its opcode and its body.
+    pub async fn read<S: ReadExt>(&mut self, source: &mut S) -> Result<(u16, Box<[u8]>)> {
+        let size = source.read_u32_le::<u32>().await? as usize;
+        let tag = source.read_exact_slice::<TAG_SIZE>().await?;
+        if size < 2 {
+            bail!("Packet is too short ({} bytes)", size);
+        }
+        if size > MAX_PACKET_SIZE {
+            bail!("Packet is too large ({} bytes)", size);
+        }
+
+        let mut payload = source.read_slice(size).await?;
+        if let Some(decryptor) = &mut self.decryptor {
+            decryptor.decrypt(&mut payload, &tag)?;
+        }
+
+        let opcode = u16::from_le_bytes([payload[0], payload[1]]);
+        Ok((opcode, payload[2..].into()))
+    }
+
+    /// Writes a packet with the given opcode and body.
+    pub async fn write<D: WriteExt>(&mut self, dest: &mut D, opcode: u16, body: &[u8]) -> Result<()> {
+        let mut payload = Vec::with_capacity(body.len() + 2);
+        payload.extend_from_slice(&opcode.to_le_bytes());
+        payload.extend_from_slice(body);
+
+        if payload.len() > MAX_PACKET_SIZE {
+            bail!("Packet {:#06X} is too large ({} bytes)", opcode, payload.len());
+        }
+
+        let tag = match &mut self.encryptor {
+            Some(encryptor) => encryptor.encrypt(&mut payload)?,
+            None => [0; TAG_SIZE],
+        };
+
+        dest.write_u32_le(payload.len() as u32).await?;
+        dest.write_slice(&tag).await?;
+        dest.write_slice(&payload).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{BufReader, duplex};
+
+    use crate::grunt::protocol::Role;
+    use crate::world::transport::{CLIENT_BANNER, ModernFraming, PacketCipher, SERVER_BANNER, encryption_key, exchange_banners};
+    use crate::world::transport::verify_connectivity;
+
+    #[tokio::test]
+    pub async fn test_banners() {
+        let (server, client) = duplex(1024);
+        let (server_read, mut server_write) = tokio::io::split(server);
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut server_read = BufReader::new(server_read);
+        let mut client_read = BufReader::new(client_read);
+
+        let (server, client) = tokio::join!(
+            exchange_banners(&mut server_read, &mut server_write, Role::Server),
+            exchange_banners(&mut client_read, &mut client_write, Role::Client),
+        );
+        server.expect("Server should accept the client banner");
+        client.expect("Client should accept the server banner");
+
+        let mut source = CLIENT_BANNER.as_bytes();
+        assert!(exchange_banners(&mut source, &mut Vec::new(), Role::Client).await.is_err());
+
+        let mut sent = Vec::new();
+        let mut source = CLIENT_BANNER.as_bytes();
+        exchange_banners(&mut source, &mut sent, Role::Server).await.expect("Server should accept the client banner");
+        assert_eq!(sent, SERVER_BANNER.as_bytes());
+    }
+
+    #[tokio::test]
+    pub async fn test_connectivity_banners() {
+        let mut sent = Vec::new();
+        let mut source = &b"\x00\x30WORLD OF WARCRAFT CONNECTION - CLIENT TO SERVER\x00"[..];
+        verify_connectivity(&mut source, &mut sent, Role::Server).await.expect("Server should accept the client banner");
+
+        // The size is followed by what the client reads as MSG_VERIFY_CONNECTIVITY (0x4F57).
+        assert_eq!(&sent[..4], b"\x00\x30\x57\x4F");
+        assert_eq!(&sent[4..], b"RLD OF WARCRAFT CONNECTION - SERVER TO CLIENT\x00");
+
+        let mut sent = Vec::new();
+        let mut source = &b"\x00\x30WORLD OF WARCRAFT CONNECTION - SERVER TO CLIENT\x00"[..];
+        verify_connectivity(&mut source, &mut sent, Role::Client).await.expect("Client should accept the server banner");
+        assert_eq!(sent, b"\x00\x30WORLD OF WARCRAFT CONNECTION - CLIENT TO SERVER\x00");
+
+        let mut source = &b"\x00\x30WORLD OF WARCRAFT CONNECTION - SERVER TO CLIENT\x00"[..];
+        assert!(verify_connectivity(&mut source, &mut Vec::new(), Role::Server).await.is_err());
+    }
+
+    #[test]
+    pub fn test_packet_cipher() {
+        let key = encryption_key(&[0x42; 40], &[0x01; 32], &[0x02; 16]).unwrap();
+        assert_ne!(key, encryption_key(&[0x42; 40], &[0x02; 32], &[0x01; 16]).unwrap());
+
+        let mut encryptor = PacketCipher::new(&key, Role::Server);
+        let mut decryptor = PacketCipher::new(&key, Role::Server);
+
+        let mut first = *b"\x48\x30first";
+        let mut second = *b"\x48\x30first";
+        let first_tag = encryptor.encrypt(&mut first).unwrap();
+        let second_tag = encryptor.encrypt(&mut second).unwrap();
+        assert_ne!(first, second, "Nonces should not be reused");
+
+        decryptor.decrypt(&mut first, &first_tag).unwrap();
+        assert_eq!(&first, b"\x48\x30first");
+
+        // Packets from the other direction use different nonces.
+        let mut wrong_direction = PacketCipher::new(&key, Role::Client);
+        let mut copy = second;
+        wrong_direction.decrypt(&mut copy, &second_tag).unwrap_err();
+
+        decryptor.decrypt(&mut second, &second_tag).unwrap();
+        assert_eq!(&second, b"\x48\x30first");
+    }
+
+    #[tokio::test]
+    pub async fn test_framing() {
+        let key = encryption_key(&[0x42; 40], &[0x01; 32], &[0x02; 16]).unwrap();
+        let mut server = ModernFraming::default();
+        let mut client = ModernFraming::default();
+
+        let mut buffer = Vec::new();
+        server.write(&mut buffer, 0x3048, &[1, 2, 3]).await.unwrap();
+        assert_eq!(buffer, [5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x48, 0x30, 1, 2, 3]);
+        assert_eq!(client.read(&mut &buffer[..]).await.unwrap(), (0x3048, vec![1, 2, 3].into()));
+
+        server.enter_encrypted_mode(Role::Server, &key);
+        client.enter_encrypted_mode(Role::Client, &key);
+
+        let mut buffer = Vec::new();
+        for opcode in 0..4 {
+            server.write(&mut buffer, opcode, &[opcode as u8; 7]).await.unwrap();
+        }
+        assert_ne!(&buffer[16..18], [0, 0], "Payload should be encrypted");
+
+        let mut source = &buffer[..];
+        for opcode in 0..4 {
+            assert_eq!(client.read(&mut source).await.unwrap(), (opcode, vec![opcode as u8; 7].into()));
+        }
+
+        let mut buffer = Vec::new();
+        client.write(&mut buffer, 0x3765, &[4, 5]).await.unwrap();
+        buffer[17] ^= 1;
+        assert!(server.read(&mut &buffer[..]).await.is_err(), "Tampered packets should be rejected");
+
+        // The size is checked before the payload is read.
+        let mut source = &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0][..];
+        let err = server.read(&mut source).await.expect_err("Oversized packets should be rejected");
+        assert!(err.to_string().starts_with("Packet is too large"));
+    }
+}